@@ -0,0 +1,164 @@
+//! Benchmarks for the frame hot path: encoding, decoding and channel throughput.
+
+use bytes::BytesMut;
+use criterion::{criterion_group, criterion_main, BatchSize, Criterion, Throughput};
+use futures::{SinkExt, StreamExt};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio_oga::commands::{self, AsFrame};
+use tokio_oga::events::Event;
+use tokio_oga::protocol::{self, FrameDecoder};
+use tokio_oga::raw::OgaCodec;
+use tokio_util::codec::{Decoder, Framed};
+
+/// Number of frames per throughput iteration.
+const BATCH: usize = 1000;
+
+/// Sample events, as sent by the host.
+static EVENTS: &[&str] = &[
+    r#"{"__name__":"refresh","apiVersion":3}"#,
+    r#"{"__name__":"api-version","apiVersion":3}"#,
+    r#"{"__name__":"lock-screen"}"#,
+    r#"{"__name__":"set-number-of-cpus","count":4}"#,
+];
+
+/// Return a large `applications` report.
+fn applications() -> commands::Applications {
+    let applications = (0..500)
+        .map(|i| format!("package-{}-1.2.{}-1.el9.x86_64", i, i % 10))
+        .collect();
+    commands::Applications { applications }
+}
+
+/// Return a `network-interfaces` report.
+fn network_interfaces() -> commands::NetworkInterfaces {
+    let interfaces = (0..8)
+        .map(|i| commands::NetworkInterface {
+            name: format!("eth{}", i),
+            hw: format!("52:54:00:12:34:{:02x}", i),
+            inet: vec![format!("192.0.2.{}", i)],
+            inet6: vec![format!("2001:db8::{:x}", i)],
+        })
+        .collect();
+    commands::NetworkInterfaces { interfaces }
+}
+
+/// Return a batch of newline-terminated event frames.
+fn event_frames(count: usize) -> Vec<u8> {
+    let mut data = Vec::new();
+    for ev in EVENTS.iter().cycle().take(count) {
+        data.extend_from_slice(ev.as_bytes());
+        data.push(b'\n');
+    }
+    data
+}
+
+fn encoding(c: &mut Criterion) {
+    let mut group = c.benchmark_group("encode");
+    let heartbeat = commands::Heartbeat::default();
+    group.bench_function("heartbeat", |b| {
+        b.iter(|| protocol::encode_frame(&heartbeat).unwrap())
+    });
+    let startup = commands::SessionStartup::default();
+    group.bench_function("session-startup", |b| {
+        b.iter(|| protocol::encode_frame(&startup).unwrap())
+    });
+    let interfaces = network_interfaces();
+    group.bench_function("network-interfaces", |b| {
+        b.iter(|| protocol::encode_frame(&interfaces).unwrap())
+    });
+    let apps = applications();
+    group.throughput(Throughput::Bytes(apps.as_frame().unwrap().len() as u64));
+    group.bench_function("applications", |b| {
+        b.iter(|| protocol::encode_frame(&apps).unwrap())
+    });
+    let mut buf = BytesMut::new();
+    group.bench_function("applications-in-place", |b| {
+        b.iter(|| {
+            buf.clear();
+            protocol::write_frame(&apps, &mut buf).unwrap()
+        })
+    });
+    group.finish();
+}
+
+fn decoding(c: &mut Criterion) {
+    let data = event_frames(BATCH);
+    let mut group = c.benchmark_group("decode");
+    group.throughput(Throughput::Elements(BATCH as u64));
+    group.bench_function("frame-decoder", |b| {
+        b.iter(|| {
+            let mut decoder = FrameDecoder::new();
+            decoder.push(&data);
+            let mut count = 0;
+            while let Some(lazy) = decoder.next_event().unwrap() {
+                Event::parse_frame(lazy.raw().as_bytes()).unwrap();
+                count += 1;
+            }
+            assert_eq!(count, BATCH);
+        })
+    });
+    group.bench_function("codec", |b| {
+        b.iter_batched(
+            || BytesMut::from(&data[..]),
+            |mut src| {
+                let mut codec = OgaCodec::new();
+                let mut count = 0;
+                while codec.decode(&mut src).unwrap().is_some() {
+                    count += 1;
+                }
+                assert_eq!(count, BATCH);
+            },
+            BatchSize::SmallInput,
+        )
+    });
+    group.finish();
+}
+
+fn throughput(c: &mut Criterion) {
+    let rt = tokio::runtime::Runtime::new().unwrap();
+    let mut group = c.benchmark_group("channel");
+    group.throughput(Throughput::Elements(BATCH as u64));
+
+    // Guest commands, from the framed channel to the host end.
+    group.bench_function("commands", |b| {
+        b.to_async(&rt).iter(|| async {
+            let (guest, mut host) = tokio::io::duplex(64 * 1024);
+            let reader = tokio::spawn(async move {
+                let mut buf = vec![0u8; 64 * 1024];
+                let mut lines = 0;
+                while lines < BATCH {
+                    let n = host.read(&mut buf).await.unwrap();
+                    lines += buf[..n].iter().filter(|b| **b == b'\n').count();
+                }
+            });
+            let mut chan = Framed::new(guest, OgaCodec::new());
+            for _ in 0..BATCH {
+                let cmd: Box<dyn AsFrame> = Box::new(commands::Heartbeat::default());
+                chan.feed(cmd).await.unwrap();
+            }
+            chan.flush().await.unwrap();
+            reader.await.unwrap();
+        })
+    });
+
+    // Host events, from the host end to the framed channel.
+    let data = event_frames(BATCH);
+    group.bench_function("events", |b| {
+        b.to_async(&rt).iter(|| async {
+            let (guest, mut host) = tokio::io::duplex(64 * 1024);
+            let data = data.clone();
+            let writer = tokio::spawn(async move {
+                host.write_all(&data).await.unwrap();
+                host
+            });
+            let chan = Framed::new(guest, OgaCodec::new());
+            let count = chan.take(BATCH).count().await;
+            assert_eq!(count, BATCH);
+            drop(writer.await.unwrap());
+        })
+    });
+    group.finish();
+}
+
+criterion_group!(benches, encoding, decoding, throughput);
+criterion_main!(benches);
@@ -1,9 +1,9 @@
 //! Simple printing app example with graceful termination.
 
 use futures::FutureExt;
-use tokio::sync::{broadcast, oneshot};
 use tokio::{runtime, time};
 use tokio_oga::events::Event;
+use tokio_oga::subscription::EventReceiver;
 
 type ExError = Box<dyn std::error::Error + 'static>;
 
@@ -15,7 +15,7 @@ fn main() -> Result<(), ExError> {
         .filter(Some("tokio_oga"), log::LevelFilter::Trace)
         .init();
 
-    let mut rt = runtime::Runtime::new().expect("tokio runtime failure");
+    let rt = runtime::Runtime::new().expect("tokio runtime failure");
     rt.block_on(run())
 }
 
@@ -26,7 +26,7 @@ async fn run() -> Result<(), ExError> {
         delay_secs: SHUTDOWN_DELAY_SECS,
     };
 
-    let mut client = builder.connect().await?;
+    let client = builder.connect().await?;
 
     let term_chan = client.termination_chan();
     let events_chan = client.event_chan();
@@ -49,33 +49,22 @@ struct AppExample {
 
 impl AppExample {
     /// Process client termination errors.
-    async fn watch_termination(&self, chan: oneshot::Receiver<tokio_oga::OgaError>) -> ExError {
-        let err = chan
-            .await
-            .unwrap_or_else(|_| "termination event, sender aborted".into());
+    async fn watch_termination(&self, mut chan: tokio_oga::TerminationReceiver) -> ExError {
+        let err = chan.recv().await;
         Box::new(err)
     }
 
     /// Process oVirt events.
-    async fn run_core_logic(
-        &self,
-        mut ch_incoming: broadcast::Receiver<Event>,
-    ) -> Result<(), ExError> {
-        use tokio::sync::broadcast::RecvError;
-
+    async fn run_core_logic(&self, mut ch_incoming: EventReceiver<Event>) -> Result<(), ExError> {
         loop {
-            let event = match ch_incoming.recv().await {
-                Err(RecvError::Closed) => {
-                    break async { Err("end of events stream".into()) }.boxed()
-                }
-                Err(RecvError::Lagged(_)) => continue,
-                Ok(ev) => ev,
+            let event = match ch_incoming.recv_event().await {
+                None => break async { Err("end of events stream".into()) }.boxed(),
+                Some(ev) => ev,
             };
             println!("got event from host: {:?}", event);
 
-            match event {
-                Event::Shutdown(_) => break async { Ok(()) }.boxed(),
-                _ => {}
+            if let Event::Shutdown(_) = event {
+                break async { Ok(()) }.boxed();
             }
         }
         .await
@@ -83,6 +72,6 @@ impl AppExample {
 
     /// Gracefully shutdown after configured delay.
     async fn shutdown_delayed(&self) -> () {
-        time::delay_for(time::Duration::from_secs(self.delay_secs.into())).await
+        time::sleep(time::Duration::from_secs(self.delay_secs.into())).await
     }
 }
@@ -1,6 +1,5 @@
 //! Example application which notifies on startup.
 
-use tokio::sync::oneshot;
 use tokio::{runtime, time};
 use tokio_oga::commands::SessionStartup;
 
@@ -14,7 +13,7 @@ fn main() -> Result<(), ExError> {
         .filter(Some("tokio_oga"), log::LevelFilter::Trace)
         .init();
 
-    let mut rt = runtime::Runtime::new().expect("tokio runtime failure");
+    let rt = runtime::Runtime::new().expect("tokio runtime failure");
     rt.block_on(run())
 }
 
@@ -23,8 +22,8 @@ async fn run() -> Result<(), ExError> {
     // Build and initialize the client.
     let builder = tokio_oga::OgaBuilder::default()
         .initial_heartbeat(Some(true))
-        .heartbeat_interval(Some(0));
-    let mut client = builder.connect().await?;
+        .pacemaker(Some(false));
+    let client = builder.connect().await?;
 
     let term_chan = client.termination_chan();
     let cmd_chan = client.command_chan();
@@ -50,18 +49,13 @@ struct AppExample {
 
 impl AppExample {
     /// Process client termination errors.
-    async fn watch_termination(&self, chan: oneshot::Receiver<tokio_oga::OgaError>) -> ExError {
-        let err = chan
-            .await
-            .unwrap_or_else(|_| "termination event, sender aborted".into());
+    async fn watch_termination(&self, mut chan: tokio_oga::TerminationReceiver) -> ExError {
+        let err = chan.recv().await;
         Box::new(err)
     }
 
     /// Send a `session-startup` command.
-    async fn send_startup(
-        &self,
-        mut ch_outgoing: tokio_oga::OgaCommandSender,
-    ) -> Result<(), ExError> {
+    async fn send_startup(&self, ch_outgoing: tokio_oga::OgaCommandSender) -> Result<(), ExError> {
         let startup_msg = SessionStartup::default();
         ch_outgoing.send(Box::new(startup_msg)).await?;
         println!("Sent 'session-startup' command to host");
@@ -70,7 +64,7 @@ impl AppExample {
 
     /// Abort after configured delay.
     async fn abort_delayed(&self) -> () {
-        time::delay_for(time::Duration::from_secs(self.delay_secs.into())).await
+        time::sleep(time::Duration::from_secs(self.delay_secs.into())).await
     }
 }
 
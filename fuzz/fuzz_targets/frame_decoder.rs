@@ -0,0 +1,114 @@
+//! Framing of byte streams, split at arbitrary boundaries.
+
+#![no_main]
+
+use arbitrary::Arbitrary;
+use bytes::BytesMut;
+use libfuzzer_sys::fuzz_target;
+use tokio_oga::events::{Event, LazyEvent};
+use tokio_oga::protocol::{FrameDecoder, Utf8Policy};
+use tokio_oga::raw::OgaCodec;
+use tokio_util::codec::Decoder;
+
+#[derive(Arbitrary, Debug)]
+struct Input {
+    data: Vec<u8>,
+    /// Sizes of the chunks the stream is read in.
+    chunks: Vec<u8>,
+    lossy: bool,
+    max_failures: u8,
+}
+
+impl Input {
+    fn decoder(&self) -> FrameDecoder {
+        FrameDecoder::new()
+            .utf8_policy(self.policy())
+            .max_decode_failures(self.max_failures.into())
+    }
+
+    fn policy(&self) -> Utf8Policy {
+        match self.lossy {
+            true => Utf8Policy::Lossy,
+            false => Utf8Policy::Terminate,
+        }
+    }
+
+    /// Split the stream into chunks, the last one holding the remainder.
+    fn split(&self) -> Vec<&[u8]> {
+        let mut rest = &self.data[..];
+        let mut chunks = Vec::new();
+        for size in &self.chunks {
+            let (chunk, tail) = rest.split_at((*size as usize).min(rest.len()));
+            chunks.push(chunk);
+            rest = tail;
+        }
+        chunks.push(rest);
+        chunks
+    }
+}
+
+/// Decode a stream read in chunks, returning raw frames up to the first error.
+fn decode(decoder: &mut FrameDecoder, chunks: &[&[u8]]) -> (Vec<String>, bool) {
+    let mut frames = Vec::new();
+    for chunk in chunks {
+        decoder.push(chunk);
+        loop {
+            match decoder.next_event() {
+                Ok(Some(lazy)) => frames.push(lazy.raw().to_string()),
+                Ok(None) => break,
+                Err(_) => return (frames, true),
+            }
+        }
+    }
+    (frames, false)
+}
+
+/// Decode a stream read in chunks with the tokio codec, returning event names up to the first error.
+fn decode_codec(codec: &mut OgaCodec, chunks: &[&[u8]]) -> (Vec<String>, bool) {
+    let mut buf = BytesMut::new();
+    let mut names = Vec::new();
+    for chunk in chunks {
+        buf.extend_from_slice(chunk);
+        loop {
+            match codec.decode(&mut buf) {
+                Ok(Some(event)) => names.push(event.name().to_string()),
+                Ok(None) => break,
+                Err(_) => return (names, true),
+            }
+        }
+    }
+    (names, false)
+}
+
+fuzz_target!(|input: Input| {
+    let chunks = input.split();
+
+    // Chunk boundaries never change decoding results.
+    let mut decoder = input.decoder();
+    let (frames, failed) = decode(&mut decoder, &chunks);
+    let mut whole = input.decoder();
+    assert_eq!(decode(&mut whole, &[&input.data]), (frames.clone(), failed));
+    assert_eq!(decoder.discarded(), whole.discarded());
+
+    // Decoded frames are single, well-formed events.
+    for frame in &frames {
+        assert!(!frame.contains('\n'));
+        assert!(LazyEvent::parse_frame(frame).is_ok(), "bad frame: {:?}", frame);
+    }
+    if !failed {
+        let tail = input.data.iter().rev().take_while(|b| **b != b'\n').count();
+        assert_eq!(decoder.buffered(), tail);
+    }
+
+    // The tokio codec agrees on fully-parsed events.
+    let mut codec = OgaCodec::new()
+        .utf8_policy(input.policy())
+        .max_decode_failures(input.max_failures.into());
+    let (names, _) = decode_codec(&mut codec, &chunks);
+    let known: Vec<String> = frames
+        .iter()
+        .filter_map(|frame| Event::parse_frame(frame.as_bytes()).ok())
+        .map(|event| event.name().to_string())
+        .collect();
+    assert_eq!(names, known);
+});
@@ -0,0 +1,33 @@
+//! Parsing of single frames into events.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use tokio_oga::events::{Event, LazyEvent};
+
+fuzz_target!(|data: &[u8]| {
+    let event = Event::parse_frame(data);
+
+    // Lazy parsing agrees with eager parsing.
+    if let Ok(line) = std::str::from_utf8(data) {
+        if let Ok(lazy) = LazyEvent::parse_frame(line) {
+            let name = lazy.name().to_string();
+            let parsed = lazy.into_event();
+            assert_eq!(parsed.is_ok(), event.is_ok());
+            if let Ok(parsed) = parsed {
+                assert_eq!(parsed.name(), name);
+            }
+        }
+    }
+
+    // Parsed events survive a round-trip, except for login passwords.
+    if let Ok(mut event) = event {
+        if let Event::Login(login) = &mut event {
+            login.password = None;
+        }
+        let frame = event.to_frame().unwrap();
+        assert_eq!(frame.last(), Some(&b'\n'));
+        let reparsed = Event::parse_frame(&frame).unwrap();
+        assert_eq!(reparsed, event);
+    }
+});
@@ -0,0 +1,225 @@
+use crate::errors::OgaError;
+use crate::{commands, events, OgaCommandSender};
+use std::path::{Path, PathBuf};
+
+/// Base sysfs directory for CPU devices.
+static SYSFS_CPU_DIR: &str = "/sys/devices/system/cpu";
+
+/// Summary of a CPU hotplug operation.
+#[derive(Clone, Debug, Default)]
+#[non_exhaustive]
+pub struct CpuHotplugSummary {
+    /// Number of CPUs requested by the host.
+    pub requested: u32,
+    /// Number of CPUs online after the operation.
+    pub online: u32,
+    /// Indexes of CPUs which have been brought online.
+    pub onlined: Vec<u32>,
+    /// Indexes of CPUs which have been taken offline.
+    pub offlined: Vec<u32>,
+}
+
+/// Online or offline CPUs, as requested by a `set-number-of-cpus` event.
+///
+/// CPUs are brought online in index order up to the requested count, and
+/// all remaining ones are taken offline. CPUs which cannot be hotplugged
+/// (i.e. without an `online` sysfs attribute) are always considered online.
+pub async fn set_number_of_cpus(
+    event: &events::SetNumberOfCpus,
+) -> Result<CpuHotplugSummary, OgaError> {
+    let requested = event.count;
+    if requested == 0 {
+        return Err("invalid number of CPUs requested: 0".into());
+    }
+
+    tokio::task::spawn_blocking(move || hotplug_cpus(Path::new(SYSFS_CPU_DIR), requested))
+        .await
+        .map_err(|e| format!("CPU hotplug task failed: {}", e))?
+}
+
+/// Report the number of online CPUs to the host.
+///
+/// This is meant to be called after handling a `set-number-of-cpus` event,
+/// so that the host reflects the actual outcome. It returns the reported count.
+pub async fn report_number_of_cpus(sender: &OgaCommandSender) -> Result<u32, OgaError> {
+    let count = number_of_cpus().await?;
+    sender
+        .send(Box::new(commands::NumberOfCpus { count }))
+        .await?;
+    Ok(count)
+}
+
+/// Return the number of online CPUs.
+///
+/// CPUs which cannot be hotplugged are always counted as online.
+pub async fn number_of_cpus() -> Result<u32, OgaError> {
+    tokio::task::spawn_blocking(|| count_online(Path::new(SYSFS_CPU_DIR)))
+        .await
+        .map_err(|e| format!("CPU count task failed: {}", e))?
+}
+
+/// Count online CPUs under the given sysfs directory.
+fn count_online(base: &Path) -> Result<u32, OgaError> {
+    let mut online = 0;
+    for (_, dir) in list_cpus(base)? {
+        let attr = dir.join("online");
+        if !attr.exists() || read_online(&attr)? {
+            online += 1;
+        }
+    }
+    Ok(online)
+}
+
+/// Adjust online CPUs under the given sysfs directory.
+fn hotplug_cpus(base: &Path, requested: u32) -> Result<CpuHotplugSummary, OgaError> {
+    let mut summary = CpuHotplugSummary {
+        requested,
+        ..Default::default()
+    };
+
+    for (index, dir) in list_cpus(base)? {
+        let wanted = summary.online < requested;
+        let attr = dir.join("online");
+        if !attr.exists() {
+            summary.online += 1;
+            continue;
+        }
+
+        let current = read_online(&attr)?;
+        if current != wanted {
+            let value = if wanted { "1" } else { "0" };
+            std::fs::write(&attr, value)
+                .map_err(|e| format!("failed to write '{}': {}", attr.display(), e))?;
+            if wanted {
+                summary.onlined.push(index);
+            } else {
+                summary.offlined.push(index);
+            }
+        }
+        if wanted {
+            summary.online += 1;
+        }
+    }
+
+    log::debug!(
+        "CPU hotplug done, {} online of {} requested",
+        summary.online,
+        summary.requested
+    );
+    Ok(summary)
+}
+
+/// List CPU directories, sorted by index.
+fn list_cpus(base: &Path) -> Result<Vec<(u32, PathBuf)>, OgaError> {
+    let entries = std::fs::read_dir(base)
+        .map_err(|e| format!("failed to read '{}': {}", base.display(), e))?;
+
+    let mut cpus = Vec::new();
+    for entry in entries {
+        let entry = entry.map_err(|e| format!("failed to read '{}': {}", base.display(), e))?;
+        let name = entry.file_name();
+        let index = name
+            .to_str()
+            .and_then(|n| n.strip_prefix("cpu"))
+            .and_then(|n| n.parse::<u32>().ok());
+        if let Some(index) = index {
+            cpus.push((index, entry.path()));
+        }
+    }
+
+    cpus.sort_unstable_by_key(|(index, _)| *index);
+    Ok(cpus)
+}
+
+/// Read the state of a CPU `online` attribute.
+fn read_online(attr: &Path) -> Result<bool, OgaError> {
+    let content = std::fs::read_to_string(attr)
+        .map_err(|e| format!("failed to read '{}': {}", attr.display(), e))?;
+    Ok(content.trim() == "1")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    /// Create a fake sysfs CPU tree, with `None` for CPUs without an `online` file.
+    fn fake_sysfs(cpus: &[Option<bool>]) -> tempfile::TempDir {
+        let base = tempfile::tempdir().unwrap();
+        for (index, online) in cpus.iter().enumerate() {
+            let dir = base.path().join(format!("cpu{}", index));
+            fs::create_dir(&dir).unwrap();
+            if let Some(online) = online {
+                fs::write(dir.join("online"), if *online { "1\n" } else { "0\n" }).unwrap();
+            }
+        }
+        // Unrelated entries must be ignored.
+        fs::create_dir(base.path().join("cpufreq")).unwrap();
+        fs::write(base.path().join("online"), "0-3\n").unwrap();
+        base
+    }
+
+    fn states(base: &Path) -> Vec<Option<bool>> {
+        list_cpus(base)
+            .unwrap()
+            .into_iter()
+            .map(|(_, dir)| {
+                let attr = dir.join("online");
+                attr.exists().then(|| read_online(&attr).unwrap())
+            })
+            .collect()
+    }
+
+    #[test]
+    fn online_cpus() {
+        let base = fake_sysfs(&[None, Some(false), Some(true), Some(false)]);
+        let summary = hotplug_cpus(base.path(), 4).unwrap();
+        assert_eq!(summary.online, 4);
+        assert_eq!(summary.onlined, [1, 3]);
+        assert!(summary.offlined.is_empty());
+        assert_eq!(
+            states(base.path()),
+            [None, Some(true), Some(true), Some(true)]
+        );
+        assert_eq!(count_online(base.path()).unwrap(), 4);
+    }
+
+    #[test]
+    fn offline_cpus() {
+        let base = fake_sysfs(&[None, Some(true), Some(true), Some(true)]);
+        let summary = hotplug_cpus(base.path(), 2).unwrap();
+        assert_eq!(summary.online, 2);
+        assert!(summary.onlined.is_empty());
+        assert_eq!(summary.offlined, [2, 3]);
+        assert_eq!(
+            states(base.path()),
+            [None, Some(true), Some(false), Some(false)]
+        );
+        assert_eq!(count_online(base.path()).unwrap(), 2);
+    }
+
+    #[test]
+    fn cpu0_without_online_file() {
+        // A single requested CPU is satisfied by the non-hotpluggable cpu0.
+        let base = fake_sysfs(&[None, Some(true)]);
+        let summary = hotplug_cpus(base.path(), 1).unwrap();
+        assert_eq!(summary.online, 1);
+        assert_eq!(summary.offlined, [1]);
+        assert_eq!(states(base.path()), [None, Some(false)]);
+
+        // Requesting more than available onlines everything, without failing.
+        let summary = hotplug_cpus(base.path(), 8).unwrap();
+        assert_eq!(summary.online, 2);
+        assert_eq!(summary.onlined, [1]);
+        assert_eq!(count_online(base.path()).unwrap(), 2);
+    }
+
+    #[test]
+    fn unchanged_cpus() {
+        let base = fake_sysfs(&[None, Some(true), Some(false)]);
+        let summary = hotplug_cpus(base.path(), 2).unwrap();
+        assert_eq!(summary.online, 2);
+        assert!(summary.onlined.is_empty());
+        assert!(summary.offlined.is_empty());
+    }
+}
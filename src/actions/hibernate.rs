@@ -0,0 +1,130 @@
+use crate::events;
+use std::path::Path;
+use thiserror::Error;
+
+/// Sysfs attribute for system power states.
+static SYSFS_POWER_STATE: &str = "/sys/power/state";
+
+/// Hibernation failures.
+#[derive(Error, Debug)]
+#[non_exhaustive]
+pub enum HibernateError {
+    /// Hibernation is not supported or not allowed on this system.
+    #[error("hibernation not supported: {0}")]
+    Unsupported(String),
+    /// D-Bus interaction with logind failed.
+    #[error("logind D-Bus call failed: {0}")]
+    Dbus(String),
+    /// Access to sysfs failed.
+    #[error("failed to access '{path}': {source}")]
+    Sysfs {
+        path: String,
+        source: std::io::Error,
+    },
+    /// Background task failed.
+    #[error("hibernation task failed: {0}")]
+    Task(String),
+}
+
+/// Mechanism used for hibernating the guest.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum HibernateMethod {
+    /// systemd-logind `Hibernate()` D-Bus call.
+    #[cfg(feature = "logind")]
+    Logind,
+    /// Direct write of `disk` to `/sys/power/state`.
+    Sysfs,
+}
+
+impl Default for HibernateMethod {
+    #[cfg(feature = "logind")]
+    fn default() -> Self {
+        HibernateMethod::Logind
+    }
+
+    #[cfg(not(feature = "logind"))]
+    fn default() -> Self {
+        HibernateMethod::Sysfs
+    }
+}
+
+/// Options for hibernating the guest.
+#[derive(Clone, Debug, Default)]
+pub struct HibernateOptions {
+    /// Hibernation mechanism (default: logind if available, sysfs otherwise).
+    pub method: HibernateMethod,
+    /// Only check whether hibernation is possible, without performing it.
+    pub dry_run: bool,
+}
+
+/// Hibernate the guest, as requested by a `hibernate` event.
+pub async fn hibernate(
+    _event: &events::Hibernate,
+    opts: &HibernateOptions,
+) -> Result<(), HibernateError> {
+    match opts.method {
+        #[cfg(feature = "logind")]
+        HibernateMethod::Logind => hibernate_logind(opts.dry_run).await,
+        HibernateMethod::Sysfs => {
+            let dry_run = opts.dry_run;
+            tokio::task::spawn_blocking(move || hibernate_sysfs(dry_run))
+                .await
+                .map_err(|e| HibernateError::Task(e.to_string()))?
+        }
+    }
+}
+
+/// Hibernate via logind.
+#[cfg(feature = "logind")]
+async fn hibernate_logind(dry_run: bool) -> Result<(), HibernateError> {
+    let conn = zbus::Connection::system()
+        .await
+        .map_err(|e| HibernateError::Dbus(e.to_string()))?;
+    let manager = crate::logind::ManagerProxy::new(&conn)
+        .await
+        .map_err(|e| HibernateError::Dbus(e.to_string()))?;
+
+    let allowed = manager
+        .can_hibernate()
+        .await
+        .map_err(|e| HibernateError::Dbus(e.to_string()))?;
+    if allowed != "yes" {
+        return Err(HibernateError::Unsupported(format!(
+            "logind CanHibernate() returned '{}'",
+            allowed
+        )));
+    }
+    if dry_run {
+        log::debug!("dry-run, skipping logind hibernation");
+        return Ok(());
+    }
+
+    manager
+        .hibernate(false)
+        .await
+        .map_err(|e| HibernateError::Dbus(e.to_string()))
+}
+
+/// Hibernate via sysfs.
+fn hibernate_sysfs(dry_run: bool) -> Result<(), HibernateError> {
+    let attr = Path::new(SYSFS_POWER_STATE);
+    let sysfs_err = |source| HibernateError::Sysfs {
+        path: attr.display().to_string(),
+        source,
+    };
+
+    let states = std::fs::read_to_string(attr).map_err(sysfs_err)?;
+    if !states.split_whitespace().any(|s| s == "disk") {
+        return Err(HibernateError::Unsupported(format!(
+            "'disk' not in available power states '{}'",
+            states.trim()
+        )));
+    }
+    if dry_run {
+        log::debug!("dry-run, skipping sysfs hibernation");
+        return Ok(());
+    }
+
+    std::fs::write(attr, "disk").map_err(sysfs_err)
+}
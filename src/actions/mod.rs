@@ -0,0 +1,19 @@
+//! Helpers for performing host-requested actions.
+//!
+//! These translate parsed [events](../events/index.html) into the
+//! corresponding guest-side actions, so that agents do not need to
+//! implement them on their own.
+
+mod cpus;
+mod hibernate;
+#[cfg(feature = "logind")]
+mod session;
+#[cfg(feature = "logind")]
+mod shutdown;
+
+pub use cpus::{number_of_cpus, report_number_of_cpus, set_number_of_cpus, CpuHotplugSummary};
+pub use hibernate::{hibernate, HibernateError, HibernateMethod, HibernateOptions};
+#[cfg(feature = "logind")]
+pub use session::{lock_screen, log_off};
+#[cfg(feature = "logind")]
+pub use shutdown::shutdown;
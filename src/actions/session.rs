@@ -0,0 +1,25 @@
+use crate::errors::OgaError;
+use crate::events;
+use crate::logind;
+
+/// Lock the active desktop session, as requested by a `lock-screen` event.
+pub async fn lock_screen(_event: &events::LockScreen) -> Result<(), OgaError> {
+    let (session_id, _) = logind::active_session().await?;
+    let manager = logind::manager().await?;
+    log::debug!("locking session '{}'", session_id);
+    manager
+        .lock_session(&session_id)
+        .await
+        .map_err(logind::dbus_error)
+}
+
+/// Terminate the active desktop session, as requested by a `log-off` event.
+pub async fn log_off(_event: &events::LogOff) -> Result<(), OgaError> {
+    let (session_id, _) = logind::active_session().await?;
+    let manager = logind::manager().await?;
+    log::debug!("terminating session '{}'", session_id);
+    manager
+        .terminate_session(&session_id)
+        .await
+        .map_err(logind::dbus_error)
+}
@@ -0,0 +1,49 @@
+use crate::errors::OgaError;
+use crate::events;
+use crate::logind;
+use std::convert::TryFrom;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Power off or reboot the guest, as requested by a `shutdown` event.
+///
+/// If the event carries a message, it is broadcast to logged-in users.
+/// A non-zero timeout schedules the action after the given amount of
+/// seconds, otherwise it is performed immediately.
+pub async fn shutdown(event: &events::Shutdown) -> Result<(), OgaError> {
+    let manager = logind::manager().await?;
+    let reboot = event.is_reboot();
+
+    if let Some(msg) = event.message.as_deref().filter(|m| !m.is_empty()) {
+        manager
+            .set_wall_message(msg, true)
+            .await
+            .map_err(logind::dbus_error)?;
+    }
+
+    let timeout = event.timeout.unwrap_or(0);
+    if timeout == 0 {
+        log::debug!("performing immediate shutdown (reboot: {})", reboot);
+        let res = if reboot {
+            manager.reboot(false).await
+        } else {
+            manager.power_off(false).await
+        };
+        return res.map_err(logind::dbus_error);
+    }
+
+    let kind = if reboot { "reboot" } else { "poweroff" };
+    let when = SystemTime::now()
+        .checked_add(Duration::from_secs(timeout))
+        .ok_or_else(|| format!("invalid shutdown timeout: {} seconds", timeout))?;
+    let usec = when
+        .duration_since(UNIX_EPOCH)
+        .map_err(|e| format!("invalid shutdown time: {}", e))?
+        .as_micros();
+    let usec = u64::try_from(usec)
+        .map_err(|_| format!("invalid shutdown timeout: {} seconds", timeout))?;
+    log::debug!("scheduling {} in {} seconds", kind, timeout);
+    manager
+        .schedule_shutdown(kind, usec)
+        .await
+        .map_err(logind::dbus_error)
+}
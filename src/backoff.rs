@@ -0,0 +1,211 @@
+/*! Backoff policies for reconnection attempts.
+
+Hosts can stay unreachable for a long time (e.g. while storage is stalled),
+thus reconnection loops should back off instead of retrying at a fixed
+cadence. Policies are plain configuration and can be (de)serialized.
+*/
+
+use serde::{Deserialize, Serialize};
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::time::Duration;
+
+/// Policy for delaying reconnection attempts.
+pub trait BackoffPolicy: std::fmt::Debug + Send + Sync {
+    /// Delay before the given reconnection attempt (starting from 1), or `None` to give up.
+    fn delay(&self, attempt: u32) -> Option<Duration>;
+}
+
+/// Retry at a fixed cadence.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(default)]
+pub struct FixedBackoff {
+    #[serde(rename = "interval_secs", with = "crate::duration_secs")]
+    interval: Duration,
+    max_retries: Option<u32>,
+}
+
+impl Default for FixedBackoff {
+    fn default() -> Self {
+        Self {
+            interval: Duration::from_secs(5),
+            max_retries: None,
+        }
+    }
+}
+
+impl FixedBackoff {
+    /// Return a policy with default settings.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Delay between attempts (default: 5 seconds).
+    pub fn interval(mut self, arg: Option<Duration>) -> Self {
+        let setting = arg.unwrap_or_else(|| Duration::from_secs(5));
+        self.interval = setting;
+        self
+    }
+
+    /// Maximum number of attempts, or `None` for unlimited (default: unlimited).
+    pub fn max_retries(mut self, arg: Option<u32>) -> Self {
+        self.max_retries = arg;
+        self
+    }
+}
+
+impl BackoffPolicy for FixedBackoff {
+    fn delay(&self, attempt: u32) -> Option<Duration> {
+        if self.max_retries.is_some_and(|max| attempt > max) {
+            return None;
+        }
+        Some(self.interval)
+    }
+}
+
+/// Default fraction of jitter for exponential delays.
+const DEFAULT_JITTER: f64 = 0.2;
+
+/// Retry with exponentially growing delays, up to a cap, with random jitter.
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+#[serde(default)]
+pub struct ExponentialBackoff {
+    #[serde(rename = "initial_delay_secs", with = "crate::duration_secs")]
+    initial_delay: Duration,
+    #[serde(rename = "max_delay_secs", with = "crate::duration_secs")]
+    max_delay: Duration,
+    multiplier: u32,
+    jitter: f64,
+    max_retries: Option<u32>,
+}
+
+impl Default for ExponentialBackoff {
+    fn default() -> Self {
+        Self {
+            initial_delay: Duration::from_secs(1),
+            max_delay: Duration::from_secs(300),
+            multiplier: 2,
+            jitter: DEFAULT_JITTER,
+            max_retries: None,
+        }
+    }
+}
+
+impl ExponentialBackoff {
+    /// Return a policy with default settings.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Delay before the first attempt (default: 1 second).
+    pub fn initial_delay(mut self, arg: Option<Duration>) -> Self {
+        let setting = arg.unwrap_or_else(|| Duration::from_secs(1));
+        self.initial_delay = setting;
+        self
+    }
+
+    /// Cap for delays, before jitter (default: 300 seconds).
+    pub fn max_delay(mut self, arg: Option<Duration>) -> Self {
+        let setting = arg.unwrap_or_else(|| Duration::from_secs(300));
+        self.max_delay = setting;
+        self
+    }
+
+    /// Growth factor between consecutive delays, at least 1 (default: 2).
+    pub fn multiplier(mut self, arg: Option<u32>) -> Self {
+        let setting = arg.unwrap_or(2);
+        self.multiplier = setting.max(1);
+        self
+    }
+
+    /// Fraction of each delay to randomly add or subtract, between 0 and 1 (default: 0.2).
+    ///
+    /// This spreads reconnections of many guests after a host outage.
+    pub fn jitter(mut self, arg: Option<f64>) -> Self {
+        let setting = arg.unwrap_or(DEFAULT_JITTER);
+        self.jitter = sanitize_jitter(setting);
+        self
+    }
+
+    /// Maximum number of attempts, or `None` for unlimited (default: unlimited).
+    pub fn max_retries(mut self, arg: Option<u32>) -> Self {
+        self.max_retries = arg;
+        self
+    }
+}
+
+impl BackoffPolicy for ExponentialBackoff {
+    fn delay(&self, attempt: u32) -> Option<Duration> {
+        if self.max_retries.is_some_and(|max| attempt > max) {
+            return None;
+        }
+
+        // Settings may come from deserialization, thus bypassing setters.
+        let exp = attempt.saturating_sub(1);
+        let factor = self.multiplier.max(1).checked_pow(exp).unwrap_or(u32::MAX);
+        let base = self
+            .initial_delay
+            .checked_mul(factor)
+            .unwrap_or(self.max_delay)
+            .min(self.max_delay);
+
+        let jitter = sanitize_jitter(self.jitter);
+        let scale = 1.0 - jitter + 2.0 * jitter * random_unit();
+        let delay =
+            Duration::try_from_secs_f64(base.as_secs_f64() * scale).unwrap_or(Duration::MAX);
+        Some(delay)
+    }
+}
+
+/// Bound a jitter fraction between 0 and 1, falling back to the default if not a number.
+fn sanitize_jitter(jitter: f64) -> f64 {
+    match jitter.is_nan() {
+        true => DEFAULT_JITTER,
+        false => jitter.clamp(0.0, 1.0),
+    }
+}
+
+/// Return a pseudo-random value in `[0, 1)`, good enough for jitter.
+fn random_unit() -> f64 {
+    let mut hasher = RandomState::new().build_hasher();
+    hasher.write_u64(0);
+    (hasher.finish() >> 11) as f64 / (1u64 << 53) as f64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sanitize_settings() {
+        let policy = ExponentialBackoff::new()
+            .jitter(Some(f64::NAN))
+            .multiplier(Some(0));
+        assert_eq!(policy.jitter, DEFAULT_JITTER);
+        assert_eq!(policy.multiplier, 1);
+
+        let policy = ExponentialBackoff {
+            jitter: f64::NAN,
+            multiplier: 0,
+            ..ExponentialBackoff::new()
+        };
+        for attempt in [1, 5] {
+            let delay = policy.delay(attempt).unwrap();
+            assert!(delay >= Duration::from_millis(800), "{:?}", delay);
+            assert!(delay <= Duration::from_millis(1200), "{:?}", delay);
+        }
+    }
+
+    #[test]
+    fn saturate_huge_delays() {
+        let policy = ExponentialBackoff::new()
+            .initial_delay(Some(Duration::MAX))
+            .max_delay(Some(Duration::MAX))
+            .jitter(Some(1.0));
+        for attempt in [1, 2, 100] {
+            assert!(policy.delay(attempt).is_some());
+        }
+        let policy = policy.jitter(Some(0.0));
+        assert_eq!(policy.delay(3), Some(Duration::MAX));
+    }
+}
@@ -0,0 +1,254 @@
+//! Reference oVirt Guest Agent daemon.
+//!
+//! This wires together the client library and its action helpers into a
+//! minimal guest agent, reconnecting to the host on failures.
+
+use futures::future::BoxFuture;
+use futures::FutureExt;
+use std::path::Path;
+use tokio::signal::unix::{signal, SignalKind};
+use tokio::time::{self, Duration};
+use tokio_oga::backoff::{BackoffPolicy, ExponentialBackoff};
+use tokio_oga::commands::AsFrame;
+use tokio_oga::events::Event;
+use tokio_oga::subscription::Received;
+use tokio_oga::{
+    actions, commands, config, guestinfo, systemd, users, OgaBuilder, OgaCommandSender, OgaError,
+};
+
+type AgentError = Box<dyn std::error::Error + 'static>;
+
+/// Time allowed for delivering the shutdown notification.
+const SHUTDOWN_DEADLINE_SECS: u64 = 2;
+
+/// Default interval between active-user checks.
+const USER_CHECK_SECS: u64 = 10;
+
+/// Default interval between CPU-count reports.
+const CPU_REPORT_SECS: u64 = 60;
+
+/// Default interval between applications reports.
+const APPS_REPORT_SECS: u64 = 120;
+
+/// Default interval between disks-usage reports.
+const DISKS_USAGE_REPORT_SECS: u64 = 300;
+
+/// Interval between other system information reports.
+const INFO_REPORT_SECS: u64 = 60;
+
+fn main() -> Result<(), AgentError> {
+    env_logger::Builder::from_default_env()
+        .filter(None, log::LevelFilter::Info)
+        .init();
+
+    let rt = tokio::runtime::Runtime::new()?;
+    rt.block_on(run())
+}
+
+/// Run the agent, reconnecting on failures, until terminated.
+async fn run() -> Result<(), AgentError> {
+    let (builder, cfg) = load_config()?;
+    let builder = builder
+        .notify_ready(Some(systemd::NotifyReady::OnConnect))
+        .systemd_watchdog(Some(true));
+    let backoff = ExponentialBackoff::default();
+    let mut sigterm = signal(SignalKind::terminate())?;
+    let mut startup_sent = false;
+    let mut attempt = 0;
+
+    loop {
+        match serve(
+            builder.clone(),
+            &cfg,
+            &mut sigterm,
+            &mut startup_sent,
+            &mut attempt,
+        )
+        .await
+        {
+            Ok(()) => return Ok(()),
+            Err(e) => log::error!("client failure: {}", e),
+        }
+
+        attempt += 1;
+        let delay = backoff
+            .delay(attempt)
+            .ok_or("too many reconnection attempts")?;
+        log::info!("reconnecting in {:.1} seconds", delay.as_secs_f64());
+        tokio::select! {
+            _ = time::sleep(delay) => {},
+            _ = sigterm.recv() => return Ok(()),
+        }
+    }
+}
+
+/// Load configuration, from the reference agent file if present.
+fn load_config() -> Result<(OgaBuilder, config::AgentConfig), OgaError> {
+    if Path::new(config::DEFAULT_CONFIG_PATH).exists() {
+        log::info!(
+            "loading configuration from '{}'",
+            config::DEFAULT_CONFIG_PATH
+        );
+        let builder = OgaBuilder::from_config_file(config::DEFAULT_CONFIG_PATH)?;
+        let cfg = config::AgentConfig::from_file(config::DEFAULT_CONFIG_PATH)?;
+        Ok((builder, cfg))
+    } else {
+        Ok((OgaBuilder::from_env()?, config::AgentConfig::default()))
+    }
+}
+
+/// Serve a single client connection, until failure or termination.
+async fn serve(
+    builder: OgaBuilder,
+    cfg: &config::AgentConfig,
+    sigterm: &mut tokio::signal::unix::Signal,
+    startup_sent: &mut bool,
+    attempt: &mut u32,
+) -> Result<(), OgaError> {
+    let client = builder.connect().await?;
+    *attempt = 0;
+    let mut term_chan = client.termination_chan();
+    let mut events = client.event_chan();
+    let cmd_chan = client.command_chan();
+    log::info!("connected to host");
+
+    if !*startup_sent {
+        cmd_chan
+            .send(Box::new(commands::SessionStartup::default()))
+            .await?;
+        *startup_sent = true;
+    }
+
+    let user_rate = cfg.report_user_rate.unwrap_or(USER_CHECK_SECS).max(1);
+    let tracker = users::ActiveUserTracker::new(
+        Box::new(users::LogindBackend::new()),
+        Duration::from_secs(user_rate),
+    )
+    .ignored_users(cfg.ignored_users.clone())
+    .run(client.command_chan());
+    tokio::pin!(tracker);
+
+    let reporter = build_reporter(cfg);
+    let refresh = reporter.refresh_handle();
+    let reporting = reporter.run(client.command_chan());
+    tokio::pin!(reporting);
+
+    loop {
+        tokio::select! {
+            err = &mut tracker => return Err(err),
+            err = &mut reporting => return Err(err),
+            res = events.recv() => match res {
+                Some(Received::Event(Event::Refresh(ev))) => {
+                    log::info!("received event: {}", Event::Refresh(ev));
+                    refresh.refresh();
+                }
+                Some(Received::Event(event)) => handle_event(event, cmd_chan.clone()),
+                Some(Received::EventsDropped(n)) => log::warn!("{} events dropped", n),
+                None => return Err("end of events stream".into()),
+            },
+            err = term_chan.recv() => return Err(err),
+            _ = sigterm.recv() => {
+                log::info!("terminating");
+                let shutdown = commands::SessionShutdown::default();
+                let deadline = time::Instant::now() + Duration::from_secs(SHUTDOWN_DEADLINE_SECS);
+                cmd_chan.send_with_deadline(Box::new(shutdown), deadline).await?;
+                return Ok(());
+            },
+        }
+    }
+}
+
+/// Build a reporter with all system information collectors, at configured rates.
+fn build_reporter(cfg: &config::AgentConfig) -> guestinfo::Reporter {
+    let rate = |setting: Option<u64>, default: u64| Duration::from_secs(setting.unwrap_or(default));
+    let info_rate = Duration::from_secs(INFO_REPORT_SECS);
+
+    let mut reporter = guestinfo::Reporter::new()
+        .collector(
+            Box::new(CpuCountCollector {}),
+            rate(cfg.report_num_cpu_rate, CPU_REPORT_SECS),
+        )
+        .collector(Box::new(guestinfo::HostNameCollector::default()), info_rate)
+        .collector(
+            Box::new(guestinfo::OsVersionCollector::default()),
+            info_rate,
+        )
+        .collector(Box::new(guestinfo::OsInfoCollector::default()), info_rate)
+        .collector(Box::new(guestinfo::TimezoneCollector::default()), info_rate);
+
+    #[cfg(target_os = "linux")]
+    {
+        let network = guestinfo::NetworkCollector::default().ignored_nics(cfg.ignored_nics.clone());
+        let disks_usage = guestinfo::DisksUsageCollector::default()
+            .ignored_fs(cfg.ignored_fs.clone())
+            .ignore_zero_size(cfg.ignore_zero_size_fs.unwrap_or(false));
+        reporter = reporter
+            .collector(Box::new(network), info_rate)
+            .collector(
+                Box::new(guestinfo::DiskMappingCollector::default()),
+                info_rate,
+            )
+            .collector(
+                Box::new(disks_usage),
+                rate(cfg.report_disk_usage, DISKS_USAGE_REPORT_SECS),
+            );
+    }
+
+    let app_sources = guestinfo::detect_app_sources();
+    if !app_sources.is_empty() {
+        reporter = reporter.collector(
+            Box::new(guestinfo::ApplicationsCollector::new(app_sources)),
+            rate(cfg.report_application_rate, APPS_REPORT_SECS),
+        );
+    }
+
+    #[cfg(feature = "containers")]
+    {
+        reporter = reporter.collector(
+            Box::new(guestinfo::ContainersCollector::default()),
+            info_rate,
+        );
+    }
+
+    reporter
+}
+
+/// Collector for the `number-of-cpus` report.
+#[derive(Debug)]
+struct CpuCountCollector {}
+
+impl guestinfo::Collector for CpuCountCollector {
+    fn collect(&self) -> BoxFuture<'_, Result<Box<dyn AsFrame>, OgaError>> {
+        async {
+            let count = actions::number_of_cpus().await?;
+            Ok(Box::new(commands::NumberOfCpus { count }) as Box<dyn AsFrame>)
+        }
+        .boxed()
+    }
+}
+
+/// Perform the action requested by an event, in the background.
+fn handle_event(event: Event, cmd_chan: OgaCommandSender) {
+    log::info!("received event: {}", event);
+    tokio::spawn(async move {
+        let res = match &event {
+            Event::Shutdown(ev) => actions::shutdown(ev).await,
+            Event::LockScreen(ev) => actions::lock_screen(ev).await,
+            Event::LogOff(ev) => actions::log_off(ev).await,
+            Event::Hibernate(ev) => {
+                let opts = actions::HibernateOptions::default();
+                actions::hibernate(ev, &opts)
+                    .await
+                    .map_err(|e| OgaError::from(e.to_string()))
+            }
+            Event::SetNumberOfCpus(ev) => match actions::set_number_of_cpus(ev).await {
+                Ok(_) => actions::report_number_of_cpus(&cmd_chan).await.map(|_| ()),
+                Err(e) => Err(e),
+            },
+            _ => Ok(()),
+        };
+        if let Err(e) = res {
+            log::error!("failed to handle event {}: {}", event, e);
+        }
+    });
+}
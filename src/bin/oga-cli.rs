@@ -0,0 +1,140 @@
+//! Command-line tool for interactive access to the oVirt Guest Agent protocol.
+
+use clap::{Parser, Subcommand};
+use std::path::PathBuf;
+use tokio_oga::commands::{self, AsFrame};
+use tokio_oga::subscription::Received;
+use tokio_oga::OgaBuilder;
+
+type CliError = Box<dyn std::error::Error + 'static>;
+
+/// oVirt Guest Agent protocol client.
+#[derive(Debug, Parser)]
+#[command(name = "oga-cli")]
+struct Cli {
+    /// Path to the VirtIO serial port.
+    #[arg(long, global = true)]
+    device: Option<PathBuf>,
+    /// Increase logging verbosity.
+    #[arg(short, long, global = true, action = clap::ArgAction::Count)]
+    verbose: u8,
+    #[command(subcommand)]
+    action: Action,
+}
+
+#[derive(Debug, Subcommand)]
+enum Action {
+    /// Send a command to the host.
+    Send {
+        #[command(subcommand)]
+        command: Command,
+    },
+    /// Print events received from the host.
+    Tail {
+        /// Exit after receiving this many events.
+        #[arg(long)]
+        count: Option<usize>,
+    },
+}
+
+#[derive(Debug, Subcommand)]
+enum Command {
+    /// Send an `heartbeat` command.
+    Heartbeat {
+        /// Free RAM, in MiB.
+        #[arg(long, default_value_t = 0)]
+        free_ram: u64,
+    },
+    /// Send a `session-startup` command.
+    SessionStartup,
+    /// Send a `session-shutdown` command.
+    SessionShutdown,
+    /// Send an `uninstalled` command.
+    Uninstalled,
+    /// Send an `active-user` command.
+    ActiveUser {
+        /// Name of the active user.
+        name: String,
+    },
+}
+
+impl Command {
+    /// Build the protocol command.
+    fn into_frame(self) -> Box<dyn AsFrame> {
+        match self {
+            Command::Heartbeat { free_ram } => {
+                let mut beat = commands::Heartbeat::default();
+                beat.free_ram = free_ram;
+                Box::new(beat)
+            }
+            Command::SessionStartup => Box::new(commands::SessionStartup::default()),
+            Command::SessionShutdown => Box::new(commands::SessionShutdown::default()),
+            Command::Uninstalled => Box::new(commands::Uninstalled::default()),
+            Command::ActiveUser { name } => Box::new(commands::ActiveUser { name }),
+        }
+    }
+}
+
+fn main() -> Result<(), CliError> {
+    let cli = Cli::parse();
+
+    let level = match cli.verbose {
+        0 => log::LevelFilter::Warn,
+        1 => log::LevelFilter::Info,
+        2 => log::LevelFilter::Debug,
+        _ => log::LevelFilter::Trace,
+    };
+    env_logger::Builder::from_default_env()
+        .filter(Some("tokio_oga"), level)
+        .init();
+
+    let rt = tokio::runtime::Runtime::new()?;
+    rt.block_on(run(cli))
+}
+
+/// Run the requested action.
+async fn run(cli: Cli) -> Result<(), CliError> {
+    let builder = OgaBuilder::default().device_path(cli.device);
+    match cli.action {
+        Action::Send { command } => send(builder, command).await,
+        Action::Tail { count } => tail(builder, count).await,
+    }
+}
+
+/// Send a single command, without any other traffic.
+async fn send(builder: OgaBuilder, command: Command) -> Result<(), CliError> {
+    let client = builder
+        .initial_heartbeat(Some(false))
+        .pacemaker(Some(false))
+        .connect()
+        .await?;
+
+    let frame = command.into_frame();
+    let desc = format!("{:?}", frame);
+    client.command_chan().send(frame).await?;
+    println!("sent: {}", desc);
+    Ok(())
+}
+
+/// Print events from the host, until termination.
+async fn tail(builder: OgaBuilder, count: Option<usize>) -> Result<(), CliError> {
+    let client = builder.connect().await?;
+    let mut term_chan = client.termination_chan();
+    let mut events = client.event_chan();
+
+    let mut received = 0;
+    while count.map(|max| received < max).unwrap_or(true) {
+        tokio::select! {
+            res = events.recv() => match res {
+                Some(Received::Event(event)) => {
+                    println!("{:?}", event);
+                    received += 1;
+                }
+                Some(Received::EventsDropped(n)) => eprintln!("warning: {} events dropped", n),
+                None => return Err("end of events stream".into()),
+            },
+            err = term_chan.recv() => return Err(Box::new(err)),
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,81 @@
+//! Synchronous client wrapper.
+//!
+//! This provides a blocking API on top of `OgaClient`, for small utilities
+//! and init scripts which do not want to deal with async plumbing.
+//! The wrapper owns its own runtime, with a background worker thread
+//! running internal tasks (e.g. heartbeats) between calls.
+
+use crate::commands::AsFrame;
+use crate::events::Event;
+use crate::subscription::EventReceiver;
+use crate::{OgaBuilder, OgaClient, OgaError, TerminationReceiver};
+use tokio::runtime::{self, Runtime};
+use tokio::time::{self, Duration};
+
+/// Blocking client for oVirt Guest Agent protocol.
+#[derive(Debug)]
+pub struct BlockingOgaClient {
+    // Fields are dropped in order; the client must go before its runtime.
+    client: OgaClient,
+    events: EventReceiver<Event>,
+    termination: TerminationReceiver,
+    runtime: Runtime,
+}
+
+impl BlockingOgaClient {
+    /// Connect, initialize, and return a blocking client.
+    ///
+    /// This must not be called from within an async runtime context.
+    pub fn connect(builder: OgaBuilder) -> Result<Self, OgaError> {
+        let runtime = runtime::Builder::new_multi_thread()
+            .worker_threads(1)
+            .thread_name("tokio-oga")
+            .enable_all()
+            .build()
+            .map_err(|e| format!("failed to create runtime: {}", e))?;
+        let client = runtime.block_on(builder.connect())?;
+        let events = client.event_chan();
+        let termination = client.termination_chan();
+
+        let blocking = Self {
+            client,
+            events,
+            termination,
+            runtime,
+        };
+        Ok(blocking)
+    }
+
+    /// Send a command to the host, blocking until it has been written.
+    pub fn send(&self, cmd: Box<dyn AsFrame>) -> Result<(), OgaError> {
+        let sender = self.client.command_chan();
+        self.runtime.block_on(sender.send(cmd))
+    }
+
+    /// Receive the next event from the host, blocking until one is available.
+    ///
+    /// This returns `None` once the client has terminated.
+    pub fn recv_event(&mut self) -> Option<Event> {
+        self.runtime.block_on(self.events.recv_event())
+    }
+
+    /// Receive the next event from the host, blocking up to the given timeout.
+    ///
+    /// This returns `Ok(None)` on timeout, and an error once the client has terminated.
+    pub fn recv_event_timeout(&mut self, timeout: Duration) -> Result<Option<Event>, OgaError> {
+        let events = &mut self.events;
+        let res = self
+            .runtime
+            .block_on(async { time::timeout(timeout, events.recv_event()).await });
+        match res {
+            Ok(Some(event)) => Ok(Some(event)),
+            Ok(None) => Err("end of events stream".into()),
+            Err(_) => Ok(None),
+        }
+    }
+
+    /// Block until the client terminates, returning the failure which caused it.
+    pub fn next_termination(&mut self) -> OgaError {
+        self.runtime.block_on(self.termination.recv())
+    }
+}
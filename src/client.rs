@@ -0,0 +1,1771 @@
+//! Tokio-based protocol client.
+
+use crate::commands::{self, AsFrame};
+use crate::errors::{self, OgaError};
+#[cfg(feature = "outbox")]
+use crate::outbox;
+#[cfg(feature = "systemd")]
+use crate::systemd;
+use crate::virtio::{FrameWriter, VirtioPort};
+#[cfg(target_os = "linux")]
+use crate::DEFAULT_CHANNEL_NAME;
+use crate::{config, health, hooks, ping, protocol, raw, retry, stats, subscription, tasks};
+use crate::{
+    DEFAULT_VIRTIO_PATH, ENV_COMMANDS_BUFFER, ENV_CONNECT_TIMEOUT, ENV_DEVICE_PATH,
+    ENV_EVENTS_BUFFER, ENV_HEARTBEAT_SECS, PRIMARY_CHANNEL,
+};
+use bytes::Bytes;
+use futures::future::{self, AbortHandle, FutureExt, TryFutureExt};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU8, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+use tokio::io::AsyncWriteExt;
+use tokio::sync::{broadcast, mpsc, oneshot, watch};
+use tokio::time::{self, Duration};
+
+/// Tuple with pending frame and channel for the result.
+pub(crate) type FramePlusChan = (
+    EncodedCommand,
+    oneshot::Sender<Result<DeliveryReceipt, OgaError>>,
+);
+
+/// Command already encoded as a frame, ready to be written.
+///
+/// Commands are encoded on the sending side, so that the manager
+/// only has to perform writes.
+#[derive(Clone, Debug)]
+pub(crate) struct EncodedCommand {
+    /// Protocol name of the command.
+    pub(crate) name: String,
+    pub(crate) frame: Bytes,
+    /// Minimum API version the host must support for this command.
+    pub(crate) min_api_version: u8,
+    /// Time when the command was queued for sending.
+    pub(crate) queued_at: time::Instant,
+}
+
+impl EncodedCommand {
+    /// Encode a command.
+    pub(crate) fn encode(cmd: &dyn AsFrame) -> Result<Self, OgaError> {
+        let frame = protocol::encode_frame(cmd)?;
+        Ok(Self {
+            name: cmd.name().to_string(),
+            frame,
+            min_api_version: cmd.min_api_version(),
+            queued_at: time::Instant::now(),
+        })
+    }
+
+    /// Return an empty marker, which gets acknowledged once dispatched instead of written.
+    pub(crate) fn marker() -> Self {
+        Self {
+            name: String::new(),
+            frame: Bytes::new(),
+            min_api_version: 0,
+            queued_at: time::Instant::now(),
+        }
+    }
+
+    /// Whether this is a marker, as opposed to an actual frame.
+    pub(crate) fn is_marker(&self) -> bool {
+        self.frame.is_empty()
+    }
+}
+
+/// Receipt for a command written to the host.
+///
+/// Queueing times steadily growing indicate a host which is slow at
+/// draining the channel.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub struct DeliveryReceipt {
+    /// Time when the frame was completely written.
+    pub written_at: SystemTime,
+    /// Time spent queued, from sending until its write started.
+    pub queued: Duration,
+    /// Time spent writing the frame, e.g. waiting for the host to make room.
+    pub writing: Duration,
+}
+
+impl DeliveryReceipt {
+    /// Return a receipt for a frame just written, whose write started at `started`.
+    pub(crate) fn new(queued_at: time::Instant, started: time::Instant) -> Self {
+        Self {
+            written_at: SystemTime::now(),
+            queued: started.saturating_duration_since(queued_at),
+            writing: started.elapsed(),
+        }
+    }
+}
+
+/// Configuration and builder for `OgaClient`.
+///
+/// Settings can be (de)serialized, so that they can be nested inside
+/// application configuration files. Missing fields keep their default value.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(default)]
+pub struct OgaBuilder {
+    additional_channels: BTreeMap<String, PathBuf>,
+    #[serde(skip)]
+    audit_hook: Option<hooks::AuditHook>,
+    commands_buffer: usize,
+    #[serde(skip)]
+    command_middleware: Vec<hooks::CommandMiddleware>,
+    #[serde(skip)]
+    connect_hook: Option<hooks::ConnectHook>,
+    #[serde(rename = "connect_timeout_secs", with = "crate::duration_secs")]
+    connect_timeout: Duration,
+    #[serde(skip)]
+    dead_letter_hook: Option<hooks::DeadLetterHook>,
+    #[serde(skip)]
+    disconnect_hook: Option<hooks::DisconnectHook>,
+    #[serde(rename = "dedup_window_secs", with = "crate::duration_secs")]
+    dedup_window: Duration,
+    events_buffer: usize,
+    #[serde(skip)]
+    event_middleware: Vec<hooks::EventMiddleware>,
+    #[serde(rename = "handshake_timeout_secs", with = "crate::duration_secs")]
+    handshake_timeout: Duration,
+    heartbeat_secs: u8,
+    #[serde(rename = "host_silence_timeout_secs", with = "crate::duration_secs")]
+    host_silence_timeout: Duration,
+    host_silence_policy: health::SilencePolicy,
+    ignored_events: BTreeSet<String>,
+    initial_heartbeat: bool,
+    lag_policy: subscription::LagPolicy,
+    invalid_utf8: raw::Utf8Policy,
+    max_decode_failures: usize,
+    #[serde(skip)]
+    retry_queue: Option<retry::RetryQueue>,
+    #[serde(skip)]
+    on_connect: Arc<Mutex<Vec<Box<dyn AsFrame>>>>,
+    #[cfg(feature = "outbox")]
+    outbox_dir: Option<PathBuf>,
+    #[cfg(feature = "systemd")]
+    notify_ready: systemd::NotifyReady,
+    pacemaker: bool,
+    #[serde(rename = "permission_retry_secs", with = "crate::duration_secs")]
+    permission_retry: Duration,
+    read_buffer: usize,
+    #[serde(rename = "read_idle_timeout_secs", with = "crate::duration_secs")]
+    read_idle_timeout: Duration,
+    read_idle_policy: health::IdlePolicy,
+    replay_buffer: usize,
+    retain_raw_frames: bool,
+    strict_device_checks: bool,
+    #[cfg(feature = "systemd")]
+    systemd_watchdog: bool,
+    #[serde(rename = "device_path")]
+    virtio: PathBuf,
+    #[serde(rename = "write_timeout_secs", with = "crate::duration_secs")]
+    write_timeout: Duration,
+}
+
+impl Default for OgaBuilder {
+    fn default() -> Self {
+        Self {
+            additional_channels: BTreeMap::new(),
+            audit_hook: None,
+            commands_buffer: 10,
+            command_middleware: Vec::new(),
+            connect_hook: None,
+            connect_timeout: Duration::from_secs(5),
+            dead_letter_hook: None,
+            disconnect_hook: None,
+            dedup_window: Duration::from_secs(0),
+            events_buffer: 10,
+            event_middleware: Vec::new(),
+            handshake_timeout: Duration::from_secs(0),
+            heartbeat_secs: 5,
+            host_silence_timeout: Duration::from_secs(0),
+            host_silence_policy: health::SilencePolicy::default(),
+            ignored_events: BTreeSet::new(),
+            initial_heartbeat: true,
+            lag_policy: subscription::LagPolicy::default(),
+            invalid_utf8: raw::Utf8Policy::default(),
+            max_decode_failures: 0,
+            retry_queue: None,
+            on_connect: Arc::new(Mutex::new(Vec::new())),
+            #[cfg(feature = "outbox")]
+            outbox_dir: None,
+            #[cfg(feature = "systemd")]
+            notify_ready: systemd::NotifyReady::default(),
+            pacemaker: true,
+            permission_retry: Duration::from_secs(0),
+            read_buffer: 8 * 1024,
+            read_idle_timeout: Duration::from_secs(0),
+            read_idle_policy: health::IdlePolicy::default(),
+            replay_buffer: 0,
+            retain_raw_frames: false,
+            strict_device_checks: false,
+            #[cfg(feature = "systemd")]
+            systemd_watchdog: false,
+            virtio: PathBuf::from(DEFAULT_VIRTIO_PATH),
+            write_timeout: Duration::from_secs(0),
+        }
+    }
+}
+
+impl OgaBuilder {
+    /// Return a builder with default configuration settings.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Return a builder configured from environment variables.
+    ///
+    /// Settings which are not present in the environment keep their
+    /// default value. Supported variables are:
+    ///  * `OGA_DEVICE_PATH` - path to the VirtIO serial port.
+    ///  * `OGA_HEARTBEAT_SECS` - seconds between heartbeats.
+    ///  * `OGA_CONNECT_TIMEOUT` - connect timeout, in seconds.
+    ///  * `OGA_COMMANDS_BUFFER` - capacity of the commands queue.
+    ///  * `OGA_EVENTS_BUFFER` - capacity of the events queue.
+    pub fn from_env() -> Result<Self, OgaError> {
+        let builder = Self::default()
+            .device_path(std::env::var_os(ENV_DEVICE_PATH))
+            .heartbeat_interval(Self::env_value(ENV_HEARTBEAT_SECS)?)
+            .connect_timeout(Self::env_value(ENV_CONNECT_TIMEOUT)?.map(Duration::from_secs))
+            .commands_buffer(Self::env_value(ENV_COMMANDS_BUFFER)?)
+            .events_buffer(Self::env_value(ENV_EVENTS_BUFFER)?);
+        Ok(builder)
+    }
+
+    /// Return a builder configured from a reference agent configuration file.
+    ///
+    /// This honors the device path and heartbeat rate from an
+    /// `ovirt-guest-agent.conf` file (e.g. `config::DEFAULT_CONFIG_PATH`).
+    /// Settings which are not present in the file keep their default value.
+    /// Report rates and ignore lists are not client settings; they are
+    /// available from `config::AgentConfig`, for reporters and trackers.
+    pub fn from_config_file(path: impl AsRef<Path>) -> Result<Self, OgaError> {
+        let cfg = config::AgentConfig::from_file(path)?;
+        let builder = Self::default()
+            .device_path(cfg.device)
+            .heartbeat_interval(cfg.heart_beat_rate);
+        Ok(builder)
+    }
+
+    /// Parse the value of an environment variable, if present.
+    fn env_value<T>(name: &str) -> Result<Option<T>, OgaError>
+    where
+        T: std::str::FromStr,
+        T::Err: std::fmt::Display,
+    {
+        let value = match std::env::var(name) {
+            Ok(v) => v,
+            Err(std::env::VarError::NotPresent) => return Ok(None),
+            Err(e) => return Err(format!("invalid environment variable '{}': {}", name, e).into()),
+        };
+        let setting = value
+            .trim()
+            .parse()
+            .map_err(|e| format!("invalid value for '{}': {}", name, e))?;
+        Ok(Some(setting))
+    }
+
+    /// Whether to send an heartbeat on connect (default: true).
+    pub fn initial_heartbeat(mut self, arg: Option<bool>) -> Self {
+        let setting = arg.unwrap_or(true);
+        self.initial_heartbeat = setting;
+        self
+    }
+
+    /// How to handle incoming frames which are not valid UTF-8 (default: terminate).
+    pub fn invalid_utf8(mut self, arg: Option<raw::Utf8Policy>) -> Self {
+        let setting = arg.unwrap_or_default();
+        self.invalid_utf8 = setting;
+        self
+    }
+
+    /// Maximum number of consecutive incoming frames which fail decoding,
+    /// or zero to disable (default: disabled).
+    ///
+    /// Once reached on any channel, the client terminates with a protocol
+    /// error, instead of endlessly skipping frames from a corrupted stream.
+    /// Well-formed frames of unknown events do not count as failures.
+    pub fn max_decode_failures(mut self, arg: Option<usize>) -> Self {
+        let setting = arg.unwrap_or(0);
+        self.max_decode_failures = setting;
+        self
+    }
+
+    /// When to notify service readiness to systemd (default: disabled).
+    #[cfg(feature = "systemd")]
+    pub fn notify_ready(mut self, arg: Option<systemd::NotifyReady>) -> Self {
+        let setting = arg.unwrap_or_default();
+        self.notify_ready = setting;
+        self
+    }
+
+    /// Whether to send systemd watchdog keepalives while healthy (default: false).
+    ///
+    /// When running under `WatchdogSec=`, keepalives are only sent while
+    /// heartbeats are being delivered to the host. This requires the pacemaker.
+    #[cfg(feature = "systemd")]
+    pub fn systemd_watchdog(mut self, arg: Option<bool>) -> Self {
+        let setting = arg.unwrap_or(false);
+        self.systemd_watchdog = setting;
+        self
+    }
+
+    /// Whether to run the heartbeat generator (default: true).
+    ///
+    /// This can be disabled for one-shot clients (e.g. startup notifiers),
+    /// in which case no periodic heartbeats are sent at all.
+    pub fn pacemaker(mut self, arg: Option<bool>) -> Self {
+        let setting = arg.unwrap_or(true);
+        self.pacemaker = setting;
+        self
+    }
+
+    /// Seconds between heartbeats, or 0 to disable (default: 5).
+    pub fn heartbeat_interval(mut self, arg: Option<u8>) -> Self {
+        let setting = arg.unwrap_or(5);
+        self.heartbeat_secs = setting;
+        self
+    }
+
+    /// Timeout for connection setup, including the initial heartbeat (default: 5 seconds).
+    ///
+    /// This also bounds each attempt at opening a device node.
+    pub fn connect_timeout(mut self, arg: Option<Duration>) -> Self {
+        let setting = arg.unwrap_or_else(|| Duration::from_secs(5));
+        self.connect_timeout = setting;
+        self
+    }
+
+    /// Maximum time for writing a single frame to the host, or zero to
+    /// disable (default: disabled).
+    ///
+    /// A host not draining the channel otherwise blocks writes forever.
+    /// On expiry, a `HealthEvent::WriteStalled` is reported via
+    /// `OgaClient::health_chan()` and the client terminates.
+    pub fn write_timeout(mut self, arg: Option<Duration>) -> Self {
+        let setting = arg.unwrap_or_else(|| Duration::from_secs(0));
+        self.write_timeout = setting;
+        self
+    }
+
+    /// Capacity of the queue for outgoing commands (default: 10).
+    pub fn commands_buffer(mut self, arg: Option<usize>) -> Self {
+        let setting = arg.unwrap_or(10);
+        self.commands_buffer = setting;
+        self
+    }
+
+    /// Capacity of the queue for incoming events (default: 10).
+    pub fn events_buffer(mut self, arg: Option<usize>) -> Self {
+        let setting = arg.unwrap_or(10);
+        self.events_buffer = setting;
+        self
+    }
+
+    /// Initial capacity in bytes of the buffer for reading from devices (default: 8 KiB).
+    ///
+    /// Larger buffers serve hosts sending large or frequent frames with fewer
+    /// reads, while smaller ones reduce the memory footprint of tiny agents.
+    /// Buffers still grow as needed to hold a full frame.
+    pub fn read_buffer(mut self, arg: Option<usize>) -> Self {
+        let setting = arg.unwrap_or(8 * 1024);
+        self.read_buffer = setting;
+        self
+    }
+
+    /// Number of early events to replay to the first subscriber, or zero to disable (default: disabled).
+    ///
+    /// Events from the primary channel are otherwise lost until an event
+    /// channel is requested. When enabled, the first such events (e.g. the
+    /// `api-version` and `refresh` burst after connecting) are held, and
+    /// delivered first to the earliest event channel.
+    pub fn replay_buffer(mut self, arg: Option<usize>) -> Self {
+        let setting = arg.unwrap_or(0);
+        self.replay_buffer = setting;
+        self
+    }
+
+    /// Whether to attach original frames to tagged events (default: false).
+    ///
+    /// This gives access to fields which are not modeled by typed events.
+    pub fn retain_raw_frames(mut self, arg: Option<bool>) -> Self {
+        let setting = arg.unwrap_or(false);
+        self.retain_raw_frames = setting;
+        self
+    }
+
+    /// Window for suppressing duplicate events, or zero to disable (default: disabled).
+    ///
+    /// Events with the same channel, kind and payload as one delivered within
+    /// the window (e.g. repeated `refresh` requests) are dropped.
+    pub fn dedup_window(mut self, arg: Option<Duration>) -> Self {
+        let setting = arg.unwrap_or_else(|| Duration::from_secs(0));
+        self.dedup_window = setting;
+        self
+    }
+
+    /// How event subscriptions resume after lagging behind (default: marker).
+    pub fn lag_policy(mut self, arg: Option<subscription::LagPolicy>) -> Self {
+        let setting = arg.unwrap_or_default();
+        self.lag_policy = setting;
+        self
+    }
+
+    /// Deadline for receiving an `api-version` or `refresh` event after connect,
+    /// or zero to disable (default: disabled).
+    ///
+    /// If neither event is received on the primary channel in time, `connect()`
+    /// fails. This detects misconfigured channels at startup, instead of
+    /// silently never receiving events.
+    pub fn handshake_timeout(mut self, arg: Option<Duration>) -> Self {
+        let setting = arg.unwrap_or_else(|| Duration::from_secs(0));
+        self.handshake_timeout = setting;
+        self
+    }
+
+    /// Period without any frame from the host after which it is considered silent,
+    /// or zero to disable (default: disabled).
+    ///
+    /// A silent host usually means a dead service on the other end, even
+    /// though writes keep succeeding. Only the primary channel is watched.
+    pub fn host_silence_timeout(mut self, arg: Option<Duration>) -> Self {
+        let setting = arg.unwrap_or_else(|| Duration::from_secs(0));
+        self.host_silence_timeout = setting;
+        self
+    }
+
+    /// How to react to a silent host (default: notify).
+    ///
+    /// Notifications are delivered via `OgaClient::health_chan()`.
+    pub fn host_silence_policy(mut self, arg: Option<health::SilencePolicy>) -> Self {
+        let setting = arg.unwrap_or_default();
+        self.host_silence_policy = setting;
+        self
+    }
+
+    /// Period without readable data on a channel after which it is considered
+    /// idle, or zero to disable (default: disabled).
+    ///
+    /// Unlike `host_silence_timeout()`, this applies to each channel and keeps
+    /// firing while the channel stays idle, helping tell an idle host from a
+    /// dead channel. Notifications are delivered via `OgaClient::health_chan()`.
+    pub fn read_idle_timeout(mut self, arg: Option<Duration>) -> Self {
+        let setting = arg.unwrap_or_else(|| Duration::from_secs(0));
+        self.read_idle_timeout = setting;
+        self
+    }
+
+    /// How to react to an idle channel (default: notify).
+    pub fn read_idle_policy(mut self, arg: Option<health::IdlePolicy>) -> Self {
+        let setting = arg.unwrap_or_default();
+        self.read_idle_policy = setting;
+        self
+    }
+
+    /// Names of events to silently drop, e.g. `lock-screen` (default: none).
+    pub fn ignored_events(mut self, arg: Option<Vec<String>>) -> Self {
+        let setting = arg.unwrap_or_default();
+        self.ignored_events = setting.into_iter().collect();
+        self
+    }
+
+    /// Additional VirtIO serial ports, keyed by channel label (default: none).
+    ///
+    /// Each additional port is opened and managed alongside the primary
+    /// one. Events from all channels are available as tagged events.
+    pub fn additional_channels(mut self, arg: Option<BTreeMap<String, PathBuf>>) -> Self {
+        let setting = arg.unwrap_or_default();
+        self.additional_channels = setting;
+        self
+    }
+
+    /// Hook invoked for every command written to the host (default: none).
+    ///
+    /// This can be used to keep an audit trail of guest reports.
+    /// Hooks are not part of (de)serialized settings.
+    pub fn audit_hook(mut self, arg: Option<hooks::AuditHook>) -> Self {
+        self.audit_hook = arg;
+        self
+    }
+
+    /// Middleware chain for outgoing commands, applied in order (default: none).
+    ///
+    /// This applies to all commands sent via `OgaCommandSender`, but not to
+    /// internally generated heartbeats. Middleware is not part of (de)serialized settings.
+    pub fn command_middleware(mut self, arg: Option<Vec<hooks::CommandMiddleware>>) -> Self {
+        let setting = arg.unwrap_or_default();
+        self.command_middleware = setting;
+        self
+    }
+
+    /// Middleware chain for incoming events, applied in order (default: none).
+    ///
+    /// This applies to events from all channels, before they get delivered to
+    /// any subscriber. Middleware is not part of (de)serialized settings.
+    pub fn event_middleware(mut self, arg: Option<Vec<hooks::EventMiddleware>>) -> Self {
+        let setting = arg.unwrap_or_default();
+        self.event_middleware = setting;
+        self
+    }
+
+    /// Hook invoked for every event dropped for lagging subscribers (default: none).
+    ///
+    /// This records events overwritten in full broadcast queues, or dropped
+    /// while held back by slow queued subscribers, along with a running count,
+    /// for diagnosis. Hooks are not part of (de)serialized settings.
+    pub fn dead_letter_hook(mut self, arg: Option<hooks::DeadLetterHook>) -> Self {
+        self.dead_letter_hook = arg;
+        self
+    }
+
+    /// Commands to send on connect, right after the initial heartbeat (default: none).
+    ///
+    /// These are validated and written in order before the client starts,
+    /// thus ahead of any periodic heartbeat or application command. As the
+    /// host has not announced its API version yet, commands requiring one
+    /// (see `AsFrame::min_api_version()`) make `connect()` fail.
+    /// They are not subject to command middleware, and are not part of
+    /// (de)serialized settings.
+    pub fn on_connect_send(mut self, arg: Option<Vec<Box<dyn AsFrame>>>) -> Self {
+        let setting = arg.unwrap_or_default();
+        self.on_connect = Arc::new(Mutex::new(setting));
+        self
+    }
+
+    /// Directory of an outbox to replay on connect (default: none).
+    ///
+    /// Commands spooled via `outbox::Outbox` are written in order right after
+    /// the on-connect commands, and removed from the outbox once written.
+    #[cfg(feature = "outbox")]
+    pub fn outbox_dir(mut self, arg: Option<impl AsRef<Path>>) -> Self {
+        self.outbox_dir = arg.map(|p| p.as_ref().to_path_buf());
+        self
+    }
+
+    /// Queue keeping pending commands across reconnections (default: none).
+    ///
+    /// Commands which are still queued when the client terminates, or whose
+    /// write failed before any byte reached the device, are moved here instead
+    /// of failing, and retransmitted by the next `connect()` of a builder
+    /// sharing the queue, right after any outbox replay. Commands sent after
+    /// termination fail right away. Only commands for the primary channel are
+    /// retried. Queues are not part of (de)serialized settings.
+    pub fn retry_queue(mut self, arg: Option<retry::RetryQueue>) -> Self {
+        self.retry_queue = arg;
+        self
+    }
+
+    /// Async hook invoked once the client is connected (default: none).
+    ///
+    /// This runs in a detached task, after all channels are established.
+    /// Hooks are not part of (de)serialized settings.
+    pub fn connect_hook(mut self, arg: Option<hooks::ConnectHook>) -> Self {
+        self.connect_hook = arg;
+        self
+    }
+
+    /// Async hook invoked once the client terminates, with the termination error (default: none).
+    ///
+    /// This runs in a detached task, thus it completes even if the client
+    /// gets dropped in the meantime. It does not fire when the client is
+    /// dropped while still running. Hooks are not part of (de)serialized settings.
+    pub fn disconnect_hook(mut self, arg: Option<hooks::DisconnectHook>) -> Self {
+        self.disconnect_hook = arg;
+        self
+    }
+
+    /// Time to keep retrying to open devices on permission errors,
+    /// or zero to disable (default: disabled).
+    ///
+    /// This rides out races at boot or hotplug, where an agent may start
+    /// before udev rules granting it access get applied to device nodes.
+    pub fn permission_retry(mut self, arg: Option<Duration>) -> Self {
+        let setting = arg.unwrap_or_else(|| Duration::from_secs(0));
+        self.permission_retry = setting;
+        self
+    }
+
+    /// Whether to verify device nodes before use (default: false).
+    ///
+    /// When enabled on Linux, device paths must be under /dev and are resolved
+    /// without escaping it (e.g. through crafted symlinks), and the opened node
+    /// must be a virtio-serial character device. This is recommended for
+    /// agents running with elevated privileges. It has no effect on Windows.
+    pub fn strict_device_checks(mut self, arg: Option<bool>) -> Self {
+        let setting = arg.unwrap_or(false);
+        self.strict_device_checks = setting;
+        self
+    }
+
+    /// Path to the VirtIO serial port (default: `DEFAULT_VIRTIO_PATH`).
+    pub fn device_path(mut self, arg: Option<impl AsRef<Path>>) -> Self {
+        let setting = match arg {
+            Some(p) => p.as_ref().to_path_buf(),
+            None => PathBuf::from(DEFAULT_VIRTIO_PATH),
+        };
+        self.virtio = setting;
+        self
+    }
+
+    /// Locate the VirtIO serial port by its channel name (default: `DEFAULT_CHANNEL_NAME`).
+    ///
+    /// This looks up ports by name in sysfs, and sets the device path to the
+    /// matching device node, independently of distribution-specific links.
+    #[cfg(target_os = "linux")]
+    pub fn discover(self, channel: Option<&str>) -> Result<Self, OgaError> {
+        let channel = channel.unwrap_or(DEFAULT_CHANNEL_NAME);
+        let node = crate::virtio::find_port_node(channel)
+            .ok_or_else(|| format!("no virtio-serial port found for channel '{}'", channel))?;
+        log::debug!("discovered channel '{}' at '{}'", channel, node.display());
+        Ok(self.device_path(Some(node)))
+    }
+
+    /// Connect, initialize, and return a client.
+    pub async fn connect(self) -> Result<OgaClient, OgaError> {
+        self.validate()?;
+
+        let mut dev = VirtioPort::open_with_retry(
+            &self.virtio,
+            self.permission_retry,
+            self.strict_device_checks,
+            self.connect_timeout,
+        )
+        .await?;
+        log::debug!("virtio port found at '{}'", &self.virtio.display());
+
+        if self.initial_heartbeat {
+            let heartbeat = Self::send_heartbeat(&mut dev, self.audit_hook.as_ref());
+            time::timeout(self.connect_timeout, heartbeat)
+                .await
+                .map_err(|e| format!("failed to send initial heartbeat: {}", e))??;
+            log::trace!("initial heartbeat sent");
+        }
+
+        // The host has not announced its API version yet.
+        let api_version = Arc::new(AtomicU8::new(0));
+        for (name, frame) in self.on_connect_frames(api_version.load(Ordering::Relaxed))? {
+            let write = Self::write_frame(&mut dev, &name, &frame, self.audit_hook.as_ref());
+            time::timeout(self.connect_timeout, write)
+                .await
+                .map_err(|e| format!("failed to send '{}' on connect: {}", name, e))??;
+            log::trace!("'{}' sent on connect", name);
+        }
+
+        #[cfg(feature = "outbox")]
+        if let Some(dir) = &self.outbox_dir {
+            let outbox = outbox::Outbox::open(dir)?;
+            for (path, cmd) in outbox.load()? {
+                let version = api_version.load(Ordering::Relaxed);
+                if let Err(e) = tasks::check_api_version(&cmd.name, cmd.min_api_version, version) {
+                    outbox.set_aside(&path, e);
+                    continue;
+                }
+                let write =
+                    Self::write_frame(&mut dev, &cmd.name, &cmd.frame, self.audit_hook.as_ref());
+                time::timeout(self.connect_timeout, write)
+                    .await
+                    .map_err(|e| format!("failed to replay '{}': {}", cmd.name, e))??;
+                outbox.remove(&path)?;
+                log::debug!("replayed spooled '{}' command", cmd.name);
+            }
+        }
+
+        if let Some(retry) = &self.retry_queue {
+            let mut writer = FrameWriter::new(&mut dev);
+            while let Some((cmd, chan)) = retry.pop() {
+                let version = api_version.load(Ordering::Relaxed);
+                if let Err(e) = tasks::check_api_version(&cmd.name, cmd.min_api_version, version) {
+                    log::debug!("rejected retransmission: {}", e.0);
+                    Self::audit(
+                        self.audit_hook.as_ref(),
+                        &cmd.name,
+                        &cmd.frame,
+                        Err(e.0.clone()),
+                    );
+                    let _ = chan.send(Err(e));
+                    continue;
+                }
+                let started = time::Instant::now();
+                let res = match time::timeout(self.connect_timeout, writer.write_frame(&cmd.frame))
+                    .await
+                {
+                    Ok(res) => res.map_err(|e| e.to_string()),
+                    Err(e) => Err(e.to_string()),
+                };
+                Self::audit(self.audit_hook.as_ref(), &cmd.name, &cmd.frame, res.clone());
+                if let Err(e) = res {
+                    // A torn frame cannot be retransmitted as a whole.
+                    if writer.wrote_any() {
+                        let msg = format!("failed to retransmit '{}' command: {}", cmd.name, e);
+                        let _ = chan.send(Err(OgaError::from(msg)));
+                    } else {
+                        retry.push_front((cmd, chan));
+                    }
+                    return Err(format!("failed to retransmit command: {}", e).into());
+                }
+                log::debug!("retransmitted '{}' command", cmd.name);
+                let _ = chan.send(Ok(DeliveryReceipt::new(cmd.queued_at, started)));
+            }
+        }
+
+        let mut extra_devs = BTreeMap::new();
+        for (label, path) in &self.additional_channels {
+            let extra = VirtioPort::open_with_retry(
+                path,
+                self.permission_retry,
+                self.strict_device_checks,
+                self.connect_timeout,
+            )
+            .await?;
+            log::debug!("virtio port '{}' found at '{}'", label, path.display());
+            extra_devs.insert(label.clone(), extra);
+        }
+
+        let connect_hook = self.connect_hook.clone();
+        let handshake_timeout = self.handshake_timeout;
+        let (client, handshake) = OgaClient::initialize(self, dev, extra_devs, api_version).await;
+        if let Some(events) = handshake {
+            Self::await_handshake(events, handshake_timeout).await?;
+        }
+        if let Some(hook) = connect_hook {
+            tokio::spawn(hook.call());
+        }
+        Ok(client)
+    }
+
+    /// Check configuration settings for consistency.
+    fn validate(&self) -> Result<(), OgaError> {
+        if self.commands_buffer == 0 {
+            return Err("invalid commands buffer size: 0".into());
+        }
+        if self.events_buffer == 0 {
+            return Err("invalid events buffer size: 0".into());
+        }
+        if self.read_buffer == 0 {
+            return Err("invalid read buffer size: 0".into());
+        }
+        if self.connect_timeout == Duration::from_secs(0) {
+            return Err("invalid connect timeout: 0".into());
+        }
+        #[cfg(feature = "systemd")]
+        if self.systemd_watchdog && (!self.pacemaker || self.heartbeat_secs == 0) {
+            return Err("systemd watchdog requires periodic heartbeats".into());
+        }
+        if self.additional_channels.contains_key(PRIMARY_CHANNEL) {
+            return Err(format!("reserved channel label: '{}'", PRIMARY_CHANNEL).into());
+        }
+        Ok(())
+    }
+
+    /// Wait for an `api-version` or `refresh` event from the primary channel.
+    async fn await_handshake(
+        mut events: broadcast::Receiver<crate::events::TaggedEvent>,
+        timeout: Duration,
+    ) -> Result<(), OgaError> {
+        let handshake = async {
+            loop {
+                match events.recv().await {
+                    Ok(tagged) if tagged.channel != PRIMARY_CHANNEL => continue,
+                    Ok(tagged) => match tagged.event {
+                        crate::events::Event::ApiVersion(_) | crate::events::Event::Refresh(_) => {
+                            return Ok(())
+                        }
+                        _ => continue,
+                    },
+                    Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                    Err(broadcast::error::RecvError::Closed) => {
+                        return Err(OgaError::from("client terminated during handshake"))
+                    }
+                }
+            }
+        };
+        time::timeout(timeout, handshake).await.map_err(|_| {
+            format!(
+                "no 'api-version' or 'refresh' event from host within {} seconds",
+                timeout.as_secs()
+            )
+        })?
+    }
+
+    /// Validate and encode all the commands to send on connect.
+    fn on_connect_frames(&self, api_version: u8) -> Result<Vec<(String, Bytes)>, OgaError> {
+        let cmds = self
+            .on_connect
+            .lock()
+            .map_err(|_| OgaError::from("poisoned on-connect commands"))?;
+        let mut frames = Vec::with_capacity(cmds.len());
+        for cmd in cmds.iter() {
+            cmd.validate()?;
+            tasks::check_api_version(cmd.name(), cmd.min_api_version(), api_version)?;
+            let frame = protocol::encode_frame(cmd.as_ref())?;
+            frames.push((cmd.name().to_string(), frame));
+        }
+        Ok(frames)
+    }
+
+    async fn send_heartbeat(
+        dev: &mut VirtioPort,
+        audit_hook: Option<&hooks::AuditHook>,
+    ) -> Result<(), errors::OgaError> {
+        let frame = protocol::encode_frame(&commands::Heartbeat::default())?;
+        Self::write_frame(dev, commands::names::HEARTBEAT, &frame, audit_hook).await
+    }
+
+    /// Write an encoded frame to the primary channel, before the client starts.
+    async fn write_frame(
+        dev: &mut VirtioPort,
+        name: &str,
+        frame: &[u8],
+        audit_hook: Option<&hooks::AuditHook>,
+    ) -> Result<(), errors::OgaError> {
+        let res = dev.write_all(frame).await;
+        Self::audit(
+            audit_hook,
+            name,
+            frame,
+            res.as_ref().map(|_| ()).map_err(|e| e.to_string()),
+        );
+        res.map_err(|e| e.to_string())?;
+        dev.flush().await.map_err(|e| e.to_string().into())
+    }
+
+    /// Record the outcome of a command written on connect, if auditing is enabled.
+    fn audit(
+        audit_hook: Option<&hooks::AuditHook>,
+        name: &str,
+        frame: &[u8],
+        result: Result<(), String>,
+    ) {
+        if let Some(hook) = audit_hook {
+            hook.call(&hooks::CommandRecord {
+                channel: PRIMARY_CHANNEL.to_string(),
+                name: name.to_string(),
+                size: frame.len(),
+                timestamp: std::time::SystemTime::now(),
+                result,
+            });
+        }
+    }
+}
+
+/// Client for oVirt Guest Agent protocol.
+#[derive(Debug)]
+pub struct OgaClient {
+    termination: watch::Receiver<Option<OgaError>>,
+    abortable_tasks: Vec<AbortHandle>,
+    command_middleware: Vec<hooks::CommandMiddleware>,
+    /// Sender for the client own commands, without a dedicated queue.
+    commands: OgaCommandSender,
+    from_app: mpsc::Sender<FramePlusChan>,
+    sources: tasks::SourceRegistry,
+    pings: ping::PingTracker,
+    priority: mpsc::Sender<FramePlusChan>,
+    to_app: broadcast::Sender<crate::events::Event>,
+    to_app_tagged: broadcast::Sender<crate::events::TaggedEvent>,
+    to_channels: BTreeMap<String, mpsc::Sender<FramePlusChan>>,
+    queued_subscribers: Arc<Mutex<Vec<mpsc::Sender<crate::events::TaggedEvent>>>>,
+    events_buffer: usize,
+    lag_policy: subscription::LagPolicy,
+    to_manager: mpsc::WeakSender<FramePlusChan>,
+    from_manager: mpsc::WeakSender<tasks::IncomingEvent>,
+    gauges: Arc<stats::Gauges>,
+    health: broadcast::Sender<health::HealthEvent>,
+    diagnostics: broadcast::Sender<protocol::TaggedDiagnostic>,
+    early_events: Arc<subscription::ReplayBuffer<crate::events::Event>>,
+}
+
+impl OgaClient {
+    /// Return a client builder with default configuration settings.
+    pub fn builder() -> OgaBuilder {
+        OgaBuilder::default()
+    }
+
+    /// Initialize and run a client.
+    ///
+    /// This internally starts the following tasks:
+    ///  * Pacemaker  - heartbeat generator (optional).
+    ///  * Manager    - socket manager towards the hypervisor service.
+    ///  * Dispatcher - channel handler towards library consumers.
+    ///  * Watchdog   - host-silence detector (optional).
+    ///  * Runner     - top-level umbrella and client engine.
+    async fn initialize(
+        builder: OgaBuilder,
+        dev: VirtioPort,
+        extra_devs: BTreeMap<String, VirtioPort>,
+        api_version: Arc<AtomicU8>,
+    ) -> (
+        Self,
+        Option<broadcast::Receiver<crate::events::TaggedEvent>>,
+    ) {
+        let (runner_abort, runner_reg) = futures::future::AbortHandle::new_pair();
+
+        // Channels.
+        let termination_chan = watch::channel(None);
+        let from_app_chan = mpsc::channel(builder.commands_buffer);
+        let to_manager_chan = mpsc::channel(builder.commands_buffer);
+        let priority_chan = mpsc::channel(builder.commands_buffer);
+        let from_manager_chan = mpsc::channel(builder.events_buffer);
+        let to_manager_weak = to_manager_chan.0.downgrade();
+        let last_frame_chan = watch::channel(time::Instant::now());
+        let health_chan = {
+            let bcast = broadcast::channel(builder.events_buffer);
+            drop(bcast.1);
+            bcast.0
+        };
+        let diagnostics_chan = {
+            let bcast = broadcast::channel(builder.events_buffer);
+            drop(bcast.1);
+            bcast.0
+        };
+        let from_manager_weak = from_manager_chan.0.downgrade();
+        let to_app_chan = {
+            let bcast = broadcast::channel(builder.events_buffer);
+            drop(bcast.1);
+            bcast.0
+        };
+        let to_app_tagged_chan = {
+            let bcast = broadcast::channel(builder.events_buffer);
+            drop(bcast.1);
+            bcast.0
+        };
+
+        let queued_subscribers = Arc::new(Mutex::new(Vec::new()));
+        let dead_letters = builder
+            .dead_letter_hook
+            .clone()
+            .map(hooks::DeadLetterSink::new);
+        let gauges = stats::Gauges::shared();
+        let pings = ping::PingTracker::default();
+        let early_events = Arc::new(subscription::ReplayBuffer::new(builder.replay_buffer));
+        let (sources, command_sources) =
+            tasks::command_sources(&from_app_chan.0, from_app_chan.1, builder.commands_buffer);
+        let (dispatcher, dispatcher_abort) = tasks::DispatcherTask::new(
+            command_sources,
+            from_manager_chan.1,
+            to_app_chan.clone(),
+            to_app_tagged_chan.clone(),
+            to_manager_chan.0.clone(),
+            tasks::DispatcherSettings {
+                events_buffer: builder.events_buffer,
+                middleware: builder.event_middleware.clone(),
+                dedup_window: builder.dedup_window,
+                dead_letters: dead_letters.clone(),
+                queued_subscribers: queued_subscribers.clone(),
+                retry_queue: builder.retry_queue.clone(),
+                gauges: gauges.clone(),
+                pings: pings.clone(),
+                early_events: early_events.clone(),
+                diagnostics: Some(diagnostics_chan.clone()),
+            },
+        );
+        let settings = tasks::ManagerSettings {
+            channel: PRIMARY_CHANNEL.to_string(),
+            codec: raw::LazyCodec::new(
+                raw::OgaCodec::new()
+                    .utf8_policy(builder.invalid_utf8)
+                    .max_decode_failures(builder.max_decode_failures),
+            ),
+            ignored_events: builder.ignored_events.clone(),
+            retain_raw: builder.retain_raw_frames,
+            read_buffer: builder.read_buffer,
+            backlog: builder.events_buffer,
+            dead_letters,
+            queued_subscribers: queued_subscribers.clone(),
+            write_timeout: builder.write_timeout,
+            read_idle_timeout: builder.read_idle_timeout,
+            read_idle_policy: builder.read_idle_policy,
+            pings: Some(pings.clone()),
+            api_version,
+            health: Some(health_chan.clone()),
+            diagnostics: Some(diagnostics_chan.clone()),
+            audit_hook: builder.audit_hook.clone(),
+            retry_queue: builder.retry_queue.clone(),
+            gauges: Some(gauges.clone()),
+            last_frame: Some(Arc::new(last_frame_chan.0)),
+        };
+        let (manager, manager_abort) = tasks::ManagerTask::new(
+            dev,
+            settings.clone(),
+            to_manager_chan.1,
+            Some(priority_chan.1),
+            from_manager_chan.0.clone(),
+        );
+        let mut abortable_tasks = vec![dispatcher_abort, manager_abort, runner_abort];
+
+        // Additional channels, each one with its own manager.
+        let mut extra_managers = Vec::with_capacity(extra_devs.len());
+        let mut to_channels = BTreeMap::new();
+        for (label, extra_dev) in extra_devs {
+            let to_extra_chan = mpsc::channel(builder.commands_buffer);
+            let extra_settings = tasks::ManagerSettings {
+                channel: label.clone(),
+                pings: None,
+                retry_queue: None,
+                gauges: None,
+                last_frame: None,
+                ..settings.clone()
+            };
+            let (extra_manager, extra_abort) = tasks::ManagerTask::new(
+                extra_dev,
+                extra_settings,
+                to_extra_chan.1,
+                None,
+                from_manager_chan.0.clone(),
+            );
+            abortable_tasks.push(extra_abort);
+            extra_managers.push(extra_manager);
+            to_channels.insert(label, to_extra_chan.0);
+        }
+
+        let pulse_chan = watch::channel(time::Instant::now());
+        let pacemaker = if builder.pacemaker {
+            let (pacemaker, pacemaker_abort) =
+                tasks::PacemakerTask::new(to_manager_chan.0, builder.heartbeat_secs, pulse_chan.0);
+            abortable_tasks.push(pacemaker_abort);
+            Some(pacemaker)
+        } else {
+            None
+        };
+
+        let watchdog = if builder.host_silence_timeout > Duration::from_secs(0) {
+            let (watchdog, watchdog_abort) = tasks::WatchdogTask::new(
+                last_frame_chan.1,
+                builder.host_silence_timeout,
+                builder.host_silence_policy,
+                health_chan.clone(),
+            );
+            abortable_tasks.push(watchdog_abort);
+            Some(watchdog)
+        } else {
+            None
+        };
+
+        #[cfg(feature = "systemd")]
+        match builder.notify_ready {
+            systemd::NotifyReady::Disabled => {}
+            systemd::NotifyReady::OnConnect => systemd::notify_ready(),
+            systemd::NotifyReady::OnFirstEvent => {
+                let (notifier_abort, notifier_reg) = AbortHandle::new_pair();
+                let mut events = to_app_tagged_chan.subscribe();
+                let notifier = async move {
+                    if let Err(broadcast::error::RecvError::Closed) = events.recv().await {
+                        return;
+                    }
+                    systemd::notify_ready();
+                };
+                tokio::spawn(futures::future::Abortable::new(notifier, notifier_reg));
+                abortable_tasks.push(notifier_abort);
+            }
+        }
+
+        #[cfg(feature = "systemd")]
+        if builder.systemd_watchdog {
+            match systemd::watchdog_timeout() {
+                Some(timeout) => {
+                    let (watchdog_abort, watchdog_reg) = AbortHandle::new_pair();
+                    let max_silence = Duration::from_secs(2 * u64::from(builder.heartbeat_secs));
+                    let watchdog = systemd::run_watchdog(pulse_chan.1, timeout, max_silence);
+                    tokio::spawn(futures::future::Abortable::new(watchdog, watchdog_reg));
+                    abortable_tasks.push(watchdog_abort);
+                }
+                None => log::debug!("no systemd watchdog configured, skipped keepalives"),
+            }
+        }
+
+        // Internal sender on the base queue, shared by flushes and pings.
+        let commands = OgaCommandSender {
+            from_app: from_app_chan.0.clone(),
+            sources: Some(sources.clone()),
+            pings: Some(pings.clone()),
+            priority: Some(priority_chan.0.clone()),
+            middleware: builder.command_middleware.clone(),
+        };
+        let client = Self {
+            termination: termination_chan.1,
+            abortable_tasks,
+            command_middleware: builder.command_middleware.clone(),
+            commands,
+            from_app: from_app_chan.0,
+            sources,
+            pings,
+            priority: priority_chan.0,
+            to_app: to_app_chan,
+            to_app_tagged: to_app_tagged_chan,
+            to_channels,
+            queued_subscribers,
+            events_buffer: builder.events_buffer,
+            lag_policy: builder.lag_policy,
+            to_manager: to_manager_weak,
+            from_manager: from_manager_weak,
+            gauges,
+            health: health_chan,
+            diagnostics: diagnostics_chan,
+            early_events,
+        };
+
+        // Subscribe before tasks start, in order not to miss early events.
+        let handshake = if builder.handshake_timeout > Duration::from_secs(0) {
+            Some(client.to_app_tagged.subscribe())
+        } else {
+            None
+        };
+
+        tokio::spawn({
+            let inner = Self::run_tasks(
+                termination_chan.0,
+                builder.disconnect_hook.clone(),
+                manager,
+                extra_managers,
+                pacemaker,
+                watchdog,
+                dispatcher,
+            );
+            futures::future::Abortable::new(inner, runner_reg)
+        });
+        (client, handshake)
+    }
+
+    /// Run all internal tasks.
+    async fn run_tasks(
+        err_chan: watch::Sender<Option<OgaError>>,
+        disconnect_hook: Option<hooks::DisconnectHook>,
+        manager: tasks::ManagerTask,
+        extra_managers: Vec<tasks::ManagerTask>,
+        pacemaker: Option<tasks::PacemakerTask>,
+        watchdog: Option<tasks::WatchdogTask>,
+        dispatcher: tasks::DispatcherTask,
+    ) {
+        // Manager.
+        let manager_task = tokio::spawn(manager.run())
+            .map_ok_or_else(|_| OgaError::from("manager task failed"), |e| e);
+
+        // Managers for additional channels (optional).
+        let extra_managers_task = if extra_managers.is_empty() {
+            future::pending().left_future()
+        } else {
+            let tasks = extra_managers.into_iter().map(|task| {
+                tokio::spawn(task.run())
+                    .map_ok_or_else(|_| OgaError::from("manager task failed"), |e| e)
+            });
+            future::select_all(tasks).map(|(e, _, _)| e).right_future()
+        };
+
+        // Pacemaker (optional).
+        let pacemaker_task = match pacemaker {
+            Some(task) => tokio::spawn(task.run())
+                .map_ok_or_else(|_| OgaError::from("pacemaker task failed"), |e| e)
+                .left_future(),
+            None => future::pending().right_future(),
+        };
+
+        // Host-silence watchdog (optional).
+        let watchdog_task = match watchdog {
+            Some(task) => tokio::spawn(task.run())
+                .map_ok_or_else(|_| OgaError::from("watchdog task failed"), |e| e)
+                .left_future(),
+            None => future::pending().right_future(),
+        };
+
+        // Dispatcher.
+        let dispatcher_task = tokio::spawn(dispatcher.run())
+            .map_ok_or_else(|_| OgaError::from("service task failed"), |e| e);
+
+        let err = tokio::select! {
+            ret = dispatcher_task => { ret },
+            ret = manager_task => { ret },
+            ret = extra_managers_task => { ret },
+            ret = pacemaker_task => { ret },
+            ret = watchdog_task => { ret },
+        };
+
+        // Forward termination failure to the application.
+        log::debug!("client terminated: {}", err);
+        if let Some(hook) = disconnect_hook {
+            tokio::spawn(hook.call(err.clone()));
+        }
+        err_chan.send_replace(Some(err));
+    }
+
+    /// Return a lightweight, cloneable handle to this client.
+    ///
+    /// Handles do not keep the client alive: once the client is dropped,
+    /// commands fail and subscriptions end.
+    pub fn handle(&self) -> OgaHandle {
+        OgaHandle {
+            commands: self.command_chan(),
+            to_app: self.to_app.downgrade(),
+            to_app_tagged: self.to_app_tagged.downgrade(),
+            early_events: self.early_events.clone(),
+            lag_policy: self.lag_policy,
+            termination: self.termination_chan(),
+        }
+    }
+
+    /// Wait until all commands queued so far have been written, or a deadline elapses.
+    ///
+    /// This allows sequencing "report everything, then exit". Only the
+    /// primary channel is covered.
+    pub async fn flush_with_deadline(&self, deadline: time::Instant) -> Result<(), OgaError> {
+        self.commands.flush_with_deadline(deadline).await
+    }
+
+    /// Probe the host with an `echo` command, returning the round-trip time.
+    ///
+    /// See `OgaCommandSender::ping()` for details.
+    pub async fn ping(&self, timeout: Duration) -> Result<Duration, OgaError> {
+        self.commands.ping(timeout).await
+    }
+
+    /// Return a channel (write-half) for sending guest commands.
+    ///
+    /// Each returned sender, as well as each clone of it, gets its own queue;
+    /// queues are served in turn, so that a busy sender cannot starve others.
+    pub fn command_chan(&self) -> OgaCommandSender {
+        let from_app = self
+            .sources
+            .register()
+            .unwrap_or_else(|| self.from_app.clone());
+        OgaCommandSender {
+            from_app,
+            sources: Some(self.sources.clone()),
+            pings: Some(self.pings.clone()),
+            priority: Some(self.priority.clone()),
+            middleware: self.command_middleware.clone(),
+        }
+    }
+
+    /// Return a channel (read-half) for receiving events from the host.
+    pub fn event_chan(&self) -> subscription::EventReceiver<crate::events::Event> {
+        self.early_events.subscribe(&self.to_app, self.lag_policy)
+    }
+
+    /// Return a channel (write-half) for sending guest commands on an additional channel.
+    ///
+    /// This returns `None` if no additional channel with the given label exists.
+    pub fn channel_command_chan(&self, label: &str) -> Option<OgaCommandSender> {
+        let from_app = self.to_channels.get(label)?.clone();
+        Some(OgaCommandSender {
+            from_app,
+            sources: None,
+            pings: None,
+            priority: None,
+            middleware: self.command_middleware.clone(),
+        })
+    }
+
+    /// Return a channel (read-half) for receiving events from all channels, tagged by channel.
+    ///
+    /// Events from the primary channel are tagged with `PRIMARY_CHANNEL`.
+    pub fn tagged_event_chan(&self) -> subscription::EventReceiver<crate::events::TaggedEvent> {
+        subscription::EventReceiver::new(self.to_app_tagged.subscribe(), self.lag_policy)
+    }
+
+    /// Return a channel (read-half) for receiving health notifications.
+    ///
+    /// Besides partial frames discarded at stream start, these are only
+    /// generated if a host-silence, write or read-idle timeout is configured.
+    pub fn health_chan(&self) -> subscription::EventReceiver<health::HealthEvent> {
+        subscription::EventReceiver::new(self.health.subscribe(), self.lag_policy)
+    }
+
+    /// Return a channel (read-half) for receiving diagnostics about malformed incoming frames.
+    ///
+    /// Frames which are skipped or repaired while decoding are reported here,
+    /// tagged by channel, instead of being logged as warnings.
+    pub fn diagnostics_chan(&self) -> subscription::EventReceiver<protocol::TaggedDiagnostic> {
+        subscription::EventReceiver::new(self.diagnostics.subscribe(), self.lag_policy)
+    }
+
+    /// Return a dedicated queue (read-half) for receiving events from all channels, tagged by channel.
+    ///
+    /// Unlike broadcast subscriptions, events are not dropped for this
+    /// subscriber: once its queue is full, event delivery to all subscribers
+    /// waits until it catches up. Meanwhile up to `events_buffer` events per
+    /// channel are held back; beyond that, devices are not read anymore until
+    /// the subscriber catches up, leaving the host to block on writing.
+    /// Events received before subscribing are not delivered.
+    pub fn queued_event_chan(&self) -> mpsc::Receiver<crate::events::TaggedEvent> {
+        queued_subscribe(&self.queued_subscribers, self.events_buffer)
+    }
+
+    /// Return a channel (read-half) for receiving termination event notifications.
+    pub fn termination_chan(&self) -> TerminationReceiver {
+        TerminationReceiver {
+            inner: self.termination.clone(),
+        }
+    }
+
+    /// Split this client into independently owned command and event halves.
+    ///
+    /// Each half can be moved into a different application task. Internal
+    /// tasks keep running until both halves are dropped.
+    pub fn into_split(mut self) -> (OgaCommandHalf, OgaEventHalf) {
+        let mut senders = vec![self.from_app.clone(), self.priority.clone()];
+        senders.extend(self.to_channels.values().cloned());
+        let tasks = Arc::new(TaskGuard {
+            tasks: std::mem::take(&mut self.abortable_tasks),
+            _senders: senders,
+        });
+        let channels = self
+            .to_channels
+            .keys()
+            .filter_map(|label| Some((label.clone(), self.channel_command_chan(label)?)))
+            .collect();
+        let commands = OgaCommandHalf {
+            commands: self.command_chan(),
+            channels,
+            termination: self.termination_chan(),
+            _tasks: tasks.clone(),
+        };
+        let events = OgaEventHalf {
+            to_app: self.to_app.clone(),
+            to_app_tagged: self.to_app_tagged.clone(),
+            health: self.health.clone(),
+            diagnostics: self.diagnostics.clone(),
+            early_events: self.early_events.clone(),
+            queued_subscribers: self.queued_subscribers.clone(),
+            events_buffer: self.events_buffer,
+            lag_policy: self.lag_policy,
+            termination: self.termination_chan(),
+            _tasks: tasks,
+        };
+        (commands, events)
+    }
+
+    /// Return a snapshot of client statistics, including queue fill levels.
+    pub fn stats(&self) -> stats::ClientStats {
+        fn mpsc_depth<T>(chan: &mpsc::Sender<T>) -> usize {
+            chan.max_capacity() - chan.capacity()
+        }
+
+        let outgoing = self.to_manager.upgrade();
+        let incoming = self.from_manager.upgrade();
+        stats::ClientStats {
+            commands: self
+                .gauges
+                .commands
+                .snapshot(self.from_app.max_capacity(), self.sources.max_depth()),
+            outgoing: self.gauges.outgoing.snapshot(
+                outgoing.as_ref().map_or(0, |c| c.max_capacity()),
+                outgoing.as_ref().map_or(0, mpsc_depth),
+            ),
+            incoming: self.gauges.incoming.snapshot(
+                incoming.as_ref().map_or(0, |c| c.max_capacity()),
+                incoming.as_ref().map_or(0, mpsc_depth),
+            ),
+            events: self
+                .gauges
+                .events
+                .snapshot(self.events_buffer, self.to_app.len()),
+            tagged_events: self
+                .gauges
+                .tagged_events
+                .snapshot(self.events_buffer, self.to_app_tagged.len()),
+        }
+    }
+}
+
+impl Drop for OgaClient {
+    fn drop(&mut self) {
+        for task in &self.abortable_tasks {
+            task.abort()
+        }
+    }
+}
+
+/// Cloneable handle to a client, for sharing among application tasks.
+///
+/// This bundles a command sender, an event-subscription factory and a
+/// termination watcher.
+#[derive(Clone, Debug)]
+pub struct OgaHandle {
+    commands: OgaCommandSender,
+    to_app: broadcast::WeakSender<crate::events::Event>,
+    to_app_tagged: broadcast::WeakSender<crate::events::TaggedEvent>,
+    early_events: Arc<subscription::ReplayBuffer<crate::events::Event>>,
+    lag_policy: subscription::LagPolicy,
+    termination: TerminationReceiver,
+}
+
+impl OgaHandle {
+    /// Send a command to the host.
+    pub async fn send(&self, cmd: Box<dyn commands::AsFrame>) -> Result<(), OgaError> {
+        self.commands.send(cmd).await
+    }
+
+    /// Wait until all commands queued so far have been written, or a deadline elapses.
+    pub async fn flush_with_deadline(&self, deadline: time::Instant) -> Result<(), OgaError> {
+        self.commands.flush_with_deadline(deadline).await
+    }
+
+    /// Probe the host with an `echo` command, returning the round-trip time.
+    pub async fn ping(&self, timeout: Duration) -> Result<Duration, OgaError> {
+        self.commands.ping(timeout).await
+    }
+
+    /// Return a channel (write-half) for sending guest commands.
+    pub fn command_chan(&self) -> OgaCommandSender {
+        self.commands.clone()
+    }
+
+    /// Return a channel (read-half) for receiving events from the host.
+    pub fn event_chan(&self) -> subscription::EventReceiver<crate::events::Event> {
+        match self.to_app.upgrade() {
+            Some(sender) => self.early_events.subscribe(&sender, self.lag_policy),
+            None => subscription::EventReceiver::new(broadcast::channel(1).1, self.lag_policy),
+        }
+    }
+
+    /// Return a channel (read-half) for receiving events from all channels, tagged by channel.
+    pub fn tagged_event_chan(&self) -> subscription::EventReceiver<crate::events::TaggedEvent> {
+        subscription::EventReceiver::new(Self::subscribe(&self.to_app_tagged), self.lag_policy)
+    }
+
+    /// Return a channel (read-half) for receiving termination event notifications.
+    pub fn termination_chan(&self) -> TerminationReceiver {
+        self.termination.clone()
+    }
+
+    /// Subscribe to a broadcast channel, or return a closed receiver if the client is gone.
+    fn subscribe<T: Clone>(weak: &broadcast::WeakSender<T>) -> broadcast::Receiver<T> {
+        match weak.upgrade() {
+            Some(sender) => sender.subscribe(),
+            None => broadcast::channel(1).1,
+        }
+    }
+}
+
+/// Register a queued subscriber, returning its receiving end.
+fn queued_subscribe(
+    subscribers: &Mutex<Vec<mpsc::Sender<crate::events::TaggedEvent>>>,
+    events_buffer: usize,
+) -> mpsc::Receiver<crate::events::TaggedEvent> {
+    let (tx, rx) = mpsc::channel(events_buffer);
+    if let Ok(mut subs) = subscribers.lock() {
+        subs.push(tx);
+    }
+    rx
+}
+
+/// Internal tasks of a split client, aborted once all halves are dropped.
+#[derive(Debug)]
+struct TaskGuard {
+    tasks: Vec<AbortHandle>,
+    /// Command queues, kept open for as long as the client is.
+    _senders: Vec<mpsc::Sender<FramePlusChan>>,
+}
+
+impl Drop for TaskGuard {
+    fn drop(&mut self) {
+        for task in &self.tasks {
+            task.abort()
+        }
+    }
+}
+
+/// Command half of a split client, see `OgaClient::into_split()`.
+#[derive(Debug)]
+pub struct OgaCommandHalf {
+    commands: OgaCommandSender,
+    channels: BTreeMap<String, OgaCommandSender>,
+    termination: TerminationReceiver,
+    _tasks: Arc<TaskGuard>,
+}
+
+impl OgaCommandHalf {
+    /// Send a command to the host.
+    pub async fn send(&self, cmd: Box<dyn commands::AsFrame>) -> Result<(), OgaError> {
+        self.commands.send(cmd).await
+    }
+
+    /// Wait until all commands queued so far have been written, or a deadline elapses.
+    pub async fn flush_with_deadline(&self, deadline: time::Instant) -> Result<(), OgaError> {
+        self.commands.flush_with_deadline(deadline).await
+    }
+
+    /// Probe the host with an `echo` command, returning the round-trip time.
+    pub async fn ping(&self, timeout: Duration) -> Result<Duration, OgaError> {
+        self.commands.ping(timeout).await
+    }
+
+    /// Return a channel (write-half) for sending guest commands.
+    pub fn command_chan(&self) -> OgaCommandSender {
+        self.commands.clone()
+    }
+
+    /// Return a channel (write-half) for sending guest commands on an additional channel.
+    ///
+    /// This returns `None` if no additional channel with the given label exists.
+    pub fn channel_command_chan(&self, label: &str) -> Option<OgaCommandSender> {
+        self.channels.get(label).cloned()
+    }
+
+    /// Return a channel (read-half) for receiving termination event notifications.
+    pub fn termination_chan(&self) -> TerminationReceiver {
+        self.termination.clone()
+    }
+}
+
+/// Event half of a split client, see `OgaClient::into_split()`.
+#[derive(Debug)]
+pub struct OgaEventHalf {
+    to_app: broadcast::Sender<crate::events::Event>,
+    to_app_tagged: broadcast::Sender<crate::events::TaggedEvent>,
+    health: broadcast::Sender<health::HealthEvent>,
+    diagnostics: broadcast::Sender<protocol::TaggedDiagnostic>,
+    early_events: Arc<subscription::ReplayBuffer<crate::events::Event>>,
+    queued_subscribers: Arc<Mutex<Vec<mpsc::Sender<crate::events::TaggedEvent>>>>,
+    events_buffer: usize,
+    lag_policy: subscription::LagPolicy,
+    termination: TerminationReceiver,
+    _tasks: Arc<TaskGuard>,
+}
+
+impl OgaEventHalf {
+    /// Return a channel (read-half) for receiving events from the host.
+    pub fn event_chan(&self) -> subscription::EventReceiver<crate::events::Event> {
+        self.early_events.subscribe(&self.to_app, self.lag_policy)
+    }
+
+    /// Return a channel (read-half) for receiving events from all channels, tagged by channel.
+    pub fn tagged_event_chan(&self) -> subscription::EventReceiver<crate::events::TaggedEvent> {
+        subscription::EventReceiver::new(self.to_app_tagged.subscribe(), self.lag_policy)
+    }
+
+    /// Return a channel (read-half) for receiving health notifications.
+    pub fn health_chan(&self) -> subscription::EventReceiver<health::HealthEvent> {
+        subscription::EventReceiver::new(self.health.subscribe(), self.lag_policy)
+    }
+
+    /// Return a channel (read-half) for receiving diagnostics about malformed incoming frames.
+    ///
+    /// Frames which are skipped or repaired while decoding are reported here,
+    /// tagged by channel, instead of being logged as warnings.
+    pub fn diagnostics_chan(&self) -> subscription::EventReceiver<protocol::TaggedDiagnostic> {
+        subscription::EventReceiver::new(self.diagnostics.subscribe(), self.lag_policy)
+    }
+
+    /// Return a dedicated queue (read-half) for receiving events from all channels, tagged by channel.
+    ///
+    /// See `OgaClient::queued_event_chan()` for delivery semantics.
+    pub fn queued_event_chan(&self) -> mpsc::Receiver<crate::events::TaggedEvent> {
+        queued_subscribe(&self.queued_subscribers, self.events_buffer)
+    }
+
+    /// Return a channel (read-half) for receiving termination event notifications.
+    pub fn termination_chan(&self) -> TerminationReceiver {
+        self.termination.clone()
+    }
+}
+
+/// Channel for receiving the termination event of a client.
+///
+/// This can be cloned, and all clones observe the same termination event.
+#[derive(Clone, Debug)]
+pub struct TerminationReceiver {
+    inner: watch::Receiver<Option<OgaError>>,
+}
+
+impl TerminationReceiver {
+    /// Wait for the client to terminate, returning the failure which caused it.
+    pub async fn recv(&mut self) -> OgaError {
+        match self.inner.wait_for(Option::is_some).await {
+            Ok(err) => err
+                .clone()
+                .unwrap_or_else(|| "termination event, unknown error".into()),
+            Err(_) => "termination event, sender aborted".into(),
+        }
+    }
+}
+
+#[derive(Debug)]
+/// Channel for sending commands to the host.
+///
+/// On the primary channel, each sender (including each clone) has its own
+/// queue, and the client takes commands from all queues in round-robin order.
+/// Commands from a single sender are written in the order they were queued,
+/// while a sender with a large backlog only delays others by one command per
+/// round. Heartbeats from the built-in pacemaker bypass these queues.
+pub struct OgaCommandSender {
+    from_app: mpsc::Sender<FramePlusChan>,
+    /// Registry for per-sender queues, on the primary channel only.
+    sources: Option<tasks::SourceRegistry>,
+    /// Pending echo probes, on the primary channel only.
+    pings: Option<ping::PingTracker>,
+    priority: Option<mpsc::Sender<FramePlusChan>>,
+    middleware: Vec<hooks::CommandMiddleware>,
+}
+
+impl Clone for OgaCommandSender {
+    fn clone(&self) -> Self {
+        let from_app = self
+            .sources
+            .as_ref()
+            .and_then(tasks::SourceRegistry::register)
+            .unwrap_or_else(|| self.from_app.clone());
+        Self {
+            from_app,
+            sources: self.sources.clone(),
+            pings: self.pings.clone(),
+            priority: self.priority.clone(),
+            middleware: self.middleware.clone(),
+        }
+    }
+}
+
+impl OgaCommandSender {
+    /// Send a command to the host.
+    ///
+    /// Commands are first passed through the middleware chain; commands
+    /// dropped by middleware are not sent, without failing. Commands failing
+    /// validation or encoding are rejected before getting queued.
+    pub async fn send(&self, cmd: Box<dyn commands::AsFrame>) -> Result<(), OgaError> {
+        self.send_with_receipt(cmd).await.map(|_| ())
+    }
+
+    /// Send a command to the host, returning a receipt with delivery timings.
+    ///
+    /// This behaves like `send()`, but also reports when the frame was written
+    /// and how long it spent queued. This returns `None` for commands dropped
+    /// by middleware.
+    pub async fn send_with_receipt(
+        &self,
+        cmd: Box<dyn commands::AsFrame>,
+    ) -> Result<Option<DeliveryReceipt>, OgaError> {
+        let cmd = match self.prepare(cmd).await? {
+            Some(cmd) => cmd,
+            None => return Ok(None),
+        };
+        let err_chan = oneshot::channel();
+        if self.from_app.send((cmd, err_chan.0)).await.is_err() {
+            // Only commands pending at termination wait for retransmission.
+            return Err(OgaError::from("client terminated"));
+        }
+        let receipt = err_chan
+            .1
+            .await
+            .map_err(|e| OgaError::from(e.to_string()))??;
+        Ok(Some(receipt))
+    }
+
+    /// Send a command to the host with priority, failing if it is not written before a deadline.
+    ///
+    /// This is meant for time-critical notifications (e.g. `session-shutdown`
+    /// during poweroff): on the primary channel, the command skips ahead of
+    /// all queued commands, and is written and flushed as soon as the current
+    /// write completes. A successful result means that the frame was written
+    /// to the device in time.
+    pub async fn send_with_deadline(
+        &self,
+        cmd: Box<dyn commands::AsFrame>,
+        deadline: time::Instant,
+    ) -> Result<(), OgaError> {
+        let cmd = match self.prepare(cmd).await? {
+            Some(cmd) => cmd,
+            None => return Ok(()),
+        };
+        let name = cmd.name.clone();
+        let chan = self.priority.as_ref().unwrap_or(&self.from_app);
+        let delivery = async {
+            let err_chan = oneshot::channel();
+            chan.send((cmd, err_chan.0))
+                .await
+                .map_err(|e| OgaError::from(e.to_string()))?;
+            err_chan
+                .1
+                .await
+                .map_err(|e| OgaError::from(e.to_string()))?
+        };
+        time::timeout_at(deadline, delivery)
+            .await
+            .map_err(|_| format!("deadline elapsed before '{}' command was written", name))?
+            .map(|_| ())
+    }
+
+    /// Wait until all commands queued so far have been written, or a deadline elapses.
+    ///
+    /// This queues an empty marker as a barrier behind pending commands, and
+    /// waits for it to be acknowledged in order; nothing is written to the host.
+    /// On the primary channel, this covers commands queued by all senders.
+    /// Priority commands and commands on other channels are not covered.
+    pub async fn flush_with_deadline(&self, deadline: time::Instant) -> Result<(), OgaError> {
+        let barrier = EncodedCommand::marker();
+        let delivery = async {
+            let err_chan = oneshot::channel();
+            match &self.sources {
+                Some(sources) => sources.flush((barrier, err_chan.0)).await?,
+                None => self
+                    .from_app
+                    .send((barrier, err_chan.0))
+                    .await
+                    .map_err(|e| OgaError::from(e.to_string()))?,
+            }
+            err_chan
+                .1
+                .await
+                .map_err(|e| OgaError::from(e.to_string()))?
+        };
+        time::timeout_at(deadline, delivery)
+            .await
+            .map_err(|_| OgaError::from("deadline elapsed before queued commands were written"))?
+            .map(|_| ())
+    }
+
+    /// Probe the host with an `echo` command, returning the round-trip time.
+    ///
+    /// Each probe carries its own identifier, and its answer is consumed by
+    /// the client instead of being delivered as an event, so that concurrent
+    /// pings each get their own measurement. Time spent in local queues is
+    /// not accounted. This is only available on the primary channel.
+    pub async fn ping(&self, timeout: Duration) -> Result<Duration, OgaError> {
+        let pings = self.pings.as_ref().ok_or_else(|| {
+            OgaError::from("echo probes are only supported on the primary channel")
+        })?;
+        let (id, answer) = pings.register();
+        let probe = commands::EchoProbe { id: id.clone() };
+        let sent = time::Instant::now();
+        let round_trip = async {
+            let receipt = self
+                .send_with_receipt(Box::new(probe))
+                .await?
+                .ok_or_else(|| OgaError::from("echo probe dropped by middleware"))?;
+            let answered = answer.await.map_err(|e| OgaError::from(e.to_string()))?;
+            Ok(answered.saturating_duration_since(sent + receipt.queued))
+        };
+        let res = time::timeout(timeout, round_trip).await;
+        pings.cancel(&id);
+        res.map_err(|_| format!("no answer to echo probe '{}' within {:?}", id, timeout))?
+    }
+
+    /// Whether the client is gone, thus no further commands can be sent.
+    #[cfg(feature = "guestinfo")]
+    pub(crate) fn is_closed(&self) -> bool {
+        self.from_app.is_closed()
+    }
+
+    /// Pass a command through the middleware chain, then validate and encode it.
+    ///
+    /// This returns `None` if the command was dropped by middleware.
+    async fn prepare(
+        &self,
+        cmd: Box<dyn commands::AsFrame>,
+    ) -> Result<Option<EncodedCommand>, OgaError> {
+        let mut cmd = cmd;
+        for middleware in &self.middleware {
+            let name = cmd.name().to_string();
+            cmd = match middleware.call(cmd).await {
+                Some(cmd) => cmd,
+                None => {
+                    log::debug!("'{}' command dropped by middleware", name);
+                    return Ok(None);
+                }
+            };
+        }
+
+        cmd.validate()?;
+        let encoded = EncodedCommand::encode(cmd.as_ref())?;
+        Ok(Some(encoded))
+    }
+}
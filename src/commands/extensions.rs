@@ -0,0 +1,66 @@
+/*! Non-standard commands.
+
+These commands are not part of the oVirt guest agent protocol, and are
+not recognized by VDSM: stock hosts log and discard them. They are only
+meant for hosts which have been explicitly extended to handle them, and
+are never sent unless requested by the application.
+*/
+
+use super::{json_frame, write_json, AsFrame, ValidationError};
+use crate::errors::OgaError;
+use bytes::{BufMut, Bytes};
+use serde::Serialize;
+
+/// Protocol names of non-standard commands.
+pub mod names {
+    /// Logged-in users.
+    pub const LOGGED_IN_USERS: &str = "logged-in-users";
+}
+
+/// Logged-in users (non-standard).
+///
+/// This complements `active-user` with the full list of users logged into
+/// the guest. It is an extension not known to VDSM; see the module docs.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash, Serialize)]
+#[serde(tag = "__name__")]
+#[serde(rename(serialize = "logged-in-users"))]
+pub struct LoggedInUsers {
+    pub users: Vec<LoggedInUser>,
+}
+
+impl AsFrame for LoggedInUsers {
+    fn as_frame(&self) -> Result<Bytes, OgaError> {
+        json_frame(self)
+    }
+
+    fn write_frame(&self, buf: &mut dyn BufMut) -> Result<(), OgaError> {
+        write_json(buf, self)
+    }
+
+    fn name(&self) -> &str {
+        names::LOGGED_IN_USERS
+    }
+
+    fn validate(&self) -> Result<(), ValidationError> {
+        if self.users.iter().any(|u| u.name.is_empty()) {
+            return Err(ValidationError::new(self.name(), "empty user name"));
+        }
+        Ok(())
+    }
+}
+
+/// Details of a single logged-in user.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash, Serialize)]
+pub struct LoggedInUser {
+    /// User name.
+    pub name: String,
+    /// Terminal or seat of the session, if known.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub line: Option<String>,
+    /// Remote host of the session, if any.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub host: Option<String>,
+    /// Login time, in seconds since the Unix epoch, if known.
+    #[serde(rename = "login-time", skip_serializing_if = "Option::is_none")]
+    pub login_time: Option<u64>,
+}
@@ -1,18 +1,387 @@
 //! Commands (guest-to-host messages).
 
 use crate::errors::OgaError;
-use serde::Serialize;
+use bytes::{BufMut, Bytes, BytesMut};
+use serde::ser::SerializeMap;
+use serde::{Deserialize, Serialize, Serializer};
+use std::collections::BTreeMap;
+use std::sync::OnceLock;
+use thiserror::Error;
+
+#[cfg(feature = "derive")]
+pub use tokio_oga_derive::OgaCommand;
+
+pub mod extensions;
 
 /// Supported protocol/API version.
-const API_VERSION: u8 = 3;
+pub(crate) const API_VERSION: u8 = 3;
+
+/// Minimum protocol/API version the host must support for a command, by protocol name.
+///
+/// This backs `AsFrame::min_api_version()` for commands modeled by this
+/// library, and covers frames which are only known by name (e.g. spooled ones).
+pub(crate) fn min_api_version(name: &str) -> u8 {
+    match name {
+        names::NUMBER_OF_CPUS => 1,
+        names::OS_INFO | names::TIMEZONE => 2,
+        names::DISK_MAPPING => 3,
+        _ => 0,
+    }
+}
+
+/// Maximum size of an encoded frame (including the trailing newline) accepted by the host.
+pub const MAX_FRAME_SIZE: usize = 1024 * 1024;
+
+/// Protocol names of guest commands.
+pub mod names {
+    /// Periodic heartbeat.
+    pub const HEARTBEAT: &str = "heartbeat";
+    /// Guest system is started or restarted.
+    pub const SESSION_STARTUP: &str = "session-startup";
+    /// Guest system shuts down.
+    pub const SESSION_SHUTDOWN: &str = "session-shutdown";
+    /// Guest agent was uninstalled.
+    pub const UNINSTALLED: &str = "uninstalled";
+    /// Active user.
+    pub const ACTIVE_USER: &str = "active-user";
+    /// Reply to an echo probe.
+    pub const ECHO: &str = "echo";
+    /// Number of online CPUs.
+    pub const NUMBER_OF_CPUS: &str = "number-of-cpus";
+    /// Guest timezone.
+    pub const TIMEZONE: &str = "timezone";
+    /// Mapping of disk serials to guest block devices.
+    pub const DISK_MAPPING: &str = "disk-mapping";
+    /// Network interfaces and their addresses.
+    pub const NETWORK_INTERFACES: &str = "network-interfaces";
+    /// Usage of mounted filesystems.
+    pub const DISKS_USAGE: &str = "disks-usage";
+    /// Installed applications.
+    pub const APPLICATIONS: &str = "applications";
+    /// Operating system version.
+    pub const OS_VERSION: &str = "os-version";
+    /// Detailed operating system information.
+    pub const OS_INFO: &str = "os-info";
+    /// Running containers.
+    pub const CONTAINERS: &str = "containers";
+    /// Guest host name.
+    pub const HOST_NAME: &str = "host-name";
+}
+
+/// Kind of a guest command modeled by this library.
+///
+/// This can be used as a metrics label or in configuration files, in place of raw names.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+#[non_exhaustive]
+pub enum CommandKind {
+    /// Periodic heartbeat.
+    Heartbeat,
+    /// Guest system is started or restarted.
+    SessionStartup,
+    /// Guest system shuts down.
+    SessionShutdown,
+    /// Guest agent was uninstalled.
+    Uninstalled,
+    /// Active user.
+    ActiveUser,
+    /// Reply to an echo probe.
+    Echo,
+    /// Number of online CPUs.
+    NumberOfCpus,
+    /// Guest timezone.
+    Timezone,
+    /// Mapping of disk serials to guest block devices.
+    DiskMapping,
+    /// Network interfaces and their addresses.
+    NetworkInterfaces,
+    /// Usage of mounted filesystems.
+    DisksUsage,
+    /// Installed applications.
+    Applications,
+    /// Operating system version.
+    OsVersion,
+    /// Detailed operating system information.
+    OsInfo,
+    /// Running containers.
+    Containers,
+    /// Guest host name.
+    HostName,
+}
+
+impl CommandKind {
+    /// All known command kinds.
+    pub const ALL: &'static [CommandKind] = &[
+        CommandKind::Heartbeat,
+        CommandKind::SessionStartup,
+        CommandKind::SessionShutdown,
+        CommandKind::Uninstalled,
+        CommandKind::ActiveUser,
+        CommandKind::Echo,
+        CommandKind::NumberOfCpus,
+        CommandKind::Timezone,
+        CommandKind::DiskMapping,
+        CommandKind::NetworkInterfaces,
+        CommandKind::DisksUsage,
+        CommandKind::Applications,
+        CommandKind::OsVersion,
+        CommandKind::OsInfo,
+        CommandKind::Containers,
+        CommandKind::HostName,
+    ];
+
+    /// Protocol name of this command kind.
+    pub fn name(self) -> &'static str {
+        match self {
+            CommandKind::Heartbeat => names::HEARTBEAT,
+            CommandKind::SessionStartup => names::SESSION_STARTUP,
+            CommandKind::SessionShutdown => names::SESSION_SHUTDOWN,
+            CommandKind::Uninstalled => names::UNINSTALLED,
+            CommandKind::ActiveUser => names::ACTIVE_USER,
+            CommandKind::Echo => names::ECHO,
+            CommandKind::NumberOfCpus => names::NUMBER_OF_CPUS,
+            CommandKind::Timezone => names::TIMEZONE,
+            CommandKind::DiskMapping => names::DISK_MAPPING,
+            CommandKind::NetworkInterfaces => names::NETWORK_INTERFACES,
+            CommandKind::DisksUsage => names::DISKS_USAGE,
+            CommandKind::Applications => names::APPLICATIONS,
+            CommandKind::OsVersion => names::OS_VERSION,
+            CommandKind::OsInfo => names::OS_INFO,
+            CommandKind::Containers => names::CONTAINERS,
+            CommandKind::HostName => names::HOST_NAME,
+        }
+    }
+
+    /// Return the command kind for a protocol name, if known.
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.iter().copied().find(|kind| kind.name() == name)
+    }
+}
+
+impl std::fmt::Display for CommandKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl std::str::FromStr for CommandKind {
+    type Err = OgaError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::from_name(s).ok_or_else(|| format!("unknown command name: '{}'", s).into())
+    }
+}
 
 /// Encode command as frame.
 pub trait AsFrame: std::fmt::Debug + Send {
-    fn as_frame(&self) -> Result<Vec<u8>, OgaError>;
+    fn as_frame(&self) -> Result<Bytes, OgaError>;
+
+    /// Protocol name of this command.
+    fn name(&self) -> &str;
+
+    /// Kind of this command, if modeled by this library.
+    fn kind(&self) -> Option<CommandKind> {
+        CommandKind::from_name(self.name())
+    }
+
+    /// Minimum protocol/API version the host must support for this command.
+    ///
+    /// Commands are rejected, instead of being written, while the version
+    /// negotiated with the host is lower than this, including before the host
+    /// announces it. A host would otherwise silently ignore them.
+    fn min_api_version(&self) -> u8 {
+        0
+    }
+
+    /// Check command content, before it gets queued for sending.
+    fn validate(&self) -> Result<(), ValidationError> {
+        Ok(())
+    }
+
+    /// Write this command as a frame into the given buffer.
+    ///
+    /// By default this copies the output of `as_frame()`. Commands with large
+    /// content can serialize directly into the buffer instead, without any
+    /// intermediate allocation. On errors, partial content may have been written.
+    /// Fixed-capacity buffers without enough room for the frame result in an
+    /// error, instead of a panic.
+    fn write_frame(&self, buf: &mut dyn BufMut) -> Result<(), OgaError> {
+        let frame = self.as_frame()?;
+        if buf.remaining_mut() < frame.len() {
+            let msg = format!(
+                "insufficient buffer space for '{}' frame: {} bytes (available {})",
+                self.name(),
+                frame.len(),
+                buf.remaining_mut()
+            );
+            return Err(msg.into());
+        }
+        buf.put_slice(&frame);
+        Ok(())
+    }
+}
+
+/// Encode a serializable command as a frame.
+///
+/// All built-in commands are encoded through this (or `write_json()` when
+/// streaming), so that framing and size limits are handled in a single place.
+pub(crate) fn json_frame(cmd: &impl Serialize) -> Result<Bytes, OgaError> {
+    let mut buf = BytesMut::new();
+    write_json(&mut buf, cmd)?;
+    Ok(buf.freeze())
+}
+
+/// Serialize a command as a frame, directly into a buffer.
+///
+/// Encoding stops with an error as soon as the frame grows past `MAX_FRAME_SIZE`,
+/// or past the remaining capacity of the buffer.
+fn write_json(buf: &mut dyn BufMut, cmd: &impl Serialize) -> Result<(), OgaError> {
+    use std::io::Write;
+
+    let mut writer = ChunkWriter {
+        buf,
+        chunk: [0; 512],
+        len: 0,
+        written: 0,
+    };
+    serde_json::to_writer(&mut writer, cmd)
+        .map_err(|e| format!("failed to encode frame: {}", e))?;
+    writer
+        .write_all(b"\n")
+        .and_then(|_| writer.flush_chunk())
+        .map_err(|e| format!("failed to encode frame: {}", e))?;
+    Ok(())
+}
+
+/// Writer coalescing small writes on the stack, before copying them into a buffer.
+///
+/// Serializers emit many tiny fragments, which are costly to append
+/// one at a time through a `dyn BufMut`.
+struct ChunkWriter<'a> {
+    buf: &'a mut dyn BufMut,
+    chunk: [u8; 512],
+    len: usize,
+    written: usize,
+}
+
+impl ChunkWriter<'_> {
+    /// Copy pending bytes into the buffer.
+    fn flush_chunk(&mut self) -> std::io::Result<()> {
+        let pending = self.chunk;
+        self.put(&pending[..self.len])?;
+        self.len = 0;
+        Ok(())
+    }
+
+    /// Copy bytes into the buffer, checking size limits first.
+    fn put(&mut self, data: &[u8]) -> std::io::Result<()> {
+        self.written += data.len();
+        if self.written > MAX_FRAME_SIZE {
+            let msg = format!("frame exceeds {} bytes", MAX_FRAME_SIZE);
+            return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, msg));
+        }
+        if self.buf.remaining_mut() < data.len() {
+            let msg = format!(
+                "insufficient buffer space: {} bytes (available {})",
+                data.len(),
+                self.buf.remaining_mut()
+            );
+            return Err(std::io::Error::new(std::io::ErrorKind::WriteZero, msg));
+        }
+        self.buf.put_slice(data);
+        Ok(())
+    }
+}
+
+impl std::io::Write for ChunkWriter<'_> {
+    fn write(&mut self, data: &[u8]) -> std::io::Result<usize> {
+        if self.len + data.len() > self.chunk.len() {
+            self.flush_chunk()?;
+        }
+        if data.len() > self.chunk.len() {
+            self.put(data)?;
+        } else {
+            self.chunk[self.len..self.len + data.len()].copy_from_slice(data);
+            self.len += data.len();
+        }
+        Ok(data.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.flush_chunk()
+    }
+}
+
+/// Encode a command with constant content, caching the frame for later calls.
+fn constant_frame(
+    cache: &'static OnceLock<Bytes>,
+    cmd: &impl Serialize,
+) -> Result<Bytes, OgaError> {
+    if let Some(frame) = cache.get() {
+        return Ok(frame.clone());
+    }
+    let frame = json_frame(cmd)?;
+    Ok(cache.get_or_init(|| frame).clone())
+}
+
+/// Invalid command content.
+#[derive(Error, Clone, Debug, PartialEq, Eq)]
+#[error("invalid '{command}' command: {reason}")]
+pub struct ValidationError {
+    /// Protocol name of the command.
+    pub command: String,
+    /// Reason for rejecting the command.
+    pub reason: String,
+}
+
+impl ValidationError {
+    /// Return a validation error for the given command.
+    pub fn new(command: impl Into<String>, reason: impl Into<String>) -> Self {
+        Self {
+            command: command.into(),
+            reason: reason.into(),
+        }
+    }
+}
+
+/// Encode a serializable command as a frame, tagged with the given message name.
+///
+/// This is used by `#[derive(OgaCommand)]`.
+#[doc(hidden)]
+pub fn encode_tagged<T: Serialize>(name: &str, cmd: &T) -> Result<Bytes, OgaError> {
+    #[derive(Serialize)]
+    struct Tagged<'a, T> {
+        #[serde(rename = "__name__")]
+        name: &'a str,
+        #[serde(flatten)]
+        inner: &'a T,
+    }
+
+    json_frame(&Tagged { name, inner: cmd })
+}
+
+/// Write a serializable command as a frame into a buffer, tagged with the given message name.
+///
+/// This is used by `#[derive(OgaCommand)]`.
+#[doc(hidden)]
+pub fn write_tagged<T: Serialize>(
+    name: &str,
+    cmd: &T,
+    buf: &mut dyn BufMut,
+) -> Result<(), OgaError> {
+    #[derive(Serialize)]
+    struct Tagged<'a, T> {
+        #[serde(rename = "__name__")]
+        name: &'a str,
+        #[serde(flatten)]
+        inner: &'a T,
+    }
+
+    write_json(buf, &Tagged { name, inner: cmd })
 }
 
 /// Heartbeat.
-#[derive(Clone, Debug, Serialize)]
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Serialize)]
 #[serde(tag = "__name__")]
 #[serde(rename(serialize = "heartbeat"))]
 pub struct Heartbeat {
@@ -32,61 +401,74 @@ impl Default for Heartbeat {
 }
 
 impl AsFrame for Heartbeat {
-    fn as_frame(&self) -> Result<Vec<u8>, OgaError> {
-        let mut msg =
-            serde_json::to_vec(self).map_err(|e| format!("failed to encode frame: {}", e))?;
-        msg.push(b'\n');
-        Ok(msg)
+    fn as_frame(&self) -> Result<Bytes, OgaError> {
+        // Default heartbeats are sent at every interval, thus encoded once.
+        static FRAME: OnceLock<Bytes> = OnceLock::new();
+        if *self == Self::default() {
+            return constant_frame(&FRAME, self);
+        }
+
+        json_frame(self)
+    }
+
+    fn name(&self) -> &str {
+        names::HEARTBEAT
     }
 }
 
 /// Guest system is started or restarted.
-#[derive(Clone, Debug, Default, Serialize)]
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash, Serialize)]
 #[serde(tag = "__name__")]
 #[serde(rename(serialize = "session-startup"))]
 pub struct SessionStartup {}
 
 impl AsFrame for SessionStartup {
-    fn as_frame(&self) -> Result<Vec<u8>, OgaError> {
-        let mut msg =
-            serde_json::to_vec(self).map_err(|e| format!("failed to encode frame: {}", e))?;
-        msg.push(b'\n');
-        Ok(msg)
+    fn as_frame(&self) -> Result<Bytes, OgaError> {
+        static FRAME: OnceLock<Bytes> = OnceLock::new();
+        constant_frame(&FRAME, self)
+    }
+
+    fn name(&self) -> &str {
+        names::SESSION_STARTUP
     }
 }
 
 /// Guest system shuts down.
-#[derive(Clone, Debug, Default, Serialize)]
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash, Serialize)]
 #[serde(tag = "__name__")]
 #[serde(rename(serialize = "session-shutdown"))]
 pub struct SessionShutdown {}
 
 impl AsFrame for SessionShutdown {
-    fn as_frame(&self) -> Result<Vec<u8>, OgaError> {
-        let mut msg =
-            serde_json::to_vec(self).map_err(|e| format!("failed to encode frame: {}", e))?;
-        msg.push(b'\n');
-        Ok(msg)
+    fn as_frame(&self) -> Result<Bytes, OgaError> {
+        static FRAME: OnceLock<Bytes> = OnceLock::new();
+        constant_frame(&FRAME, self)
+    }
+
+    fn name(&self) -> &str {
+        names::SESSION_SHUTDOWN
     }
 }
 
 /// Guest agent was uninstalled.
-#[derive(Clone, Debug, Default, Serialize)]
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash, Serialize)]
 #[serde(tag = "__name__")]
 #[serde(rename(serialize = "uninstalled"))]
 pub struct Uninstalled {}
 
 impl AsFrame for Uninstalled {
-    fn as_frame(&self) -> Result<Vec<u8>, OgaError> {
-        let mut msg =
-            serde_json::to_vec(self).map_err(|e| format!("failed to encode frame: {}", e))?;
-        msg.push(b'\n');
-        Ok(msg)
+    fn as_frame(&self) -> Result<Bytes, OgaError> {
+        static FRAME: OnceLock<Bytes> = OnceLock::new();
+        constant_frame(&FRAME, self)
+    }
+
+    fn name(&self) -> &str {
+        names::UNINSTALLED
     }
 }
 
 /// Active user.
-#[derive(Clone, Debug, Serialize)]
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Serialize)]
 #[serde(tag = "__name__")]
 #[serde(rename(serialize = "active-user"))]
 pub struct ActiveUser {
@@ -94,11 +476,19 @@ pub struct ActiveUser {
 }
 
 impl AsFrame for ActiveUser {
-    fn as_frame(&self) -> Result<Vec<u8>, OgaError> {
-        let mut msg =
-            serde_json::to_vec(self).map_err(|e| format!("failed to encode frame: {}", e))?;
-        msg.push(b'\n');
-        Ok(msg)
+    fn as_frame(&self) -> Result<Bytes, OgaError> {
+        json_frame(self)
+    }
+
+    fn name(&self) -> &str {
+        names::ACTIVE_USER
+    }
+
+    fn validate(&self) -> Result<(), ValidationError> {
+        if self.name.is_empty() {
+            return Err(ValidationError::new(self.name(), "empty user name"));
+        }
+        Ok(())
     }
 }
 
@@ -109,3 +499,523 @@ impl Default for ActiveUser {
         }
     }
 }
+
+/// Number of online CPUs.
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Serialize)]
+#[serde(tag = "__name__")]
+#[serde(rename(serialize = "number-of-cpus"))]
+pub struct NumberOfCpus {
+    pub count: u32,
+}
+
+impl AsFrame for NumberOfCpus {
+    fn as_frame(&self) -> Result<Bytes, OgaError> {
+        json_frame(self)
+    }
+
+    fn name(&self) -> &str {
+        names::NUMBER_OF_CPUS
+    }
+
+    fn min_api_version(&self) -> u8 {
+        min_api_version(self.name())
+    }
+
+    fn validate(&self) -> Result<(), ValidationError> {
+        if self.count == 0 {
+            return Err(ValidationError::new(self.name(), "zero CPUs"));
+        }
+        Ok(())
+    }
+}
+
+/// Guest timezone.
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Serialize)]
+#[serde(tag = "__name__")]
+#[serde(rename(serialize = "timezone"))]
+pub struct Timezone {
+    /// Zone name, e.g. `Europe/Rome`.
+    pub zone: String,
+    /// Current offset from UTC, in minutes.
+    pub offset: i32,
+}
+
+impl AsFrame for Timezone {
+    fn as_frame(&self) -> Result<Bytes, OgaError> {
+        json_frame(self)
+    }
+
+    fn name(&self) -> &str {
+        names::TIMEZONE
+    }
+
+    fn min_api_version(&self) -> u8 {
+        min_api_version(self.name())
+    }
+
+    fn validate(&self) -> Result<(), ValidationError> {
+        if self.zone.is_empty() {
+            return Err(ValidationError::new(self.name(), "empty zone name"));
+        }
+        Ok(())
+    }
+}
+
+/// Mapping of disk serials to guest block devices.
+///
+/// The host uses this to correlate VM disks (by serial) with guest devices.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash, Serialize)]
+#[serde(tag = "__name__")]
+#[serde(rename(serialize = "disk-mapping"))]
+pub struct DiskMapping {
+    /// Guest devices, keyed by disk serial.
+    pub mapping: BTreeMap<String, MappedDisk>,
+}
+
+impl AsFrame for DiskMapping {
+    fn as_frame(&self) -> Result<Bytes, OgaError> {
+        json_frame(self)
+    }
+
+    fn write_frame(&self, buf: &mut dyn BufMut) -> Result<(), OgaError> {
+        write_json(buf, self)
+    }
+
+    fn name(&self) -> &str {
+        names::DISK_MAPPING
+    }
+
+    fn min_api_version(&self) -> u8 {
+        min_api_version(self.name())
+    }
+
+    fn validate(&self) -> Result<(), ValidationError> {
+        if self.mapping.keys().any(|serial| serial.is_empty()) {
+            return Err(ValidationError::new(self.name(), "empty disk serial"));
+        }
+        Ok(())
+    }
+}
+
+/// Guest block device for a mapped disk.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash, Serialize)]
+pub struct MappedDisk {
+    /// Device path, e.g. `/dev/vda`.
+    pub name: String,
+}
+
+/// Network interfaces and their addresses.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash, Serialize)]
+#[serde(tag = "__name__")]
+#[serde(rename(serialize = "network-interfaces"))]
+pub struct NetworkInterfaces {
+    pub interfaces: Vec<NetworkInterface>,
+}
+
+impl AsFrame for NetworkInterfaces {
+    fn as_frame(&self) -> Result<Bytes, OgaError> {
+        json_frame(self)
+    }
+
+    fn write_frame(&self, buf: &mut dyn BufMut) -> Result<(), OgaError> {
+        write_json(buf, self)
+    }
+
+    fn name(&self) -> &str {
+        names::NETWORK_INTERFACES
+    }
+
+    fn validate(&self) -> Result<(), ValidationError> {
+        if self.interfaces.iter().any(|i| i.name.is_empty()) {
+            return Err(ValidationError::new(self.name(), "empty interface name"));
+        }
+        Ok(())
+    }
+}
+
+/// Details of a single network interface.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash, Serialize)]
+pub struct NetworkInterface {
+    /// Interface name.
+    pub name: String,
+    /// Hardware address, e.g. `52:54:00:12:34:56`.
+    pub hw: String,
+    /// IPv4 addresses.
+    pub inet: Vec<String>,
+    /// IPv6 addresses.
+    pub inet6: Vec<String>,
+}
+
+/// Usage of mounted filesystems.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash, Serialize)]
+#[serde(tag = "__name__")]
+#[serde(rename(serialize = "disks-usage"))]
+pub struct DisksUsage {
+    pub disks: Vec<DiskUsage>,
+}
+
+impl AsFrame for DisksUsage {
+    fn as_frame(&self) -> Result<Bytes, OgaError> {
+        json_frame(self)
+    }
+
+    fn write_frame(&self, buf: &mut dyn BufMut) -> Result<(), OgaError> {
+        write_json(buf, self)
+    }
+
+    fn name(&self) -> &str {
+        names::DISKS_USAGE
+    }
+
+    fn validate(&self) -> Result<(), ValidationError> {
+        if self.disks.iter().any(|d| d.used > d.total) {
+            return Err(ValidationError::new(
+                self.name(),
+                "used space exceeds total",
+            ));
+        }
+        if let Some(disk) = self
+            .disks
+            .iter()
+            .find(|d| !std::path::Path::new(&d.path).is_absolute())
+        {
+            let msg = format!("mountpoint '{}' is not an absolute path", disk.path);
+            return Err(ValidationError::new(self.name(), msg));
+        }
+        Ok(())
+    }
+}
+
+/// Usage of a single mounted filesystem.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash, Serialize)]
+pub struct DiskUsage {
+    /// Mountpoint, as an absolute path (e.g. `/home`, or `C:\` on Windows).
+    pub path: String,
+    /// Filesystem type, e.g. `ext4`.
+    pub fs: String,
+    /// Total size, in bytes.
+    pub total: u64,
+    /// Used space, in bytes.
+    pub used: u64,
+}
+
+impl DiskUsage {
+    /// Used space, as a percentage of the total size.
+    pub fn used_percent(&self) -> u8 {
+        match self.total {
+            0 => 0,
+            total => (self.used.saturating_mul(100) / total).min(100) as u8,
+        }
+    }
+}
+
+/// Installed applications.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash, Serialize)]
+#[serde(tag = "__name__")]
+#[serde(rename(serialize = "applications"))]
+pub struct Applications {
+    /// Application names, including versions (e.g. `bash-5.1.8-6.el9`).
+    pub applications: Vec<String>,
+}
+
+impl AsFrame for Applications {
+    fn as_frame(&self) -> Result<Bytes, OgaError> {
+        json_frame(self)
+    }
+
+    fn write_frame(&self, buf: &mut dyn BufMut) -> Result<(), OgaError> {
+        write_json(buf, self)
+    }
+
+    fn name(&self) -> &str {
+        names::APPLICATIONS
+    }
+}
+
+/// Operating system version.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash, Serialize)]
+#[serde(tag = "__name__")]
+#[serde(rename(serialize = "os-version"))]
+pub struct OsVersion {
+    /// Human-readable version, e.g. `Fedora Linux 39 (Server Edition)`.
+    pub version: String,
+}
+
+impl AsFrame for OsVersion {
+    fn as_frame(&self) -> Result<Bytes, OgaError> {
+        json_frame(self)
+    }
+
+    fn name(&self) -> &str {
+        names::OS_VERSION
+    }
+}
+
+/// Detailed operating system information.
+///
+/// This complements `os-version` with structured fields, as understood
+/// by newer hosts.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash, Serialize)]
+#[serde(tag = "__name__")]
+#[serde(rename(serialize = "os-info"))]
+pub struct OsInfo {
+    /// Distribution version, e.g. `39`.
+    pub version: String,
+    /// Distribution name, e.g. `Fedora Linux`.
+    pub distribution: String,
+    /// Release codename, if any.
+    pub codename: String,
+    /// Machine architecture, e.g. `x86_64`.
+    pub arch: String,
+    /// Operating system type, e.g. `linux`.
+    #[serde(rename = "type")]
+    pub os_type: String,
+    /// Kernel release, e.g. `6.5.6-300.fc39.x86_64`.
+    pub kernel: String,
+}
+
+impl AsFrame for OsInfo {
+    fn as_frame(&self) -> Result<Bytes, OgaError> {
+        json_frame(self)
+    }
+
+    fn name(&self) -> &str {
+        names::OS_INFO
+    }
+
+    fn min_api_version(&self) -> u8 {
+        min_api_version(self.name())
+    }
+}
+
+/// Running containers.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash, Serialize)]
+#[serde(tag = "__name__")]
+#[serde(rename(serialize = "containers"))]
+pub struct Containers {
+    pub list: Vec<Container>,
+}
+
+impl AsFrame for Containers {
+    fn as_frame(&self) -> Result<Bytes, OgaError> {
+        json_frame(self)
+    }
+
+    fn write_frame(&self, buf: &mut dyn BufMut) -> Result<(), OgaError> {
+        write_json(buf, self)
+    }
+
+    fn name(&self) -> &str {
+        names::CONTAINERS
+    }
+}
+
+/// Details of a single running container.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash, Serialize)]
+pub struct Container {
+    /// Container ID.
+    pub id: String,
+    /// Container names.
+    pub names: Vec<String>,
+    /// Image the container was created from.
+    pub image: String,
+    /// Command run in the container.
+    pub command: String,
+    /// Human-readable status, e.g. `Up 2 hours`.
+    pub status: String,
+}
+
+/// Guest host name.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash, Serialize)]
+#[serde(tag = "__name__")]
+#[serde(rename(serialize = "host-name"))]
+pub struct HostName {
+    pub name: String,
+}
+
+impl AsFrame for HostName {
+    fn as_frame(&self) -> Result<Bytes, OgaError> {
+        json_frame(self)
+    }
+
+    fn name(&self) -> &str {
+        names::HOST_NAME
+    }
+
+    fn validate(&self) -> Result<(), ValidationError> {
+        if self.name.is_empty() {
+            return Err(ValidationError::new(self.name(), "empty host name"));
+        }
+        Ok(())
+    }
+}
+
+/// Reply to an echo probe from the host.
+///
+/// This mirrors the payload of the `echo` event, for applications answering
+/// probes on their own.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash, Serialize)]
+#[serde(tag = "__name__")]
+#[serde(rename(serialize = "echo"))]
+pub struct EchoReply {
+    #[serde(flatten)]
+    pub payload: serde_json::Map<String, serde_json::Value>,
+}
+
+impl From<&crate::events::Echo> for EchoReply {
+    fn from(probe: &crate::events::Echo) -> Self {
+        Self {
+            payload: probe.payload.clone(),
+        }
+    }
+}
+
+impl AsFrame for EchoReply {
+    fn as_frame(&self) -> Result<Bytes, OgaError> {
+        json_frame(self)
+    }
+
+    fn name(&self) -> &str {
+        names::ECHO
+    }
+
+    fn validate(&self) -> Result<(), ValidationError> {
+        if self.payload.contains_key("__name__") {
+            return Err(ValidationError::new(
+                self.name(),
+                "reserved '__name__' field",
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Echo probe, sent by the guest to measure the round-trip time towards the host.
+///
+/// The host answers with an `echo` event carrying the same `id`.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash, Serialize)]
+#[serde(tag = "__name__")]
+#[serde(rename(serialize = "echo"))]
+pub struct EchoProbe {
+    /// Identifier for matching the answer to this probe.
+    pub id: String,
+}
+
+impl AsFrame for EchoProbe {
+    fn as_frame(&self) -> Result<Bytes, OgaError> {
+        json_frame(self)
+    }
+
+    fn name(&self) -> &str {
+        names::ECHO
+    }
+}
+
+/// Custom command, for protocol messages which are not modeled by this library.
+///
+/// The payload fields are sent alongside the `__name__` tag.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Custom {
+    /// Protocol name of the message.
+    pub name: String,
+    /// Message fields.
+    pub payload: serde_json::Map<String, serde_json::Value>,
+}
+
+impl Serialize for Custom {
+    fn serialize<S: Serializer>(&self, ser: S) -> Result<S::Ok, S::Error> {
+        let mut map = ser.serialize_map(Some(self.payload.len() + 1))?;
+        map.serialize_entry("__name__", &self.name)?;
+        for (key, value) in &self.payload {
+            map.serialize_entry(key, value)?;
+        }
+        map.end()
+    }
+}
+
+impl AsFrame for Custom {
+    fn as_frame(&self) -> Result<Bytes, OgaError> {
+        self.validate()?;
+        json_frame(self)
+    }
+
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn validate(&self) -> Result<(), ValidationError> {
+        if self.name.is_empty() {
+            return Err(ValidationError::new("custom", "empty name"));
+        }
+        if self.payload.contains_key("__name__") {
+            return Err(ValidationError::new(
+                self.name(),
+                "reserved '__name__' field in payload",
+            ));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn write_into_short_buffer() {
+        let cmd = ActiveUser {
+            name: "someone".to_string(),
+        };
+        let mut storage = [0u8; 8];
+        let mut buf = &mut storage[..];
+        let err = cmd.write_frame(&mut buf).unwrap_err();
+        assert!(err.0.contains("insufficient buffer space"), "{}", err);
+
+        let cmd = Custom {
+            name: "x-report".to_string(),
+            payload: serde_json::Map::new(),
+        };
+        let mut buf = &mut storage[..];
+        let err = write_json(&mut buf, &cmd).unwrap_err();
+        assert!(err.0.contains("insufficient buffer space"), "{}", err);
+    }
+
+    #[test]
+    fn reject_oversized_frame() {
+        let mut payload = serde_json::Map::new();
+        payload.insert("data".to_string(), "x".repeat(MAX_FRAME_SIZE).into());
+        let cmd = Custom {
+            name: "x-report".to_string(),
+            payload,
+        };
+        let err = cmd.as_frame().unwrap_err();
+        assert!(err.0.contains("frame exceeds"), "{}", err);
+    }
+
+    #[test]
+    fn frame_is_newline_terminated() {
+        let frame = SessionStartup::default().as_frame().unwrap();
+        assert_eq!(&frame[..], b"{\"__name__\":\"session-startup\"}\n");
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn reject_relative_mountpoints() {
+        let disk = |path: &str| DiskUsage {
+            path: path.to_string(),
+            fs: "ext4".to_string(),
+            total: 2,
+            used: 1,
+        };
+        let mut cmd = DisksUsage {
+            disks: vec![disk("/"), disk("/home")],
+        };
+        cmd.validate().unwrap();
+        for path in ["", "home", "./home"] {
+            cmd.disks.push(disk(path));
+            let err = cmd.validate().unwrap_err();
+            assert!(err.to_string().contains("not an absolute path"), "{}", err);
+            cmd.disks.pop();
+        }
+    }
+}
@@ -0,0 +1,226 @@
+//! Configuration files compatible with the reference agent.
+//!
+//! This parses the INI-like `ovirt-guest-agent.conf` format used by the
+//! reference Python agent, so that existing configuration can be reused.
+
+use crate::errors::OgaError;
+use std::path::{Path, PathBuf};
+
+/// Default path to the reference agent configuration file.
+pub static DEFAULT_CONFIG_PATH: &str = "/etc/ovirt-guest-agent.conf";
+
+/// Settings from an `ovirt-guest-agent.conf` configuration file.
+///
+/// Settings which are not present in the file are left unset.
+#[derive(Clone, Debug, Default)]
+#[non_exhaustive]
+pub struct AgentConfig {
+    /// Path to the VirtIO serial port (`virtio.device`).
+    pub device: Option<PathBuf>,
+    /// Seconds between heartbeats (`general.heart_beat_rate`).
+    pub heart_beat_rate: Option<u8>,
+    /// Seconds between active-user reports (`general.report_user_rate`).
+    pub report_user_rate: Option<u64>,
+    /// Seconds between CPU-count reports (`general.report_num_cpu_rate`).
+    pub report_num_cpu_rate: Option<u64>,
+    /// Seconds between applications reports (`general.report_application_rate`).
+    pub report_application_rate: Option<u64>,
+    /// Seconds between disks-usage reports (`general.report_disk_usage`).
+    pub report_disk_usage: Option<u64>,
+    /// Application names to report (`general.applications_list`).
+    pub applications_list: Vec<String>,
+    /// Filesystem types to ignore (`general.ignored_fs`).
+    pub ignored_fs: Vec<String>,
+    /// Whether to ignore zero-sized filesystems (`general.ignore_zero_size_fs`).
+    pub ignore_zero_size_fs: Option<bool>,
+    /// Network interfaces to ignore (`general.ignored_nics`).
+    pub ignored_nics: Vec<String>,
+    /// Users to ignore (`general.ignored_users`).
+    pub ignored_users: Vec<String>,
+}
+
+impl AgentConfig {
+    /// Read and parse a configuration file.
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Self, OgaError> {
+        let content = std::fs::read_to_string(path.as_ref()).map_err(|e| {
+            format!(
+                "failed to read config file '{}': {}",
+                path.as_ref().display(),
+                e
+            )
+        })?;
+        Self::parse(&content)
+    }
+
+    /// Parse configuration from file content.
+    pub fn parse(content: &str) -> Result<Self, OgaError> {
+        let mut cfg = Self::default();
+        let mut section = String::new();
+
+        for (index, raw_line) in content.lines().enumerate() {
+            let line = raw_line.trim();
+            if line.is_empty() || line.starts_with('#') || line.starts_with(';') {
+                continue;
+            }
+
+            if line.starts_with('[') {
+                section = line
+                    .strip_suffix(']')
+                    .map(|s| s[1..].trim().to_string())
+                    .ok_or_else(|| format!("invalid section at line {}", index + 1))?;
+                continue;
+            }
+
+            let (key, value) = line
+                .split_once(['=', ':'])
+                .map(|(k, v)| (k.trim(), v.trim()))
+                .ok_or_else(|| format!("invalid entry at line {}", index + 1))?;
+            cfg.set(&section, key, value)
+                .map_err(|e| format!("invalid value for '{}' at line {}: {}", key, index + 1, e))?;
+        }
+
+        Ok(cfg)
+    }
+
+    /// Apply a single `key = value` entry from a section.
+    fn set(&mut self, section: &str, key: &str, value: &str) -> Result<(), String> {
+        match (section, key) {
+            ("virtio", "device") => self.device = Some(PathBuf::from(value)),
+            ("general", "heart_beat_rate") => self.heart_beat_rate = Some(parse_num(value)?),
+            ("general", "report_user_rate") => self.report_user_rate = Some(parse_num(value)?),
+            ("general", "report_num_cpu_rate") => {
+                self.report_num_cpu_rate = Some(parse_num(value)?)
+            }
+            ("general", "report_application_rate") => {
+                self.report_application_rate = Some(parse_num(value)?)
+            }
+            ("general", "report_disk_usage") => self.report_disk_usage = Some(parse_num(value)?),
+            ("general", "applications_list") => self.applications_list = parse_list(value),
+            ("general", "ignored_fs") => self.ignored_fs = parse_list(value),
+            ("general", "ignore_zero_size_fs") => {
+                self.ignore_zero_size_fs = Some(parse_bool(value)?)
+            }
+            ("general", "ignored_nics") => self.ignored_nics = parse_list(value),
+            ("general", "ignored_users") => self.ignored_users = parse_list(value),
+            _ => log::trace!("ignoring config entry '{}.{}'", section, key),
+        };
+        Ok(())
+    }
+}
+
+/// Parse a numeric value.
+fn parse_num<T>(value: &str) -> Result<T, String>
+where
+    T: std::str::FromStr,
+    T::Err: std::fmt::Display,
+{
+    value.parse().map_err(|e: T::Err| e.to_string())
+}
+
+/// Parse a boolean value, following Python `ConfigParser` conventions.
+fn parse_bool(value: &str) -> Result<bool, String> {
+    match value.to_ascii_lowercase().as_str() {
+        "1" | "yes" | "true" | "on" => Ok(true),
+        "0" | "no" | "false" | "off" => Ok(false),
+        _ => Err(format!("not a boolean: '{}'", value)),
+    }
+}
+
+/// Parse a whitespace-separated list of values.
+fn parse_list(value: &str) -> Vec<String> {
+    value.split_whitespace().map(String::from).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_booleans() {
+        for value in &["1", "yes", "True", "ON"] {
+            assert_eq!(parse_bool(value), Ok(true), "{}", value);
+        }
+        for value in &["0", "no", "FALSE", "off"] {
+            assert_eq!(parse_bool(value), Ok(false), "{}", value);
+        }
+        assert!(parse_bool("maybe").is_err());
+        assert!(parse_bool("").is_err());
+    }
+
+    #[test]
+    fn parse_lists() {
+        assert_eq!(parse_list("gdm  sddm\tlightdm"), ["gdm", "sddm", "lightdm"]);
+        assert!(parse_list("").is_empty());
+        assert!(parse_list("   ").is_empty());
+    }
+
+    #[test]
+    fn parse_sections() {
+        let content = "
+[virtio]
+device = /dev/virtio-ports/custom
+
+[general]
+heart_beat_rate: 10
+report_user_rate = 30
+ignored_users = gdm sddm
+ignored_fs = squashfs
+ignore_zero_size_fs = true
+ignored_nics = lo docker0
+";
+        let cfg = AgentConfig::parse(content).unwrap();
+        assert_eq!(cfg.device, Some(PathBuf::from("/dev/virtio-ports/custom")));
+        assert_eq!(cfg.heart_beat_rate, Some(10));
+        assert_eq!(cfg.report_user_rate, Some(30));
+        assert_eq!(cfg.report_disk_usage, None);
+        assert_eq!(cfg.ignored_users, ["gdm", "sddm"]);
+        assert_eq!(cfg.ignored_fs, ["squashfs"]);
+        assert_eq!(cfg.ignore_zero_size_fs, Some(true));
+        assert_eq!(cfg.ignored_nics, ["lo", "docker0"]);
+    }
+
+    #[test]
+    fn keys_are_scoped_to_sections() {
+        let content = "
+device = /dev/ignored
+[general]
+device = /dev/ignored
+[unknown]
+heart_beat_rate = 1
+";
+        let cfg = AgentConfig::parse(content).unwrap();
+        assert_eq!(cfg.device, None);
+        assert_eq!(cfg.heart_beat_rate, None);
+    }
+
+    #[test]
+    fn skip_comments() {
+        let content = "
+# heart_beat_rate = 1
+[general]
+; heart_beat_rate = 2
+   # indented comment
+heart_beat_rate = 3
+";
+        let cfg = AgentConfig::parse(content).unwrap();
+        assert_eq!(cfg.heart_beat_rate, Some(3));
+    }
+
+    #[test]
+    fn reject_malformed_lines() {
+        let err = AgentConfig::parse("[general\nheart_beat_rate = 1").unwrap_err();
+        assert!(err.0.contains("invalid section at line 1"), "{}", err);
+
+        let err = AgentConfig::parse("[general]\nheart_beat_rate").unwrap_err();
+        assert!(err.0.contains("invalid entry at line 2"), "{}", err);
+
+        let err = AgentConfig::parse("[general]\n\nheart_beat_rate = fast").unwrap_err();
+        assert!(err.0.contains("'heart_beat_rate' at line 3"), "{}", err);
+
+        let err = AgentConfig::parse("[general]\nheart_beat_rate = 300").unwrap_err();
+        assert!(err.0.contains("'heart_beat_rate' at line 2"), "{}", err);
+
+        let err = AgentConfig::parse("[general]\nignore_zero_size_fs = maybe").unwrap_err();
+        assert!(err.0.contains("not a boolean"), "{}", err);
+    }
+}
@@ -0,0 +1,83 @@
+/*! Single sign-on credentials channel.
+
+This supports the dedicated channel used by the host to inject
+user credentials into the guest for single sign-on (SSO).
+Each frame is a newline-terminated JSON object, carrying a username,
+a password and an optional domain.
+
+Credentials are never logged; malformed frames are discarded without
+recording their content.
+*/
+
+use crate::errors::OgaError;
+use crate::secret::Secret;
+use crate::virtio::VirtioPort;
+use bytes::BytesMut;
+use futures::StreamExt;
+use serde::Deserialize;
+use std::path::Path;
+use tokio_util::codec::{Decoder, FramedRead};
+use zeroize::Zeroize;
+
+/// Default path to the VirtIO credentials device.
+#[cfg(not(target_os = "freebsd"))]
+pub static DEFAULT_CREDENTIALS_PATH: &str = "/dev/virtio-ports/ovirt.credentials.0";
+/// Default path to the VirtIO credentials device.
+#[cfg(target_os = "freebsd")]
+pub static DEFAULT_CREDENTIALS_PATH: &str = "/dev/vtcon/ovirt.credentials.0";
+
+/// User credentials for single sign-on.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize)]
+pub struct Credentials {
+    pub username: String,
+    #[serde(default)]
+    pub domain: Option<String>,
+    pub password: Secret,
+}
+
+/// Codec for newline-delimited credentials frames.
+#[derive(Clone, Debug, Default)]
+struct CredentialsCodec {}
+
+impl Decoder for CredentialsCodec {
+    type Item = Credentials;
+    type Error = OgaError;
+
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
+        while let Some(pos) = src.iter().position(|b| *b == b'\n') {
+            let mut frame = src.split_to(pos + 1);
+            let res = serde_json::from_slice(&frame[..pos]);
+            frame.as_mut().zeroize();
+            match res {
+                Ok(creds) => return Ok(Some(creds)),
+                Err(_) => log::warn!("discarded malformed credentials frame"),
+            }
+        }
+        Ok(None)
+    }
+}
+
+/// Channel for receiving credentials from the host.
+#[derive(Debug)]
+pub struct CredentialsChannel {
+    inner: FramedRead<VirtioPort, CredentialsCodec>,
+}
+
+impl CredentialsChannel {
+    /// Open the credentials channel on the VirtIO serial port at given path.
+    ///
+    /// This must be called from within a tokio runtime context.
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, OgaError> {
+        let dev = VirtioPort::open(path)?;
+        let inner = FramedRead::new(dev, CredentialsCodec::default());
+        Ok(Self { inner })
+    }
+
+    /// Receive the next set of credentials from the host.
+    pub async fn recv(&mut self) -> Result<Credentials, OgaError> {
+        self.inner
+            .next()
+            .await
+            .ok_or_else(|| OgaError::from("end of credentials stream"))?
+    }
+}
@@ -1,9 +1,10 @@
 //! Error handling.
 
+use crate::commands::ValidationError;
 use thiserror::Error;
 
 /// Library errors.
-#[derive(Error, Debug)]
+#[derive(Clone, Error, Debug)]
 #[error("tokio-oga error: {0}")]
 pub struct OgaError(pub(crate) String);
 
@@ -18,3 +19,15 @@ impl From<String> for OgaError {
         Self(arg)
     }
 }
+
+impl From<std::io::Error> for OgaError {
+    fn from(arg: std::io::Error) -> Self {
+        Self(arg.to_string())
+    }
+}
+
+impl From<ValidationError> for OgaError {
+    fn from(arg: ValidationError) -> Self {
+        Self(arg.to_string())
+    }
+}
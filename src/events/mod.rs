@@ -1,12 +1,30 @@
 //! Events (host-to-guest messages).
 
 use crate::errors::OgaError;
-use serde::Deserialize;
+use crate::secret::Secret;
+use bytes::Bytes;
+use serde::{Deserialize, Serialize, Serializer};
+use std::sync::OnceLock;
+use zeroize::Zeroizing;
 
 // TODO(lucab): complete events with their args.
 
+/// Protocol wire names of all known events.
+pub(crate) const NAMES: &[&str] = &[
+    "api-version",
+    "echo",
+    "hibernate",
+    "lifecycle-event",
+    "lock-screen",
+    "login",
+    "log-off",
+    "refresh",
+    "set-number-of-cpus",
+    "shutdown",
+];
+
 /// Event message from host.
-#[derive(Clone, Debug, Deserialize)]
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
 #[serde(tag = "__name__")]
 #[serde(rename_all = "kebab-case")]
 #[non_exhaustive]
@@ -28,73 +46,246 @@ impl Event {
     pub fn parse_frame(data: &[u8]) -> Result<Self, OgaError> {
         serde_json::from_slice(data).map_err(|e| OgaError::from(e.to_string()))
     }
+
+    /// Encode this event as a protocol frame.
+    ///
+    /// Sensitive content (i.e. login passwords) is left out.
+    pub fn to_frame(&self) -> Result<Bytes, OgaError> {
+        let mut msg =
+            serde_json::to_vec(self).map_err(|e| format!("failed to encode frame: {}", e))?;
+        msg.push(b'\n');
+        Ok(Bytes::from(msg))
+    }
+
+    /// Return the protocol wire name of this event (e.g. `lock-screen`).
+    ///
+    /// This matches the names used for `OgaBuilder::ignored_events`.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Event::ApiVersion(_) => "api-version",
+            Event::Echo(_) => "echo",
+            Event::Hibernate(_) => "hibernate",
+            Event::LifecycleEvent(_) => "lifecycle-event",
+            Event::LockScreen(_) => "lock-screen",
+            Event::Login(_) => "login",
+            Event::LogOff(_) => "log-off",
+            Event::Refresh(_) => "refresh",
+            Event::SetNumberOfCpus(_) => "set-number-of-cpus",
+            Event::Shutdown(_) => "shutdown",
+        }
+    }
 }
 
 impl std::fmt::Display for Event {
+    /// Format the event kind, along with its key arguments (credentials are never shown).
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Event::ApiVersion(ev) => write!(f, "ApiVersion(api_version={})", ev.api_version),
+            Event::Echo(_) => write!(f, "Echo"),
+            Event::Hibernate(_) => write!(f, "Hibernate"),
+            Event::LifecycleEvent(_) => write!(f, "LifecycleEvent"),
+            Event::LockScreen(_) => write!(f, "LockScreen"),
+            Event::Login(ev) => match (&ev.username, &ev.domain) {
+                (Some(user), Some(domain)) => {
+                    write!(f, "Login(username={:?}, domain={:?})", user, domain)
+                }
+                (Some(user), None) => write!(f, "Login(username={:?})", user),
+                (None, _) => write!(f, "Login"),
+            },
+            Event::LogOff(_) => write!(f, "LogOff"),
+            Event::Refresh(ev) => write!(f, "Refresh(api_version={})", ev.api_version),
+            Event::SetNumberOfCpus(ev) => write!(f, "SetNumberOfCpus(count={})", ev.count),
+            Event::Shutdown(ev) => {
+                write!(f, "Shutdown(reboot={}", ev.is_reboot())?;
+                if let Some(timeout) = ev.timeout {
+                    write!(f, ", timeout={}", timeout)?;
+                }
+                if let Some(message) = &ev.message {
+                    write!(f, ", message={:?}", message)?;
+                }
+                write!(f, ")")
+            }
+        }
+    }
+}
+
+/// Lazily-parsed event message from host.
+///
+/// Only the `__name__` discriminator is parsed eagerly; full deserialization
+/// is deferred to the first access, and skipped for events which are never
+/// inspected.
+///
+/// Frames may carry credentials (i.e. `login` events), thus the original
+/// content is wiped from memory on drop, and never shown in `Debug` output.
+#[derive(Clone)]
+pub struct LazyEvent {
+    name: String,
+    raw: Zeroizing<String>,
+    parsed: OnceLock<Event>,
+}
+
+impl std::fmt::Debug for LazyEvent {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
-        let kind = match self {
-            Event::ApiVersion(_) => "ApiVersion",
-            Event::Echo(_) => "Echo",
-            Event::Hibernate(_) => "Hibernate",
-            Event::LifecycleEvent(_) => "LifecycleEvent",
-            Event::LockScreen(_) => "LockScreen",
-            Event::Login(_) => "Login",
-            Event::LogOff(_) => "LogOff",
-            Event::Refresh(_) => "Refresh",
-            Event::SetNumberOfCpus(_) => "SetNumberOfCpus",
-            Event::Shutdown(_) => "Shutdown",
+        f.debug_struct("LazyEvent")
+            .field("name", &self.name)
+            .finish_non_exhaustive()
+    }
+}
+
+impl LazyEvent {
+    /// Try to parse the name of an event from a protocol frame.
+    ///
+    /// This checks that the whole frame is well-formed JSON, in a single pass.
+    pub fn parse_frame(data: &str) -> Result<Self, OgaError> {
+        #[derive(Deserialize)]
+        struct Header {
+            #[serde(rename = "__name__")]
+            name: String,
+        }
+
+        let header: Header = serde_json::from_str(data).map_err(|e| e.to_string())?;
+        let lazy = Self {
+            name: header.name,
+            raw: Zeroizing::new(data.to_string()),
+            parsed: OnceLock::new(),
         };
+        Ok(lazy)
+    }
+
+    /// Return the protocol wire name of this event.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
 
-        write!(f, "{}", kind)
+    /// Return the original JSON content of this event.
+    pub fn raw(&self) -> &str {
+        &self.raw
+    }
+
+    /// Return the fully parsed event.
+    pub fn event(&self) -> Result<&Event, OgaError> {
+        if let Some(event) = self.parsed.get() {
+            return Ok(event);
+        }
+        let event = Event::parse_frame(self.raw.as_bytes())?;
+        Ok(self.parsed.get_or_init(|| event))
+    }
+
+    /// Consume this and return the fully parsed event.
+    pub fn into_event(self) -> Result<Event, OgaError> {
+        match self.parsed.into_inner() {
+            Some(event) => Ok(event),
+            None => Event::parse_frame(self.raw.as_bytes()),
+        }
     }
 }
 
+/// Event message from host, tagged with the label of its channel.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct TaggedEvent {
+    /// Label of the channel where this event was received.
+    pub channel: String,
+    /// Event message.
+    pub event: Event,
+    /// Original JSON content of the frame, if retained.
+    ///
+    /// This is only available when enabled via `OgaBuilder::retain_raw_frames`,
+    /// and never for `login` events. Content is shared, thus cheap to clone.
+    #[serde(
+        skip_serializing_if = "Option::is_none",
+        serialize_with = "serialize_raw"
+    )]
+    pub raw: Option<Bytes>,
+}
+
+/// Serialize retained frame content as a string.
+fn serialize_raw<S: Serializer>(raw: &Option<Bytes>, serializer: S) -> Result<S::Ok, S::Error> {
+    let text = raw.as_deref().map(String::from_utf8_lossy);
+    text.serialize(serializer)
+}
+
 /// `api-version` event.
-#[derive(Clone, Debug, Deserialize)]
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Deserialize, Serialize)]
 pub struct ApiVersion {
     #[serde(rename = "apiVersion")]
     pub api_version: u8,
 }
 
 /// `echo` event.
-#[derive(Clone, Debug, Deserialize)]
-pub struct Echo {}
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash, Deserialize, Serialize)]
+pub struct Echo {
+    /// Probe content, to be sent back unchanged.
+    #[serde(flatten)]
+    pub payload: serde_json::Map<String, serde_json::Value>,
+}
+
+impl Echo {
+    /// Return the identifier of the probe this answers, if any (see `commands::EchoProbe`).
+    pub fn probe_id(&self) -> Option<&str> {
+        self.payload.get("id")?.as_str()
+    }
+}
 
 /// `hibernate` event.
-#[derive(Clone, Debug, Deserialize)]
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Deserialize, Serialize)]
 pub struct Hibernate {}
 
 /// `lifecycle-event` event.
-#[derive(Clone, Debug, Deserialize)]
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Deserialize, Serialize)]
 pub struct LifecycleEvent {}
 
 /// `lock-screen` event.
-#[derive(Clone, Debug, Deserialize)]
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Deserialize, Serialize)]
 pub struct LockScreen {}
 
 /// `login` event.
-#[derive(Clone, Debug, Deserialize)]
-pub struct Login {}
+///
+/// The password is never serialized, so that re-encoding or logging an
+/// event does not leak it.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub struct Login {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub username: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub domain: Option<String>,
+    #[serde(default, skip_serializing)]
+    pub password: Option<Secret>,
+}
 
 /// `log-off` event.
-#[derive(Clone, Debug, Deserialize)]
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Deserialize, Serialize)]
 pub struct LogOff {}
 
 /// `refresh` event.
-#[derive(Clone, Debug, Deserialize)]
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Deserialize, Serialize)]
 pub struct Refresh {
     #[serde(rename = "apiVersion")]
     pub api_version: u8,
 }
 
 /// `set-number-of-cpus` event.
-#[derive(Clone, Debug, Deserialize)]
-pub struct SetNumberOfCpus {}
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Deserialize, Serialize)]
+pub struct SetNumberOfCpus {
+    pub count: u32,
+}
 
 /// `shutdown` event.
-#[derive(Clone, Debug, Deserialize)]
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Deserialize, Serialize)]
 pub struct Shutdown {
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub message: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub timeout: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub reboot: Option<String>,
 }
+
+impl Shutdown {
+    /// Whether a reboot (instead of a power-off) was requested.
+    pub fn is_reboot(&self) -> bool {
+        self.reboot
+            .as_deref()
+            .map(|r| r.eq_ignore_ascii_case("true"))
+            .unwrap_or(false)
+    }
+}
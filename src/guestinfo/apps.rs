@@ -0,0 +1,215 @@
+use crate::commands::Applications;
+use crate::errors::OgaError;
+use futures::future::BoxFuture;
+#[cfg(any(feature = "apps-dpkg", feature = "apps-flatpak", feature = "apps-rpm"))]
+use futures::FutureExt;
+#[cfg(any(feature = "apps-dpkg", feature = "apps-flatpak", feature = "apps-rpm"))]
+use std::path::Path;
+
+/// Source of installed applications, e.g. a package manager.
+pub trait AppSource: std::fmt::Debug + Send + Sync {
+    /// Whether this source is present on the guest.
+    fn is_available(&self) -> bool;
+
+    /// Return installed applications, as `name-version` strings.
+    fn applications(&self) -> BoxFuture<'_, Result<Vec<String>, OgaError>>;
+}
+
+/// Return all enabled sources which are present on the guest.
+pub fn detect_app_sources() -> Vec<Box<dyn AppSource>> {
+    #[allow(unused_mut)]
+    let mut sources: Vec<Box<dyn AppSource>> = Vec::new();
+    #[cfg(feature = "apps-rpm")]
+    sources.push(Box::new(RpmSource::new()));
+    #[cfg(feature = "apps-dpkg")]
+    sources.push(Box::new(DpkgSource::new()));
+    #[cfg(feature = "apps-flatpak")]
+    sources.push(Box::new(FlatpakSource::new()));
+
+    sources.retain(|s| s.is_available());
+    log::trace!("detected application sources: {:?}", sources);
+    sources
+}
+
+/// Collect installed applications from the given sources.
+///
+/// Results from all sources are merged, sorted and deduplicated. A failing
+/// source is logged and skipped, unless all of them fail.
+pub async fn applications(sources: &[Box<dyn AppSource>]) -> Result<Applications, OgaError> {
+    let mut report = Applications::default();
+    let mut last_err = None;
+    for source in sources {
+        match source.applications().await {
+            Ok(apps) => report.applications.extend(apps),
+            Err(e) => {
+                log::warn!("failed to list applications from {:?}: {}", source, e);
+                last_err = Some(e);
+            }
+        }
+    }
+    if let (true, Some(e)) = (report.applications.is_empty(), last_err) {
+        return Err(e);
+    }
+
+    report.applications.sort();
+    report.applications.dedup();
+    Ok(report)
+}
+
+/// Run a listing tool, returning its non-empty output lines.
+#[cfg(any(feature = "apps-flatpak", feature = "apps-rpm"))]
+async fn output_lines(program: &str, args: &[&str]) -> Result<Vec<String>, OgaError> {
+    let output = tokio::process::Command::new(program)
+        .args(args)
+        .output()
+        .await
+        .map_err(|e| format!("failed to run {}: {}", program, e))?;
+    if !output.status.success() {
+        return Err(format!("{} failed: {}", program, output.status).into());
+    }
+    let lines = String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .map(str::trim)
+        .filter(|l| !l.is_empty())
+        .map(String::from)
+        .collect();
+    Ok(lines)
+}
+
+/// Installed RPM packages.
+#[cfg(feature = "apps-rpm")]
+#[derive(Clone, Debug, Default)]
+pub struct RpmSource {}
+
+#[cfg(feature = "apps-rpm")]
+impl RpmSource {
+    /// RPM database locations, current and legacy.
+    const DB_PATHS: &'static [&'static str] = &["/usr/lib/sysimage/rpm", "/var/lib/rpm"];
+
+    /// Return a new RPM source.
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+#[cfg(feature = "apps-rpm")]
+impl AppSource for RpmSource {
+    fn is_available(&self) -> bool {
+        Self::DB_PATHS.iter().any(|p| Path::new(p).is_dir())
+    }
+
+    fn applications(&self) -> BoxFuture<'_, Result<Vec<String>, OgaError>> {
+        output_lines(
+            "rpm",
+            &[
+                "--query",
+                "--all",
+                "--queryformat",
+                "%{NAME}-%{VERSION}-%{RELEASE}\\n",
+            ],
+        )
+        .boxed()
+    }
+}
+
+/// Installed Debian packages, from the dpkg status database.
+#[cfg(feature = "apps-dpkg")]
+#[derive(Clone, Debug, Default)]
+pub struct DpkgSource {}
+
+#[cfg(feature = "apps-dpkg")]
+impl DpkgSource {
+    /// dpkg status database.
+    const STATUS_PATH: &'static str = "/var/lib/dpkg/status";
+
+    /// Return a new dpkg source.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Parse installed packages from the content of a status database.
+    fn parse_status(content: &str) -> Vec<String> {
+        let mut apps = Vec::new();
+        for stanza in content.split("\n\n") {
+            let (mut name, mut version, mut installed) = (None, None, false);
+            for line in stanza.lines() {
+                if let Some(value) = line.strip_prefix("Package: ") {
+                    name = Some(value.trim());
+                } else if let Some(value) = line.strip_prefix("Version: ") {
+                    version = Some(value.trim());
+                } else if let Some(value) = line.strip_prefix("Status: ") {
+                    installed = value.trim().ends_with(" installed");
+                }
+            }
+            if let (Some(name), Some(version), true) = (name, version, installed) {
+                apps.push(format!("{}-{}", name, version));
+            }
+        }
+        apps
+    }
+}
+
+#[cfg(feature = "apps-dpkg")]
+impl AppSource for DpkgSource {
+    fn is_available(&self) -> bool {
+        Path::new(Self::STATUS_PATH).is_file()
+    }
+
+    fn applications(&self) -> BoxFuture<'_, Result<Vec<String>, OgaError>> {
+        async {
+            let content =
+                tokio::task::spawn_blocking(|| std::fs::read_to_string(Self::STATUS_PATH))
+                    .await
+                    .map_err(|e| format!("dpkg status task failed: {}", e))?
+                    .map_err(|e| format!("failed to read '{}': {}", Self::STATUS_PATH, e))?;
+            Ok(Self::parse_status(&content))
+        }
+        .boxed()
+    }
+}
+
+/// Installed Flatpak applications.
+#[cfg(feature = "apps-flatpak")]
+#[derive(Clone, Debug, Default)]
+pub struct FlatpakSource {}
+
+#[cfg(feature = "apps-flatpak")]
+impl FlatpakSource {
+    /// System-wide Flatpak installation.
+    const INSTALL_PATH: &'static str = "/var/lib/flatpak";
+
+    /// Return a new Flatpak source.
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+#[cfg(feature = "apps-flatpak")]
+impl AppSource for FlatpakSource {
+    fn is_available(&self) -> bool {
+        Path::new(Self::INSTALL_PATH).is_dir()
+    }
+
+    fn applications(&self) -> BoxFuture<'_, Result<Vec<String>, OgaError>> {
+        async {
+            let lines = output_lines(
+                "flatpak",
+                &["list", "--system", "--app", "--columns=application,version"],
+            )
+            .await?;
+            let apps = lines
+                .iter()
+                .map(|line| {
+                    let mut cols = line.split('\t');
+                    let app = cols.next().unwrap_or_default();
+                    match cols.next().map(str::trim).filter(|v| !v.is_empty()) {
+                        Some(version) => format!("{}-{}", app, version),
+                        None => app.to_string(),
+                    }
+                })
+                .collect();
+            Ok(apps)
+        }
+        .boxed()
+    }
+}
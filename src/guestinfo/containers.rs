@@ -0,0 +1,148 @@
+use crate::commands::{Container, Containers};
+use crate::errors::OgaError;
+use serde::Deserialize;
+use std::path::{Path, PathBuf};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::UnixStream;
+
+/// Docker-compatible endpoint listing running containers.
+static LIST_PATH: &str = "/containers/json";
+
+/// Maximum size of an API response.
+const MAX_RESPONSE_SIZE: u64 = 16 * 1024 * 1024;
+
+/// Container runtime, reachable via a Docker-compatible API socket.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ContainerRuntime {
+    name: String,
+    socket: PathBuf,
+}
+
+impl ContainerRuntime {
+    /// Return a runtime listening on the given socket.
+    pub fn new(name: impl Into<String>, socket: impl Into<PathBuf>) -> Self {
+        Self {
+            name: name.into(),
+            socket: socket.into(),
+        }
+    }
+
+    /// Docker, at its default system socket.
+    pub fn docker() -> Self {
+        Self::new("docker", "/var/run/docker.sock")
+    }
+
+    /// Podman, at its default system socket.
+    pub fn podman() -> Self {
+        Self::new("podman", "/run/podman/podman.sock")
+    }
+
+    /// All runtimes with well-known sockets.
+    pub fn defaults() -> Vec<Self> {
+        vec![Self::docker(), Self::podman()]
+    }
+
+    /// Runtime name.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Whether the runtime socket is present.
+    pub fn is_available(&self) -> bool {
+        self.socket.exists()
+    }
+
+    /// List running containers.
+    pub async fn list(&self) -> Result<Vec<Container>, OgaError> {
+        let body = http_get(&self.socket, LIST_PATH).await?;
+        let entries: Vec<ApiContainer> = serde_json::from_slice(&body)
+            .map_err(|e| format!("invalid {} containers list: {}", self.name, e))?;
+        Ok(entries.into_iter().map(Container::from).collect())
+    }
+}
+
+/// Container entry, as returned by the Docker-compatible API.
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct ApiContainer {
+    id: String,
+    #[serde(default)]
+    names: Vec<String>,
+    #[serde(default)]
+    image: String,
+    #[serde(default)]
+    command: String,
+    #[serde(default)]
+    status: String,
+}
+
+impl From<ApiContainer> for Container {
+    fn from(entry: ApiContainer) -> Self {
+        Self {
+            id: entry.id,
+            names: entry
+                .names
+                .into_iter()
+                .map(|n| n.trim_start_matches('/').to_string())
+                .collect(),
+            image: entry.image,
+            command: entry.command,
+            status: entry.status,
+        }
+    }
+}
+
+/// List running containers from all available runtimes.
+///
+/// Runtimes without a socket are skipped. A failing runtime is logged and
+/// skipped, unless all of them fail.
+pub async fn containers(runtimes: &[ContainerRuntime]) -> Result<Containers, OgaError> {
+    let mut report = Containers::default();
+    let mut last_err = None;
+    for runtime in runtimes.iter().filter(|r| r.is_available()) {
+        match runtime.list().await {
+            Ok(list) => report.list.extend(list),
+            Err(e) => {
+                log::warn!("failed to list {} containers: {}", runtime.name, e);
+                last_err = Some(e);
+            }
+        }
+    }
+    if let (true, Some(e)) = (report.list.is_empty(), last_err) {
+        return Err(e);
+    }
+    Ok(report)
+}
+
+/// Perform a plain HTTP/1.0 GET request over a unix socket, returning the body.
+async fn http_get(socket: &Path, path: &str) -> Result<Vec<u8>, OgaError> {
+    let mut stream = UnixStream::connect(socket)
+        .await
+        .map_err(|e| format!("failed to connect to '{}': {}", socket.display(), e))?;
+    let request = format!(
+        "GET {} HTTP/1.0\r\nHost: localhost\r\nAccept: application/json\r\n\r\n",
+        path
+    );
+    stream.write_all(request.as_bytes()).await?;
+
+    let mut response = Vec::new();
+    stream
+        .take(MAX_RESPONSE_SIZE)
+        .read_to_end(&mut response)
+        .await?;
+
+    let split = response
+        .windows(4)
+        .position(|w| w == b"\r\n\r\n")
+        .ok_or("truncated HTTP response")?;
+    let head = String::from_utf8_lossy(&response[..split]);
+    let status = head
+        .lines()
+        .next()
+        .and_then(|l| l.split_whitespace().nth(1))
+        .unwrap_or_default();
+    if status != "200" {
+        return Err(format!("unexpected HTTP status '{}' for '{}'", status, path).into());
+    }
+    Ok(response.split_off(split + 4))
+}
@@ -0,0 +1,46 @@
+use crate::commands::{DiskMapping, MappedDisk};
+use crate::errors::OgaError;
+use std::path::Path;
+
+/// Base sysfs directory for block devices.
+static SYSFS_BLOCK_DIR: &str = "/sys/block";
+
+/// Per-device serial attributes, in lookup order.
+///
+/// virtio-blk exposes `serial` directly, while NVMe and some SCSI
+/// drivers expose it under the underlying device.
+static SERIAL_ATTRS: &[&str] = &["serial", "device/serial"];
+
+/// Build the disk mapping from sysfs block devices.
+///
+/// Devices without a serial (e.g. loop or device-mapper ones) are skipped.
+pub async fn disk_mapping() -> Result<DiskMapping, OgaError> {
+    tokio::task::spawn_blocking(|| scan_block_devices(Path::new(SYSFS_BLOCK_DIR)))
+        .await
+        .map_err(|e| format!("disk mapping task failed: {}", e))?
+}
+
+/// Scan block devices under the given sysfs directory.
+fn scan_block_devices(base: &Path) -> Result<DiskMapping, OgaError> {
+    let entries = std::fs::read_dir(base)
+        .map_err(|e| format!("failed to read '{}': {}", base.display(), e))?;
+
+    let mut report = DiskMapping::default();
+    for entry in entries {
+        let entry = entry.map_err(|e| format!("failed to read '{}': {}", base.display(), e))?;
+        let name = entry.file_name().to_string_lossy().into_owned();
+        let serial = SERIAL_ATTRS.iter().find_map(|attr| {
+            let content = std::fs::read_to_string(entry.path().join(attr)).ok()?;
+            Some(content.trim().to_string()).filter(|s| !s.is_empty())
+        });
+        if let Some(serial) = serial {
+            let disk = MappedDisk {
+                name: format!("/dev/{}", name),
+            };
+            report.mapping.insert(serial, disk);
+        }
+    }
+
+    log::trace!("mapped {} disks", report.mapping.len());
+    Ok(report)
+}
@@ -0,0 +1,17 @@
+use crate::commands::HostName;
+use crate::errors::OgaError;
+
+/// Detect the guest host name.
+pub async fn host_name() -> Result<HostName, OgaError> {
+    let mut buf = [0u8; 256];
+    // SAFETY: the buffer is valid for writes of its whole length, minus
+    // a trailing NUL which is always kept.
+    let res = unsafe { libc::gethostname(buf.as_mut_ptr() as *mut libc::c_char, buf.len() - 1) };
+    if res != 0 {
+        let err = std::io::Error::last_os_error();
+        return Err(format!("failed to get host name: {}", err).into());
+    }
+    let len = buf.iter().position(|b| *b == 0).unwrap_or(buf.len());
+    let name = String::from_utf8_lossy(&buf[..len]).into_owned();
+    Ok(HostName { name })
+}
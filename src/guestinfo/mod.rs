@@ -0,0 +1,65 @@
+/*! Collectors for guest information reports.
+
+These gather details about the guest system and return them as the
+corresponding [commands](../commands/index.html), ready to be sent to the host.
+Collectors backed by Linux-specific interfaces (e.g. sysfs) are only available
+there, while Windows guests use registry and WMI based equivalents.
+
+A [`Reporter`] periodically runs built-in and custom [`Collector`]s,
+sending their reports whenever the content changes.
+*/
+
+mod apps;
+#[cfg(all(feature = "containers", unix))]
+mod containers;
+#[cfg(target_os = "linux")]
+mod disks;
+#[cfg(unix)]
+mod host;
+#[cfg(target_os = "linux")]
+mod network;
+mod os;
+mod reporter;
+#[cfg(unix)]
+mod timezone;
+#[cfg(target_os = "linux")]
+mod usage;
+#[cfg(windows)]
+mod windows;
+
+#[cfg(feature = "apps-dpkg")]
+pub use apps::DpkgSource;
+#[cfg(feature = "apps-flatpak")]
+pub use apps::FlatpakSource;
+#[cfg(feature = "apps-rpm")]
+pub use apps::RpmSource;
+pub use apps::{applications, detect_app_sources, AppSource};
+#[cfg(all(feature = "containers", unix))]
+pub use containers::{containers, ContainerRuntime};
+#[cfg(target_os = "linux")]
+pub use disks::disk_mapping;
+#[cfg(unix)]
+pub use host::host_name;
+#[cfg(target_os = "linux")]
+pub use network::network_interfaces;
+#[cfg(all(feature = "netlink", target_os = "linux"))]
+pub use network::NetworkWatcher;
+pub use os::OsRelease;
+#[cfg(unix)]
+pub use os::{os_info, os_version};
+#[cfg(all(feature = "containers", unix))]
+pub use reporter::ContainersCollector;
+#[cfg(unix)]
+pub use reporter::TimezoneCollector;
+pub use reporter::{
+    ApplicationsCollector, Collector, HostNameCollector, OsInfoCollector, OsVersionCollector,
+    RefreshHandle, Reporter,
+};
+#[cfg(target_os = "linux")]
+pub use reporter::{DiskMappingCollector, DisksUsageCollector, NetworkCollector};
+#[cfg(unix)]
+pub use timezone::timezone;
+#[cfg(target_os = "linux")]
+pub use usage::{disks_usage, DiskUsageMonitor, UsageAlert};
+#[cfg(windows)]
+pub use windows::{host_name, os_info, os_version};
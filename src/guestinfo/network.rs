@@ -0,0 +1,255 @@
+use crate::commands::{NetworkInterface, NetworkInterfaces};
+use crate::errors::OgaError;
+use std::collections::BTreeMap;
+use std::net::{Ipv4Addr, Ipv6Addr};
+
+/// Loopback interface, never reported.
+static LOOPBACK_NAME: &str = "lo";
+
+/// Collect network interfaces and their addresses.
+pub async fn network_interfaces() -> Result<NetworkInterfaces, OgaError> {
+    tokio::task::spawn_blocking(scan_interfaces)
+        .await
+        .map_err(|e| format!("network scan task failed: {}", e))?
+}
+
+/// Scan interface addresses, grouped by interface name.
+fn scan_interfaces() -> Result<NetworkInterfaces, OgaError> {
+    let mut interfaces: BTreeMap<String, NetworkInterface> = BTreeMap::new();
+
+    // SAFETY: the list returned by `getifaddrs` is only read before being
+    // released by `freeifaddrs`, and addresses are cast per their family.
+    unsafe {
+        let mut head: *mut libc::ifaddrs = std::ptr::null_mut();
+        if libc::getifaddrs(&mut head) != 0 {
+            let err = std::io::Error::last_os_error();
+            return Err(format!("failed to list interface addresses: {}", err).into());
+        }
+
+        let mut cursor = head;
+        while let Some(ifa) = cursor.as_ref() {
+            cursor = ifa.ifa_next;
+            let name = std::ffi::CStr::from_ptr(ifa.ifa_name)
+                .to_string_lossy()
+                .into_owned();
+            if name == LOOPBACK_NAME {
+                continue;
+            }
+            let entry = interfaces
+                .entry(name.clone())
+                .or_insert_with(|| NetworkInterface {
+                    name,
+                    ..Default::default()
+                });
+            let addr = match ifa.ifa_addr.as_ref() {
+                Some(addr) => addr,
+                None => continue,
+            };
+
+            match i32::from(addr.sa_family) {
+                libc::AF_INET => {
+                    let sin = &*(ifa.ifa_addr as *const libc::sockaddr_in);
+                    let ip = Ipv4Addr::from(u32::from_be(sin.sin_addr.s_addr));
+                    entry.inet.push(ip.to_string());
+                }
+                libc::AF_INET6 => {
+                    let sin6 = &*(ifa.ifa_addr as *const libc::sockaddr_in6);
+                    let ip = Ipv6Addr::from(sin6.sin6_addr.s6_addr);
+                    entry.inet6.push(ip.to_string());
+                }
+                libc::AF_PACKET => {
+                    let sll = &*(ifa.ifa_addr as *const libc::sockaddr_ll);
+                    let len = usize::from(sll.sll_halen).min(sll.sll_addr.len());
+                    let hw: Vec<String> = sll.sll_addr[..len]
+                        .iter()
+                        .map(|b| format!("{:02x}", b))
+                        .collect();
+                    entry.hw = hw.join(":");
+                }
+                _ => {}
+            }
+        }
+        libc::freeifaddrs(head);
+    }
+
+    Ok(NetworkInterfaces {
+        interfaces: interfaces.into_values().collect(),
+    })
+}
+
+#[cfg(feature = "netlink")]
+pub use watcher::NetworkWatcher;
+
+#[cfg(feature = "netlink")]
+mod watcher {
+    use super::network_interfaces;
+    use crate::commands::NetworkInterfaces;
+    use crate::errors::OgaError;
+    use crate::OgaCommandSender;
+    use std::os::unix::io::{AsRawFd, FromRawFd, OwnedFd};
+    use tokio::io::unix::AsyncFd;
+    use tokio::time::{self, Duration};
+
+    /// Default settle time after a change notification.
+    const DEFAULT_DEBOUNCE_MSECS: u64 = 500;
+
+    /// Watcher reporting network interfaces to the host, on changes.
+    ///
+    /// Link and address changes are detected via route-netlink notifications,
+    /// and interfaces are also re-scanned at a fixed interval as a fallback.
+    #[derive(Debug)]
+    pub struct NetworkWatcher {
+        interval: Duration,
+        debounce: Duration,
+    }
+
+    impl NetworkWatcher {
+        /// Return a watcher, re-scanning interfaces at least at the given interval.
+        pub fn new(interval: Duration) -> Self {
+            Self {
+                interval,
+                debounce: Duration::from_millis(DEFAULT_DEBOUNCE_MSECS),
+            }
+        }
+
+        /// Time to wait for further changes before re-scanning (default: 500ms).
+        ///
+        /// This coalesces bursts of notifications, e.g. on interface bring-up.
+        pub fn debounce(mut self, debounce: Duration) -> Self {
+            self.debounce = debounce;
+            self
+        }
+
+        /// Watch interfaces, sending a `network-interfaces` command on each change.
+        ///
+        /// Current interfaces are reported when starting. This only returns on
+        /// netlink socket failures or on failures to deliver commands; scan
+        /// failures are logged and retried.
+        pub async fn run(self, sender: OgaCommandSender) -> OgaError {
+            let socket = match NetlinkSocket::open() {
+                Ok(socket) => socket,
+                Err(e) => return e,
+            };
+            let mut ticker = time::interval(self.interval);
+            let mut reported: Option<NetworkInterfaces> = None;
+
+            loop {
+                tokio::select! {
+                    _ = ticker.tick() => {},
+                    res = socket.changed() => {
+                        if let Err(e) = res {
+                            return e;
+                        }
+                        time::sleep(self.debounce).await;
+                        if let Err(e) = socket.drain() {
+                            return e;
+                        }
+                        log::trace!("network configuration changed");
+                    },
+                }
+
+                let current = match network_interfaces().await {
+                    Ok(report) => report,
+                    Err(e) => {
+                        log::warn!("failed to scan network interfaces: {}", e);
+                        continue;
+                    }
+                };
+                if reported.as_ref() == Some(&current) {
+                    continue;
+                }
+
+                if let Err(e) = sender.send(Box::new(current.clone())).await {
+                    return e;
+                }
+                log::debug!("reported {} network interfaces", current.interfaces.len());
+                reported = Some(current);
+            }
+        }
+    }
+
+    /// Route-netlink socket, subscribed to link and address changes.
+    #[derive(Debug)]
+    struct NetlinkSocket {
+        fd: AsyncFd<OwnedFd>,
+    }
+
+    impl NetlinkSocket {
+        /// Open and subscribe a non-blocking netlink socket.
+        fn open() -> Result<Self, OgaError> {
+            let groups = libc::RTMGRP_LINK | libc::RTMGRP_IPV4_IFADDR | libc::RTMGRP_IPV6_IFADDR;
+
+            // SAFETY: the new descriptor is immediately owned, and the address
+            // is a fully-initialized `sockaddr_nl`.
+            let fd = unsafe {
+                let raw = libc::socket(
+                    libc::AF_NETLINK,
+                    libc::SOCK_RAW | libc::SOCK_NONBLOCK | libc::SOCK_CLOEXEC,
+                    libc::NETLINK_ROUTE,
+                );
+                if raw < 0 {
+                    let err = std::io::Error::last_os_error();
+                    return Err(format!("failed to open netlink socket: {}", err).into());
+                }
+                let fd = OwnedFd::from_raw_fd(raw);
+
+                let mut addr: libc::sockaddr_nl = std::mem::zeroed();
+                addr.nl_family = libc::AF_NETLINK as libc::sa_family_t;
+                addr.nl_groups = groups as u32;
+                let res = libc::bind(
+                    fd.as_raw_fd(),
+                    &addr as *const libc::sockaddr_nl as *const libc::sockaddr,
+                    std::mem::size_of::<libc::sockaddr_nl>() as libc::socklen_t,
+                );
+                if res != 0 {
+                    let err = std::io::Error::last_os_error();
+                    return Err(format!("failed to bind netlink socket: {}", err).into());
+                }
+                fd
+            };
+
+            let fd = AsyncFd::new(fd)?;
+            Ok(Self { fd })
+        }
+
+        /// Wait for change notifications.
+        async fn changed(&self) -> Result<(), OgaError> {
+            loop {
+                let mut guard = self.fd.readable().await?;
+                if self.drain()? > 0 {
+                    return Ok(());
+                }
+                guard.clear_ready();
+            }
+        }
+
+        /// Discard all pending notifications, returning their count.
+        fn drain(&self) -> Result<usize, OgaError> {
+            let mut buf = [0u8; 8192];
+            let mut count = 0;
+            loop {
+                // SAFETY: the buffer is valid for writes of its whole length.
+                let res = unsafe {
+                    libc::recv(
+                        self.fd.as_raw_fd(),
+                        buf.as_mut_ptr() as *mut libc::c_void,
+                        buf.len(),
+                        0,
+                    )
+                };
+                if res >= 0 {
+                    count += 1;
+                    continue;
+                }
+                let err = std::io::Error::last_os_error();
+                match err.kind() {
+                    std::io::ErrorKind::WouldBlock => return Ok(count),
+                    std::io::ErrorKind::Interrupted => continue,
+                    // Notifications were lost, which still means a change.
+                    _ if err.raw_os_error() == Some(libc::ENOBUFS) => count += 1,
+                    _ => return Err(format!("failed to read netlink socket: {}", err).into()),
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,181 @@
+#[cfg(unix)]
+use crate::commands::{OsInfo, OsVersion};
+use crate::errors::OgaError;
+use std::collections::BTreeMap;
+use std::path::Path;
+
+/// os-release locations, in lookup order.
+static OS_RELEASE_PATHS: &[&str] = &["/etc/os-release", "/usr/lib/os-release"];
+
+/// Operating system identification, from `os-release`.
+///
+/// See <https://www.freedesktop.org/software/systemd/man/os-release.html>.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct OsRelease {
+    fields: BTreeMap<String, String>,
+}
+
+impl OsRelease {
+    /// Load the local os-release file.
+    pub fn load() -> Result<Self, OgaError> {
+        let path = OS_RELEASE_PATHS
+            .iter()
+            .map(Path::new)
+            .find(|p| p.exists())
+            .ok_or("no os-release file found")?;
+        Self::from_file(path)
+    }
+
+    /// Load an os-release file from the given path.
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Self, OgaError> {
+        let path = path.as_ref();
+        let content = std::fs::read_to_string(path)
+            .map_err(|e| format!("failed to read '{}': {}", path.display(), e))?;
+        Ok(Self::parse(&content))
+    }
+
+    /// Parse os-release content.
+    ///
+    /// Comments, blank lines and malformed assignments are ignored.
+    pub fn parse(content: &str) -> Self {
+        let fields = content
+            .lines()
+            .map(str::trim)
+            .filter(|l| !l.is_empty() && !l.starts_with('#'))
+            .filter_map(|l| l.split_once('='))
+            .filter(|(key, _)| !key.is_empty())
+            .map(|(key, value)| (key.to_string(), unquote(value.trim())))
+            .collect();
+        Self { fields }
+    }
+
+    /// Return the value of a field, if present and non-empty.
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.fields
+            .get(key)
+            .map(String::as_str)
+            .filter(|v| !v.is_empty())
+    }
+
+    /// Operating system name (`NAME`), defaulting to `Linux`.
+    pub fn name(&self) -> &str {
+        self.get("NAME").unwrap_or("Linux")
+    }
+
+    /// Operating system identifier (`ID`), defaulting to `linux`.
+    pub fn id(&self) -> &str {
+        self.get("ID").unwrap_or("linux")
+    }
+
+    /// Version identifier (`VERSION_ID`), e.g. `39`.
+    pub fn version_id(&self) -> Option<&str> {
+        self.get("VERSION_ID")
+    }
+
+    /// Release codename (`VERSION_CODENAME`), e.g. `bookworm`.
+    pub fn version_codename(&self) -> Option<&str> {
+        self.get("VERSION_CODENAME")
+    }
+
+    /// Human-readable name (`PRETTY_NAME`), defaulting to name and version.
+    pub fn pretty_name(&self) -> String {
+        match (self.get("PRETTY_NAME"), self.version_id()) {
+            (Some(pretty), _) => pretty.to_string(),
+            (None, Some(version)) => format!("{} {}", self.name(), version),
+            (None, None) => self.name().to_string(),
+        }
+    }
+}
+
+/// Remove shell-style quoting from an os-release value.
+fn unquote(value: &str) -> String {
+    let quote = match value.chars().next() {
+        Some(q @ ('"' | '\'')) if value.len() >= 2 && value.ends_with(q) => q,
+        _ => return value.to_string(),
+    };
+    let inner = &value[1..value.len() - 1];
+    if quote == '\'' {
+        return inner.to_string();
+    }
+
+    let mut out = String::with_capacity(inner.len());
+    let mut chars = inner.chars();
+    while let Some(c) = chars.next() {
+        match (c, chars.clone().next()) {
+            ('\\', Some(next @ ('"' | '\\' | '$' | '`'))) => {
+                out.push(next);
+                chars.next();
+            }
+            _ => out.push(c),
+        }
+    }
+    out
+}
+
+/// Detect the operating system version.
+#[cfg(unix)]
+pub async fn os_version() -> Result<OsVersion, OgaError> {
+    let release = tokio::task::spawn_blocking(OsRelease::load)
+        .await
+        .map_err(|e| format!("os-release task failed: {}", e))??;
+    Ok(OsVersion {
+        version: release.pretty_name(),
+    })
+}
+
+/// Detect detailed operating system information, including kernel details.
+#[cfg(unix)]
+pub async fn os_info() -> Result<OsInfo, OgaError> {
+    let (release, uname) =
+        tokio::task::spawn_blocking(|| Ok::<_, OgaError>((OsRelease::load()?, uname()?)))
+            .await
+            .map_err(|e| format!("os-info task failed: {}", e))??;
+    Ok(OsInfo {
+        version: release.version_id().unwrap_or_default().to_string(),
+        distribution: release.name().to_string(),
+        codename: release.version_codename().unwrap_or_default().to_string(),
+        arch: uname.machine,
+        os_type: uname.sysname.to_lowercase(),
+        kernel: uname.release,
+    })
+}
+
+/// Kernel identification, from `uname(2)`.
+#[cfg(unix)]
+#[derive(Clone, Debug)]
+struct Uname {
+    sysname: String,
+    release: String,
+    machine: String,
+}
+
+/// Query kernel identification.
+#[cfg(unix)]
+fn uname() -> Result<Uname, OgaError> {
+    // SAFETY: `uname` only writes into the provided struct, whose fields
+    // are NUL-terminated on success.
+    let uts = unsafe {
+        let mut uts: libc::utsname = std::mem::zeroed();
+        if libc::uname(&mut uts) != 0 {
+            let err = std::io::Error::last_os_error();
+            return Err(format!("uname failed: {}", err).into());
+        }
+        uts
+    };
+    Ok(Uname {
+        sysname: utsname_field(&uts.sysname),
+        release: utsname_field(&uts.release),
+        machine: utsname_field(&uts.machine),
+    })
+}
+
+/// Convert a NUL-terminated `utsname` field.
+#[cfg(unix)]
+fn utsname_field(field: &[libc::c_char]) -> String {
+    let raw: Vec<u8> = field
+        .iter()
+        .take_while(|c| **c != 0)
+        .map(|c| *c as u8)
+        .collect();
+    String::from_utf8_lossy(&raw).into_owned()
+}
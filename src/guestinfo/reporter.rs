@@ -0,0 +1,379 @@
+use super::AppSource;
+use crate::commands::AsFrame;
+use crate::errors::OgaError;
+use crate::events::Event;
+use crate::subscription::EventReceiver;
+use crate::{protocol, OgaCommandSender};
+use bytes::Bytes;
+use futures::future::BoxFuture;
+use futures::FutureExt;
+#[cfg(target_os = "linux")]
+use std::collections::BTreeSet;
+use std::sync::Arc;
+use tokio::sync::Notify;
+use tokio::time::{self, Duration, Instant};
+
+/// Shortest interval between runs of the same collector.
+const MIN_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Source of a periodic report to the host.
+///
+/// Implement this to plug custom data sources (e.g. license details or
+/// inventory) into a [`Reporter`], next to the built-in collectors.
+pub trait Collector: std::fmt::Debug + Send + Sync {
+    /// Collect the current data, as a command ready to be sent.
+    fn collect(&self) -> BoxFuture<'_, Result<Box<dyn AsFrame>, OgaError>>;
+}
+
+/// Collector for the `timezone` report.
+#[cfg(unix)]
+#[derive(Clone, Debug, Default)]
+pub struct TimezoneCollector {}
+
+#[cfg(unix)]
+impl Collector for TimezoneCollector {
+    fn collect(&self) -> BoxFuture<'_, Result<Box<dyn AsFrame>, OgaError>> {
+        async { Ok(Box::new(super::timezone().await?) as Box<dyn AsFrame>) }.boxed()
+    }
+}
+
+/// Collector for the `disk-mapping` report.
+#[cfg(target_os = "linux")]
+#[derive(Clone, Debug, Default)]
+pub struct DiskMappingCollector {}
+
+#[cfg(target_os = "linux")]
+impl Collector for DiskMappingCollector {
+    fn collect(&self) -> BoxFuture<'_, Result<Box<dyn AsFrame>, OgaError>> {
+        async { Ok(Box::new(super::disk_mapping().await?) as Box<dyn AsFrame>) }.boxed()
+    }
+}
+
+/// Collector for the `network-interfaces` report.
+#[cfg(target_os = "linux")]
+#[derive(Clone, Debug, Default)]
+pub struct NetworkCollector {
+    ignored_nics: BTreeSet<String>,
+}
+
+#[cfg(target_os = "linux")]
+impl NetworkCollector {
+    /// Names of interfaces to leave out of reports (default: none).
+    ///
+    /// This matches `ignored_nics` in the reference agent configuration.
+    pub fn ignored_nics(mut self, names: Vec<String>) -> Self {
+        self.ignored_nics = names.into_iter().collect();
+        self
+    }
+}
+
+#[cfg(target_os = "linux")]
+impl Collector for NetworkCollector {
+    fn collect(&self) -> BoxFuture<'_, Result<Box<dyn AsFrame>, OgaError>> {
+        async move {
+            let mut report = super::network_interfaces().await?;
+            report
+                .interfaces
+                .retain(|nic| !self.ignored_nics.contains(&nic.name));
+            Ok(Box::new(report) as Box<dyn AsFrame>)
+        }
+        .boxed()
+    }
+}
+
+/// Collector for the `disks-usage` report.
+#[cfg(target_os = "linux")]
+#[derive(Clone, Debug, Default)]
+pub struct DisksUsageCollector {
+    ignored_fs: BTreeSet<String>,
+    ignore_zero_size: bool,
+}
+
+#[cfg(target_os = "linux")]
+impl DisksUsageCollector {
+    /// Filesystem types to leave out of reports, e.g. `squashfs` (default: none).
+    ///
+    /// This matches `ignored_fs` in the reference agent configuration.
+    pub fn ignored_fs(mut self, types: Vec<String>) -> Self {
+        self.ignored_fs = types.into_iter().collect();
+        self
+    }
+
+    /// Whether to leave zero-sized filesystems out of reports (default: false).
+    ///
+    /// This matches `ignore_zero_size_fs` in the reference agent configuration.
+    pub fn ignore_zero_size(mut self, enabled: bool) -> Self {
+        self.ignore_zero_size = enabled;
+        self
+    }
+}
+
+#[cfg(target_os = "linux")]
+impl Collector for DisksUsageCollector {
+    fn collect(&self) -> BoxFuture<'_, Result<Box<dyn AsFrame>, OgaError>> {
+        async move {
+            let mut report = super::disks_usage().await?;
+            report.disks.retain(|disk| {
+                let zero_size = self.ignore_zero_size && disk.total == 0;
+                !zero_size && !self.ignored_fs.contains(&disk.fs)
+            });
+            Ok(Box::new(report) as Box<dyn AsFrame>)
+        }
+        .boxed()
+    }
+}
+
+/// Collector for the `host-name` report.
+#[derive(Clone, Debug, Default)]
+pub struct HostNameCollector {}
+
+impl Collector for HostNameCollector {
+    fn collect(&self) -> BoxFuture<'_, Result<Box<dyn AsFrame>, OgaError>> {
+        async { Ok(Box::new(super::host_name().await?) as Box<dyn AsFrame>) }.boxed()
+    }
+}
+
+/// Collector for the `os-version` report.
+#[derive(Clone, Debug, Default)]
+pub struct OsVersionCollector {}
+
+impl Collector for OsVersionCollector {
+    fn collect(&self) -> BoxFuture<'_, Result<Box<dyn AsFrame>, OgaError>> {
+        async { Ok(Box::new(super::os_version().await?) as Box<dyn AsFrame>) }.boxed()
+    }
+}
+
+/// Collector for the `os-info` report.
+#[derive(Clone, Debug, Default)]
+pub struct OsInfoCollector {}
+
+impl Collector for OsInfoCollector {
+    fn collect(&self) -> BoxFuture<'_, Result<Box<dyn AsFrame>, OgaError>> {
+        async { Ok(Box::new(super::os_info().await?) as Box<dyn AsFrame>) }.boxed()
+    }
+}
+
+/// Collector for the `applications` report.
+#[derive(Debug)]
+pub struct ApplicationsCollector {
+    sources: Vec<Box<dyn AppSource>>,
+}
+
+impl ApplicationsCollector {
+    /// Return a collector querying the given sources.
+    pub fn new(sources: Vec<Box<dyn AppSource>>) -> Self {
+        Self { sources }
+    }
+}
+
+impl Default for ApplicationsCollector {
+    fn default() -> Self {
+        Self::new(super::detect_app_sources())
+    }
+}
+
+impl Collector for ApplicationsCollector {
+    fn collect(&self) -> BoxFuture<'_, Result<Box<dyn AsFrame>, OgaError>> {
+        async move {
+            let report = super::applications(&self.sources).await?;
+            Ok(Box::new(report) as Box<dyn AsFrame>)
+        }
+        .boxed()
+    }
+}
+
+/// Collector for the `containers` report.
+#[cfg(all(feature = "containers", unix))]
+#[derive(Debug)]
+pub struct ContainersCollector {
+    runtimes: Vec<super::ContainerRuntime>,
+}
+
+#[cfg(all(feature = "containers", unix))]
+impl ContainersCollector {
+    /// Return a collector querying the given runtimes.
+    pub fn new(runtimes: Vec<super::ContainerRuntime>) -> Self {
+        Self { runtimes }
+    }
+}
+
+#[cfg(all(feature = "containers", unix))]
+impl Default for ContainersCollector {
+    fn default() -> Self {
+        Self::new(super::ContainerRuntime::defaults())
+    }
+}
+
+#[cfg(all(feature = "containers", unix))]
+impl Collector for ContainersCollector {
+    fn collect(&self) -> BoxFuture<'_, Result<Box<dyn AsFrame>, OgaError>> {
+        async move {
+            let report = super::containers(&self.runtimes).await?;
+            Ok(Box::new(report) as Box<dyn AsFrame>)
+        }
+        .boxed()
+    }
+}
+
+/// Scheduled collector, with the last report sent.
+#[derive(Debug)]
+struct Entry {
+    collector: Box<dyn Collector>,
+    interval: Duration,
+    next: Instant,
+    reported: Option<Bytes>,
+}
+
+/// Reporter task, periodically running collectors and sending their reports.
+#[derive(Debug, Default)]
+pub struct Reporter {
+    entries: Vec<Entry>,
+    refresh: Arc<Notify>,
+    events: Option<EventReceiver<Event>>,
+}
+
+impl Reporter {
+    /// Return a reporter without collectors.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a collector, to be run at the given interval.
+    ///
+    /// Intervals shorter than one second are raised to that.
+    pub fn collector(mut self, collector: Box<dyn Collector>, interval: Duration) -> Self {
+        let interval = if interval < MIN_INTERVAL {
+            log::warn!(
+                "interval {:?} for collector {:?} too short, using {:?}",
+                interval,
+                collector,
+                MIN_INTERVAL
+            );
+            MIN_INTERVAL
+        } else {
+            interval
+        };
+        self.entries.push(Entry {
+            collector,
+            interval,
+            next: Instant::now(),
+            reported: None,
+        });
+        self
+    }
+
+    /// Resend all reports whenever the host asks for a `refresh` (default: disabled).
+    ///
+    /// This takes a subscription to events from the primary channel.
+    pub fn refresh_on(mut self, events: EventReceiver<Event>) -> Self {
+        self.events = Some(events);
+        self
+    }
+
+    /// Return a handle for forcing a full resend of all reports.
+    pub fn refresh_handle(&self) -> RefreshHandle {
+        RefreshHandle {
+            notify: self.refresh.clone(),
+        }
+    }
+
+    /// Run collectors, sending each report when its content changes.
+    ///
+    /// All collectors run once when starting, and again on each refresh,
+    /// sending their reports even if unchanged. This only returns once the
+    /// client is gone; collector failures and rejected reports are logged
+    /// and retried at the next interval.
+    pub async fn run(mut self, sender: OgaCommandSender) -> OgaError {
+        if self.entries.is_empty() {
+            return futures::future::pending().await;
+        }
+
+        loop {
+            let next = self.entries.iter().map(|e| e.next).min();
+            let refresh = tokio::select! {
+                _ = time::sleep_until(next.expect("no collectors")) => false,
+                _ = self.refresh.notified() => true,
+                refresh = Self::recv_refresh(&mut self.events) => {
+                    if !refresh {
+                        log::debug!("events subscription ended, refresh requests not tracked");
+                        self.events = None;
+                    }
+                    refresh
+                },
+            };
+            if refresh {
+                log::debug!("refreshing all reports");
+                let now = Instant::now();
+                for entry in &mut self.entries {
+                    entry.next = now;
+                    entry.reported = None;
+                }
+                continue;
+            }
+
+            let entry = self
+                .entries
+                .iter_mut()
+                .min_by_key(|e| e.next)
+                .expect("no collectors");
+            entry.next = Instant::now() + entry.interval;
+
+            let cmd = match entry.collector.collect().await {
+                Ok(cmd) => cmd,
+                Err(e) => {
+                    log::warn!("collector {:?} failed: {}", entry.collector, e);
+                    continue;
+                }
+            };
+            let frame = match protocol::encode_frame(cmd.as_ref()) {
+                Ok(frame) => frame,
+                Err(e) => {
+                    log::warn!("invalid '{}' report: {}", cmd.name(), e);
+                    continue;
+                }
+            };
+            if entry.reported.as_ref() == Some(&frame) {
+                continue;
+            }
+
+            let name = cmd.name().to_string();
+            if let Err(e) = sender.send(cmd).await {
+                // Commands rejected by the host (e.g. for API version) are retried later.
+                if sender.is_closed() {
+                    return e;
+                }
+                log::info!("'{}' report not sent, retrying later: {}", name, e.0);
+                continue;
+            }
+            log::debug!("reported '{}'", name);
+            entry.reported = Some(frame);
+        }
+    }
+
+    /// Wait for the next `refresh` event, returning `false` once the subscription ended.
+    async fn recv_refresh(events: &mut Option<EventReceiver<Event>>) -> bool {
+        let events = match events {
+            Some(events) => events,
+            None => return futures::future::pending().await,
+        };
+        while let Some(event) = events.recv_event().await {
+            if let Event::Refresh(_) = event {
+                return true;
+            }
+        }
+        false
+    }
+}
+
+/// Handle for forcing a [`Reporter`] to resend all its reports.
+#[derive(Clone, Debug)]
+pub struct RefreshHandle {
+    notify: Arc<Notify>,
+}
+
+impl RefreshHandle {
+    /// Run all collectors as soon as possible, sending their reports even if unchanged.
+    pub fn refresh(&self) {
+        self.notify.notify_one();
+    }
+}
@@ -0,0 +1,74 @@
+use crate::commands::Timezone;
+use crate::errors::OgaError;
+use std::path::Path;
+
+/// Symlink to the active zoneinfo file.
+static LOCALTIME_PATH: &str = "/etc/localtime";
+
+/// Plain-text zone name, on Debian-like systems.
+static TIMEZONE_PATH: &str = "/etc/timezone";
+
+/// Detect the guest timezone.
+///
+/// The zone name is taken from the `/etc/localtime` symlink (or `/etc/timezone`),
+/// falling back to `timedatectl`. The offset is the current one, including DST.
+pub async fn timezone() -> Result<Timezone, OgaError> {
+    let zone = match tokio::task::spawn_blocking(zone_from_files)
+        .await
+        .map_err(|e| format!("timezone detection task failed: {}", e))?
+    {
+        Some(zone) => zone,
+        None => zone_from_timedatectl().await?,
+    };
+    let offset = utc_offset()?;
+    Ok(Timezone { zone, offset })
+}
+
+/// Read the zone name from local configuration files.
+fn zone_from_files() -> Option<String> {
+    if let Ok(target) = std::fs::read_link(LOCALTIME_PATH) {
+        if let Some(zone) = zone_from_link(&target) {
+            return Some(zone);
+        }
+    }
+    let content = std::fs::read_to_string(TIMEZONE_PATH).ok()?;
+    Some(content.trim().to_string()).filter(|z| !z.is_empty())
+}
+
+/// Extract the zone name from a zoneinfo path, e.g. `../usr/share/zoneinfo/Europe/Rome`.
+fn zone_from_link(target: &Path) -> Option<String> {
+    let target = target.to_str()?;
+    let (_, zone) = target.rsplit_once("zoneinfo/")?;
+    Some(zone.trim_start_matches("posix/").to_string()).filter(|z| !z.is_empty())
+}
+
+/// Query the zone name from systemd-timedated.
+async fn zone_from_timedatectl() -> Result<String, OgaError> {
+    let output = tokio::process::Command::new("timedatectl")
+        .args(["show", "--property=Timezone", "--value"])
+        .output()
+        .await
+        .map_err(|e| format!("failed to run timedatectl: {}", e))?;
+    if !output.status.success() {
+        return Err(format!("timedatectl failed: {}", output.status).into());
+    }
+    let zone = String::from_utf8_lossy(&output.stdout).trim().to_string();
+    if zone.is_empty() {
+        return Err("no timezone configured".into());
+    }
+    Ok(zone)
+}
+
+/// Return the current local offset from UTC, in minutes.
+fn utc_offset() -> Result<i32, OgaError> {
+    // SAFETY: `time` and `localtime_r` only write into the provided locals.
+    let gmtoff = unsafe {
+        let now = libc::time(std::ptr::null_mut());
+        let mut tm: libc::tm = std::mem::zeroed();
+        if libc::localtime_r(&now, &mut tm).is_null() {
+            return Err("failed to convert local time".into());
+        }
+        tm.tm_gmtoff
+    };
+    Ok((gmtoff / 60) as i32)
+}
@@ -0,0 +1,236 @@
+use crate::commands::{DiskUsage, DisksUsage};
+use crate::errors::OgaError;
+use crate::subscription::{EventReceiver, LagPolicy};
+use crate::OgaCommandSender;
+use std::collections::{BTreeMap, BTreeSet};
+use std::ffi::CString;
+use tokio::sync::broadcast;
+use tokio::time::{self, Duration, Instant};
+
+/// Table of mounted filesystems.
+static MOUNTS_PATH: &str = "/proc/self/mounts";
+
+/// Default interval between threshold checks.
+const DEFAULT_CHECK_SECS: u64 = 10;
+
+/// Shortest interval between usage scans.
+const MIN_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Capacity of the alerts channel.
+const ALERTS_CAPACITY: usize = 16;
+
+/// Collect usage of mounted block-device filesystems.
+///
+/// Pseudo filesystems (e.g. `proc`, `tmpfs`) are skipped, as well as
+/// filesystems mounted more than once (only the first mountpoint is reported).
+pub async fn disks_usage() -> Result<DisksUsage, OgaError> {
+    tokio::task::spawn_blocking(scan_mounts)
+        .await
+        .map_err(|e| format!("disk usage task failed: {}", e))?
+}
+
+/// Scan mounted filesystems and their usage.
+fn scan_mounts() -> Result<DisksUsage, OgaError> {
+    let mounts = std::fs::read_to_string(MOUNTS_PATH)
+        .map_err(|e| format!("failed to read '{}': {}", MOUNTS_PATH, e))?;
+
+    let mut seen = BTreeSet::new();
+    let mut report = DisksUsage::default();
+    for line in mounts.lines() {
+        let mut fields = line.split_whitespace();
+        let (source, path, fs) = match (fields.next(), fields.next(), fields.next()) {
+            (Some(source), Some(path), Some(fs)) => (source, unescape(path), fs),
+            _ => continue,
+        };
+        if !source.starts_with("/dev/") || !seen.insert(source) {
+            continue;
+        }
+        match statvfs(&path) {
+            Ok((total, used)) => report.disks.push(DiskUsage {
+                path,
+                fs: fs.to_string(),
+                total,
+                used,
+            }),
+            Err(e) => log::debug!("skipped '{}': {}", path, e),
+        }
+    }
+    Ok(report)
+}
+
+/// Decode octal escapes (e.g. `\040` for spaces) in a mounts field.
+fn unescape(field: &str) -> String {
+    let mut out = Vec::with_capacity(field.len());
+    let bytes = field.as_bytes();
+    let mut i = 0;
+    while i < bytes.len() {
+        let escaped = bytes.get(i + 1..i + 4).and_then(|oct| {
+            let oct = std::str::from_utf8(oct).ok()?;
+            u8::from_str_radix(oct, 8).ok()
+        });
+        match (bytes[i], escaped) {
+            (b'\\', Some(byte)) => {
+                out.push(byte);
+                i += 4;
+            }
+            (byte, _) => {
+                out.push(byte);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&out).into_owned()
+}
+
+/// Return total and used bytes of the filesystem at the given path.
+fn statvfs(path: &str) -> Result<(u64, u64), OgaError> {
+    let c_path = CString::new(path).map_err(|e| e.to_string())?;
+    // SAFETY: the path is NUL-terminated and `stat` is only written by the call.
+    let stat = unsafe {
+        let mut stat: libc::statvfs = std::mem::zeroed();
+        if libc::statvfs(c_path.as_ptr(), &mut stat) != 0 {
+            return Err(std::io::Error::last_os_error().into());
+        }
+        stat
+    };
+    let frsize = stat.f_frsize as u64;
+    let total = (stat.f_blocks as u64).saturating_mul(frsize);
+    let free = (stat.f_bfree as u64).saturating_mul(frsize);
+    Ok((total, total.saturating_sub(free)))
+}
+
+/// Local notification for a filesystem crossing its usage threshold.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub struct UsageAlert {
+    /// Mountpoint.
+    pub path: String,
+    /// Used space, as a percentage of the total size.
+    pub used_percent: u8,
+    /// Configured threshold, as a percentage.
+    pub threshold: u8,
+}
+
+/// Raise intervals shorter than [`MIN_INTERVAL`].
+fn clamp_interval(interval: Duration) -> Duration {
+    if interval < MIN_INTERVAL {
+        log::warn!(
+            "disks usage interval {:?} too short, using {:?}",
+            interval,
+            MIN_INTERVAL
+        );
+        return MIN_INTERVAL;
+    }
+    interval
+}
+
+/// Monitor reporting disks usage to the host.
+///
+/// Usage is reported at a fixed interval. Additionally, per-mountpoint
+/// thresholds are checked more often, and crossing any of them triggers
+/// an immediate report and a local [`UsageAlert`].
+#[derive(Debug)]
+pub struct DiskUsageMonitor {
+    interval: Duration,
+    check_interval: Duration,
+    thresholds: BTreeMap<String, u8>,
+    alerts: broadcast::Sender<UsageAlert>,
+}
+
+impl DiskUsageMonitor {
+    /// Return a monitor reporting usage at the given interval.
+    ///
+    /// Intervals shorter than one second are raised to that.
+    pub fn new(interval: Duration) -> Self {
+        Self {
+            interval: clamp_interval(interval),
+            check_interval: Duration::from_secs(DEFAULT_CHECK_SECS),
+            thresholds: BTreeMap::new(),
+            alerts: broadcast::channel(ALERTS_CAPACITY).0,
+        }
+    }
+
+    /// Set the usage threshold for a mountpoint, as a percentage (capped at 100).
+    pub fn threshold(mut self, path: impl Into<String>, percent: u8) -> Self {
+        self.thresholds.insert(path.into(), percent.min(100));
+        self
+    }
+
+    /// Interval between threshold checks (default: 10 seconds, at least 1 second).
+    pub fn check_interval(mut self, interval: Duration) -> Self {
+        self.check_interval = clamp_interval(interval);
+        self
+    }
+
+    /// Return a subscription to threshold alerts.
+    pub fn alert_chan(&self) -> EventReceiver<UsageAlert> {
+        EventReceiver::new(self.alerts.subscribe(), LagPolicy::default())
+    }
+
+    /// Monitor usage, sending `disks-usage` commands.
+    ///
+    /// Current usage is reported when starting. This only returns on
+    /// failures to deliver commands; scan failures are logged and retried.
+    pub async fn run(self, sender: OgaCommandSender) -> OgaError {
+        let check_interval = if self.thresholds.is_empty() {
+            self.interval
+        } else {
+            self.check_interval.min(self.interval)
+        };
+        let mut ticker = time::interval(check_interval);
+        let mut last_report: Option<Instant> = None;
+        let mut exceeded: BTreeSet<String> = BTreeSet::new();
+
+        loop {
+            ticker.tick().await;
+            let current = match disks_usage().await {
+                Ok(report) => report,
+                Err(e) => {
+                    log::warn!("failed to collect disks usage: {}", e);
+                    continue;
+                }
+            };
+
+            let mut crossed = false;
+            for disk in &current.disks {
+                let threshold = match self.thresholds.get(&disk.path) {
+                    Some(threshold) => *threshold,
+                    None => continue,
+                };
+                let used_percent = disk.used_percent();
+                if used_percent < threshold {
+                    if exceeded.remove(&disk.path) {
+                        log::info!("'{}' usage back under {}%", disk.path, threshold);
+                    }
+                    continue;
+                }
+                if !exceeded.insert(disk.path.clone()) {
+                    continue;
+                }
+                log::warn!(
+                    "'{}' usage at {}%, over {}% threshold",
+                    disk.path,
+                    used_percent,
+                    threshold
+                );
+                let alert = UsageAlert {
+                    path: disk.path.clone(),
+                    used_percent,
+                    threshold,
+                };
+                let _ = self.alerts.send(alert);
+                crossed = true;
+            }
+
+            let due = last_report.is_none_or(|at| at.elapsed() >= self.interval);
+            if !due && !crossed {
+                continue;
+            }
+            if let Err(e) = sender.send(Box::new(current)).await {
+                return e;
+            }
+            log::debug!("reported disks usage");
+            last_report = Some(Instant::now());
+        }
+    }
+}
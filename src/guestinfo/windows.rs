@@ -0,0 +1,96 @@
+use crate::commands::{HostName, OsInfo, OsVersion};
+use crate::errors::OgaError;
+use winreg::enums::HKEY_LOCAL_MACHINE;
+use winreg::RegKey;
+
+/// Registry key with the operating system version.
+static CURRENT_VERSION_KEY: &str = r"SOFTWARE\Microsoft\Windows NT\CurrentVersion";
+
+/// Registry key with the TCP/IP host name.
+static TCPIP_PARAMETERS_KEY: &str = r"SYSTEM\CurrentControlSet\Services\Tcpip\Parameters";
+
+/// Operating system version details, from the registry.
+#[derive(Clone, Debug)]
+struct CurrentVersion {
+    product_name: String,
+    display_version: Option<String>,
+    build: String,
+    major: Option<u32>,
+    minor: Option<u32>,
+}
+
+impl CurrentVersion {
+    /// Read version details from the registry.
+    fn load() -> Result<Self, OgaError> {
+        let key = RegKey::predef(HKEY_LOCAL_MACHINE)
+            .open_subkey(CURRENT_VERSION_KEY)
+            .map_err(|e| format!("failed to open '{}': {}", CURRENT_VERSION_KEY, e))?;
+        let product_name: String = key
+            .get_value("ProductName")
+            .map_err(|e| format!("failed to read product name: {}", e))?;
+        let display_version = key
+            .get_value("DisplayVersion")
+            .or_else(|_| key.get_value("ReleaseId"))
+            .ok();
+        let build = key.get_value("CurrentBuild").unwrap_or_default();
+        Ok(Self {
+            product_name,
+            display_version,
+            build,
+            major: key.get_value("CurrentMajorVersionNumber").ok(),
+            minor: key.get_value("CurrentMinorVersionNumber").ok(),
+        })
+    }
+}
+
+/// Run a blocking registry query.
+async fn query<T, F>(f: F) -> Result<T, OgaError>
+where
+    T: Send + 'static,
+    F: FnOnce() -> Result<T, OgaError> + Send + 'static,
+{
+    tokio::task::spawn_blocking(f)
+        .await
+        .map_err(|e| format!("registry query task failed: {}", e))?
+}
+
+/// Detect the guest host name.
+pub async fn host_name() -> Result<HostName, OgaError> {
+    query(|| {
+        let key = RegKey::predef(HKEY_LOCAL_MACHINE)
+            .open_subkey(TCPIP_PARAMETERS_KEY)
+            .map_err(|e| format!("failed to open '{}': {}", TCPIP_PARAMETERS_KEY, e))?;
+        let name: String = key
+            .get_value("Hostname")
+            .map_err(|e| format!("failed to read host name: {}", e))?;
+        Ok(HostName { name })
+    })
+    .await
+}
+
+/// Detect the operating system version.
+pub async fn os_version() -> Result<OsVersion, OgaError> {
+    let current = query(CurrentVersion::load).await?;
+    let version = match &current.display_version {
+        Some(display) => format!("{} {}", current.product_name, display),
+        None => current.product_name,
+    };
+    Ok(OsVersion { version })
+}
+
+/// Detect detailed operating system information, including kernel details.
+pub async fn os_info() -> Result<OsInfo, OgaError> {
+    let current = query(CurrentVersion::load).await?;
+    let kernel = match (current.major, current.minor) {
+        (Some(major), Some(minor)) => format!("{}.{}.{}", major, minor, current.build),
+        _ => current.build.clone(),
+    };
+    Ok(OsInfo {
+        version: current.display_version.unwrap_or_default(),
+        distribution: current.product_name,
+        codename: String::new(),
+        arch: std::env::consts::ARCH.to_string(),
+        os_type: "windows".to_string(),
+        kernel,
+    })
+}
@@ -0,0 +1,45 @@
+//! Health notifications about the host side of the channel.
+
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+/// Health event, reported alongside host events.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum HealthEvent {
+    /// No frames were received from the host for the given period.
+    HostSilent(Duration),
+    /// Frames are being received again, after a silence.
+    HostResumed,
+    /// Writing a frame did not complete within the given period.
+    WriteStalled(Duration),
+    /// No data was readable on the named channel for the given period.
+    ReadIdle(String, Duration),
+    /// A partial frame was discarded at the start of the named channel, with its size in bytes.
+    Resynced(String, usize),
+}
+
+/// How to react to a silent host.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum SilencePolicy {
+    /// Deliver `HealthEvent`s, keeping the client running.
+    #[default]
+    Notify,
+    /// Terminate the client.
+    Terminate,
+}
+
+/// How to react to a channel without readable data.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum IdlePolicy {
+    /// Deliver `HealthEvent`s only.
+    #[default]
+    Notify,
+    /// Also send an `echo` probe to the host, so that a live one has a reason to answer.
+    ///
+    /// Probes are only sent on the primary channel, and their answers are
+    /// not delivered as events.
+    Probe,
+}
@@ -0,0 +1,235 @@
+//! Hooks for observing and customizing client behavior.
+
+use crate::commands::AsFrame;
+use crate::errors::OgaError;
+use crate::events::TaggedEvent;
+use futures::future::BoxFuture;
+use futures::FutureExt;
+use std::future::Future;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::SystemTime;
+
+/// Record of a command written to the host.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CommandRecord {
+    /// Label of the channel where the command was written.
+    pub channel: String,
+    /// Protocol name of the command.
+    pub name: String,
+    /// Size of the encoded frame, in bytes.
+    pub size: usize,
+    /// Time of the write.
+    pub timestamp: SystemTime,
+    /// Outcome of the write.
+    pub result: Result<(), String>,
+}
+
+/// Hook invoked for every command written to the host.
+#[derive(Clone)]
+pub struct AuditHook(Arc<dyn Fn(&CommandRecord) + Send + Sync>);
+
+impl AuditHook {
+    /// Return a hook running the given function.
+    pub fn new(hook: impl Fn(&CommandRecord) + Send + Sync + 'static) -> Self {
+        Self(Arc::new(hook))
+    }
+
+    /// Run this hook on a record.
+    pub(crate) fn call(&self, record: &CommandRecord) {
+        (self.0)(record)
+    }
+}
+
+impl std::fmt::Debug for AuditHook {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str("AuditHook")
+    }
+}
+
+/// Middleware applied to outgoing commands, before they get validated and encoded.
+///
+/// Each middleware can rewrite a command, or drop it by returning `None`.
+#[derive(Clone)]
+pub struct CommandMiddleware(Arc<CommandMiddlewareFn>);
+
+/// Boxed outgoing command, as handled by middleware.
+type BoxCommand = Box<dyn AsFrame>;
+
+/// Type-erased command middleware function.
+type CommandMiddlewareFn =
+    dyn Fn(BoxCommand) -> BoxFuture<'static, Option<BoxCommand>> + Send + Sync;
+
+impl CommandMiddleware {
+    /// Return a middleware running the given async function.
+    pub fn new<F, Fut>(middleware: F) -> Self
+    where
+        F: Fn(Box<dyn AsFrame>) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Option<Box<dyn AsFrame>>> + Send + 'static,
+    {
+        Self(Arc::new(move |cmd| middleware(cmd).boxed()))
+    }
+
+    /// Run this middleware on a command.
+    pub(crate) async fn call(&self, cmd: Box<dyn AsFrame>) -> Option<Box<dyn AsFrame>> {
+        (self.0)(cmd).await
+    }
+}
+
+impl std::fmt::Debug for CommandMiddleware {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str("CommandMiddleware")
+    }
+}
+
+/// Middleware applied to incoming events, before they get delivered to subscribers.
+///
+/// Each middleware can rewrite an event, or suppress it by returning `None`.
+#[derive(Clone)]
+pub struct EventMiddleware(Arc<EventMiddlewareFn>);
+
+/// Type-erased event middleware function.
+type EventMiddlewareFn =
+    dyn Fn(TaggedEvent) -> BoxFuture<'static, Option<TaggedEvent>> + Send + Sync;
+
+impl EventMiddleware {
+    /// Return a middleware running the given async function.
+    pub fn new<F, Fut>(middleware: F) -> Self
+    where
+        F: Fn(TaggedEvent) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Option<TaggedEvent>> + Send + 'static,
+    {
+        Self(Arc::new(move |event| middleware(event).boxed()))
+    }
+
+    /// Run this middleware on an event.
+    pub(crate) async fn call(&self, event: TaggedEvent) -> Option<TaggedEvent> {
+        (self.0)(event).await
+    }
+}
+
+impl std::fmt::Debug for EventMiddleware {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str("EventMiddleware")
+    }
+}
+
+/// Event dropped for lagging subscribers, because of a full queue.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DeadLetter {
+    /// Dropped event.
+    pub event: TaggedEvent,
+    /// Total count of dropped events so far.
+    pub total_dropped: u64,
+}
+
+/// Hook invoked for every event dropped for lagging subscribers.
+#[derive(Clone)]
+pub struct DeadLetterHook(Arc<dyn Fn(&DeadLetter) + Send + Sync>);
+
+impl DeadLetterHook {
+    /// Return a hook running the given function.
+    pub fn new(hook: impl Fn(&DeadLetter) + Send + Sync + 'static) -> Self {
+        Self(Arc::new(hook))
+    }
+
+    /// Run this hook on a dropped event.
+    pub(crate) fn call(&self, dead_letter: &DeadLetter) {
+        (self.0)(dead_letter)
+    }
+}
+
+impl std::fmt::Debug for DeadLetterHook {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str("DeadLetterHook")
+    }
+}
+
+/// Dead-letter hook with a running count of dropped events, shared by client tasks.
+#[derive(Clone, Debug)]
+pub(crate) struct DeadLetterSink {
+    hook: DeadLetterHook,
+    total: Arc<AtomicU64>,
+}
+
+impl DeadLetterSink {
+    /// Return a sink reporting to the given hook.
+    pub(crate) fn new(hook: DeadLetterHook) -> Self {
+        Self {
+            hook,
+            total: Arc::new(AtomicU64::new(0)),
+        }
+    }
+
+    /// Record a dropped event.
+    pub(crate) fn record(&self, event: TaggedEvent) {
+        let total = self.total.fetch_add(1, Ordering::Relaxed).saturating_add(1);
+        log::debug!(
+            "'{}' event dropped for lagging subscribers ({} total)",
+            event.event.name(),
+            total
+        );
+        self.hook.call(&DeadLetter {
+            event,
+            total_dropped: total,
+        });
+    }
+}
+
+/// Async hook invoked once the client has established its channels.
+#[derive(Clone)]
+pub struct ConnectHook(Arc<ConnectHookFn>);
+
+/// Type-erased connect hook function.
+type ConnectHookFn = dyn Fn() -> BoxFuture<'static, ()> + Send + Sync;
+
+impl ConnectHook {
+    /// Return a hook running the given async function.
+    pub fn new<F, Fut>(hook: F) -> Self
+    where
+        F: Fn() -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        Self(Arc::new(move || hook().boxed()))
+    }
+
+    /// Run this hook.
+    pub(crate) fn call(&self) -> BoxFuture<'static, ()> {
+        (self.0)()
+    }
+}
+
+impl std::fmt::Debug for ConnectHook {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str("ConnectHook")
+    }
+}
+
+/// Async hook invoked once the client terminates, with the termination error.
+#[derive(Clone)]
+pub struct DisconnectHook(Arc<DisconnectHookFn>);
+
+/// Type-erased disconnect hook function.
+type DisconnectHookFn = dyn Fn(OgaError) -> BoxFuture<'static, ()> + Send + Sync;
+
+impl DisconnectHook {
+    /// Return a hook running the given async function.
+    pub fn new<F, Fut>(hook: F) -> Self
+    where
+        F: Fn(OgaError) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        Self(Arc::new(move |err| hook(err).boxed()))
+    }
+
+    /// Run this hook on a termination error.
+    pub(crate) fn call(&self, err: OgaError) -> BoxFuture<'static, ()> {
+        (self.0)(err)
+    }
+}
+
+impl std::fmt::Debug for DisconnectHook {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str("DisconnectHook")
+    }
+}
@@ -0,0 +1,100 @@
+//! systemd-logind D-Bus interface.
+//!
+//! References:
+//!  * <https://www.freedesktop.org/software/systemd/man/org.freedesktop.login1.html>
+
+use crate::errors::OgaError;
+use zbus::zvariant::OwnedObjectPath;
+
+/// Proxy for `org.freedesktop.login1.Manager`.
+#[zbus::proxy(
+    interface = "org.freedesktop.login1.Manager",
+    default_service = "org.freedesktop.login1",
+    default_path = "/org/freedesktop/login1"
+)]
+pub(crate) trait Manager {
+    /// Power off the system.
+    fn power_off(&self, interactive: bool) -> zbus::Result<()>;
+
+    /// Reboot the system.
+    fn reboot(&self, interactive: bool) -> zbus::Result<()>;
+
+    /// Schedule a shutdown at given time (`CLOCK_REALTIME`, in microseconds).
+    fn schedule_shutdown(&self, kind: &str, usec: u64) -> zbus::Result<()>;
+
+    /// Whether hibernation is supported and allowed (`yes`, `no`, `na`, `challenge`).
+    fn can_hibernate(&self) -> zbus::Result<String>;
+
+    /// Hibernate the system.
+    fn hibernate(&self, interactive: bool) -> zbus::Result<()>;
+
+    /// Set the message broadcast to logged-in users on shutdown.
+    fn set_wall_message(&self, wall_message: &str, enable: bool) -> zbus::Result<()>;
+
+    /// List sessions, as (ID, UID, user name, seat, object path) tuples.
+    fn list_sessions(&self) -> zbus::Result<Vec<(String, u32, String, String, OwnedObjectPath)>>;
+
+    /// Lock the screen of the given session.
+    fn lock_session(&self, session_id: &str) -> zbus::Result<()>;
+
+    /// Terminate the given session.
+    fn terminate_session(&self, session_id: &str) -> zbus::Result<()>;
+}
+
+/// Proxy for `org.freedesktop.login1.Seat`, on the default seat.
+#[zbus::proxy(
+    interface = "org.freedesktop.login1.Seat",
+    default_service = "org.freedesktop.login1",
+    default_path = "/org/freedesktop/login1/seat/seat0"
+)]
+pub(crate) trait Seat {
+    /// Currently active session on this seat, as an (ID, object path) pair.
+    #[zbus(property)]
+    fn active_session(&self) -> zbus::Result<(String, OwnedObjectPath)>;
+}
+
+/// Proxy for `org.freedesktop.login1.Session`.
+#[zbus::proxy(
+    interface = "org.freedesktop.login1.Session",
+    default_service = "org.freedesktop.login1"
+)]
+pub(crate) trait Session {
+    /// Name of the user owning this session.
+    #[zbus(property)]
+    fn name(&self) -> zbus::Result<String>;
+}
+
+/// Connect to the logind manager on the system bus.
+pub(crate) async fn manager() -> Result<ManagerProxy<'static>, OgaError> {
+    let conn = zbus::Connection::system().await.map_err(dbus_error)?;
+    ManagerProxy::new(&conn).await.map_err(dbus_error)
+}
+
+/// Return the ID and object path of the active session on the default seat.
+pub(crate) async fn active_session() -> Result<(String, OwnedObjectPath), OgaError> {
+    let conn = zbus::Connection::system().await.map_err(dbus_error)?;
+    let seat = SeatProxy::new(&conn).await.map_err(dbus_error)?;
+    let (session_id, path) = seat.active_session().await.map_err(dbus_error)?;
+    if session_id.is_empty() {
+        return Err("no active session on default seat".into());
+    }
+    Ok((session_id, path))
+}
+
+/// Return the name of the user owning the given session.
+#[cfg(feature = "users")]
+pub(crate) async fn session_user(path: OwnedObjectPath) -> Result<String, OgaError> {
+    let conn = zbus::Connection::system().await.map_err(dbus_error)?;
+    let session = SessionProxy::builder(&conn)
+        .path(path)
+        .map_err(dbus_error)?
+        .build()
+        .await
+        .map_err(dbus_error)?;
+    session.name().await.map_err(dbus_error)
+}
+
+/// Convert a D-Bus failure into a library error.
+pub(crate) fn dbus_error(err: zbus::Error) -> OgaError {
+    OgaError::from(format!("logind D-Bus call failed: {}", err))
+}
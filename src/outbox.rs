@@ -0,0 +1,252 @@
+/*! Persistent on-disk outbox for commands.
+
+Commands which cannot be delivered (e.g. the device is missing, or the
+client is disconnected) can be spooled to a directory, one encoded frame
+per file. Spooled frames survive agent restarts, and are replayed in order
+by [`OgaBuilder::connect()`](../struct.OgaBuilder.html#method.connect)
+when the builder is configured with the same outbox directory.
+
+Replay happens before the host announces its API version, thus spooled
+commands which require one are moved aside instead of being replayed.
+*/
+
+use crate::commands::{self, AsFrame};
+use crate::errors::OgaError;
+use crate::protocol;
+use crate::OgaCommandSender;
+use bytes::Bytes;
+use serde::Deserialize;
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// File extension for spooled frames.
+const FRAME_EXT: &str = "frame";
+
+/// File extension for spooled frames which cannot be decoded.
+const BAD_EXT: &str = "bad";
+
+/// Counter for unique temporary file names, within this process.
+static TMP_COUNTER: AtomicU64 = AtomicU64::new(0);
+
+/// Spool directory for undelivered commands.
+#[derive(Clone, Debug)]
+pub struct Outbox {
+    dir: PathBuf,
+}
+
+impl Outbox {
+    /// Open the outbox at the given directory, creating it if needed.
+    pub fn open(dir: impl AsRef<Path>) -> Result<Self, OgaError> {
+        let dir = dir.as_ref().to_path_buf();
+        fs::create_dir_all(&dir)
+            .map_err(|e| format!("failed to create outbox '{}': {}", dir.display(), e))?;
+        Ok(Self { dir })
+    }
+
+    /// Send a command, or spool it if it cannot be delivered.
+    ///
+    /// Commands are spooled if no sender is available, if sending fails, or
+    /// if older commands are still pending (in order to preserve ordering).
+    /// Invalid commands are rejected without being spooled.
+    pub async fn send(
+        &self,
+        sender: Option<&OgaCommandSender>,
+        cmd: Box<dyn AsFrame>,
+    ) -> Result<(), OgaError> {
+        cmd.validate()?;
+        let spooled = SpooledCommand::encode(cmd.as_ref())?;
+
+        if let (Some(sender), 0) = (sender, self.pending()?) {
+            match sender.send(Box::new(spooled.clone())).await {
+                Ok(_) => return Ok(()),
+                Err(e) => log::debug!("failed to send '{}', spooling: {}", spooled.name, e),
+            }
+        }
+        self.push(&spooled)
+    }
+
+    /// Number of spooled commands, not yet delivered.
+    pub fn pending(&self) -> Result<usize, OgaError> {
+        Ok(self.entries()?.len())
+    }
+
+    /// Persist an encoded command, after all the pending ones.
+    ///
+    /// Concurrent pushes (from clones, or other processes) may pick the same
+    /// sequence number; the final name is claimed by hard-linking, which never
+    /// replaces an existing file, and the next number is tried on conflicts.
+    fn push(&self, spooled: &SpooledCommand) -> Result<(), OgaError> {
+        let tmp = self.dir.join(format!(
+            ".{}-{}.tmp",
+            std::process::id(),
+            TMP_COUNTER.fetch_add(1, Ordering::Relaxed)
+        ));
+        let mut file = fs::File::create(&tmp)?;
+        file.write_all(&spooled.frame)?;
+        file.sync_all()?;
+
+        let mut seq = self.entries()?.last().map(|(seq, _)| seq + 1).unwrap_or(0);
+        let res = loop {
+            let path = self.dir.join(format!("{:020}.{}", seq, FRAME_EXT));
+            match fs::hard_link(&tmp, &path) {
+                Ok(_) => break Ok(path),
+                Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => seq += 1,
+                Err(e) => break Err(e),
+            }
+        };
+        let _ = fs::remove_file(&tmp);
+        let path = res?;
+        log::debug!("spooled '{}' command to '{}'", spooled.name, path.display());
+        Ok(())
+    }
+
+    /// Load all spooled commands, in order.
+    ///
+    /// Frames which cannot be decoded are moved aside (with a `.bad`
+    /// extension), so that they do not block later replays.
+    pub(crate) fn load(&self) -> Result<Vec<(PathBuf, SpooledCommand)>, OgaError> {
+        let mut spooled = Vec::new();
+        for (_, path) in self.entries()? {
+            let frame = fs::read(&path)?;
+            match SpooledCommand::decode(frame) {
+                Ok(cmd) => spooled.push((path, cmd)),
+                Err(e) => self.set_aside(&path, e),
+            }
+        }
+        Ok(spooled)
+    }
+
+    /// Move aside a spooled frame which cannot be replayed.
+    pub(crate) fn set_aside(&self, path: &Path, err: OgaError) {
+        let bad = path.with_extension(BAD_EXT);
+        log::warn!(
+            "cannot replay spooled frame '{}', moving to '{}': {}",
+            path.display(),
+            bad.display(),
+            err.0
+        );
+        if let Err(e) = fs::rename(path, &bad) {
+            log::error!("failed to move aside '{}': {}", path.display(), e);
+        }
+    }
+
+    /// Remove a spooled command, once delivered.
+    pub(crate) fn remove(&self, path: &Path) -> Result<(), OgaError> {
+        fs::remove_file(path)
+            .map_err(|e| format!("failed to remove '{}': {}", path.display(), e).into())
+    }
+
+    /// List spooled frames, sorted by sequence number.
+    fn entries(&self) -> Result<Vec<(u64, PathBuf)>, OgaError> {
+        let mut entries = Vec::new();
+        for entry in fs::read_dir(&self.dir)? {
+            let path = entry?.path();
+            if path.extension().and_then(|ext| ext.to_str()) != Some(FRAME_EXT) {
+                continue;
+            }
+            let seq = path
+                .file_stem()
+                .and_then(|stem| stem.to_str())
+                .and_then(|stem| stem.parse().ok());
+            if let Some(seq) = seq {
+                entries.push((seq, path));
+            }
+        }
+        entries.sort();
+        Ok(entries)
+    }
+}
+
+/// Command already encoded as a frame.
+#[derive(Clone, Debug)]
+pub(crate) struct SpooledCommand {
+    pub(crate) name: String,
+    pub(crate) frame: Bytes,
+    pub(crate) min_api_version: u8,
+}
+
+impl SpooledCommand {
+    /// Encode a command.
+    fn encode(cmd: &dyn AsFrame) -> Result<Self, OgaError> {
+        let frame = protocol::encode_frame(cmd)?;
+        Ok(Self {
+            name: cmd.name().to_string(),
+            frame,
+            min_api_version: cmd.min_api_version(),
+        })
+    }
+
+    /// Decode a spooled frame, recovering its command name.
+    fn decode(frame: Vec<u8>) -> Result<Self, OgaError> {
+        #[derive(Deserialize)]
+        struct Tag {
+            #[serde(rename = "__name__")]
+            name: String,
+        }
+
+        let tag: Tag = serde_json::from_slice(&frame).map_err(|e| e.to_string())?;
+        Ok(Self {
+            min_api_version: commands::min_api_version(&tag.name),
+            name: tag.name,
+            frame: frame.into(),
+        })
+    }
+}
+
+impl AsFrame for SpooledCommand {
+    fn as_frame(&self) -> Result<Bytes, OgaError> {
+        Ok(self.frame.clone())
+    }
+
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn min_api_version(&self) -> u8 {
+        self.min_api_version
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands::Heartbeat;
+
+    #[test]
+    fn concurrent_pushes() {
+        let dir = tempfile::tempdir().unwrap();
+        let outbox = Outbox::open(dir.path()).unwrap();
+        let spooled = SpooledCommand::encode(&Heartbeat::default()).unwrap();
+        std::thread::scope(|scope| {
+            for _ in 0..4 {
+                let outbox = outbox.clone();
+                let spooled = spooled.clone();
+                scope.spawn(move || {
+                    for _ in 0..25 {
+                        outbox.push(&spooled).unwrap();
+                    }
+                });
+            }
+        });
+        assert_eq!(outbox.pending().unwrap(), 100);
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 100);
+    }
+
+    #[test]
+    fn set_aside_bad_frames() {
+        let dir = tempfile::tempdir().unwrap();
+        let outbox = Outbox::open(dir.path()).unwrap();
+        let spooled = SpooledCommand::encode(&Heartbeat::default()).unwrap();
+        outbox.push(&spooled).unwrap();
+        fs::write(dir.path().join("00000000000000000001.frame"), "garbage\n").unwrap();
+        outbox.push(&spooled).unwrap();
+
+        let loaded = outbox.load().unwrap();
+        assert_eq!(loaded.len(), 2);
+        assert!(loaded.iter().all(|(_, cmd)| cmd.name == "heartbeat"));
+        assert!(dir.path().join("00000000000000000001.bad").exists());
+        assert_eq!(outbox.pending().unwrap(), 2);
+    }
+}
@@ -0,0 +1,61 @@
+//! Round-trip tracking for echo probes.
+
+use crate::events::Event;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use tokio::sync::oneshot;
+use tokio::time::Instant;
+
+/// Probes awaiting their `echo` answer, shared between senders and the dispatcher.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct PingTracker {
+    next_id: Arc<AtomicU64>,
+    pending: Arc<Mutex<HashMap<String, oneshot::Sender<Instant>>>>,
+}
+
+impl PingTracker {
+    /// Register a new probe, returning its identifier and the time its answer arrived.
+    pub(crate) fn register(&self) -> (String, oneshot::Receiver<Instant>) {
+        let id = format!("ping-{}", self.next_id.fetch_add(1, Ordering::Relaxed));
+        let (tx, rx) = oneshot::channel();
+        if let Ok(mut pending) = self.pending.lock() {
+            pending.insert(id.clone(), tx);
+        }
+        (id, rx)
+    }
+
+    /// Forget a probe, e.g. once its caller gave up.
+    pub(crate) fn cancel(&self, id: &str) {
+        if let Ok(mut pending) = self.pending.lock() {
+            pending.remove(id);
+        }
+    }
+
+    /// Whether any probe is waiting for its answer.
+    pub(crate) fn has_pending(&self) -> bool {
+        self.pending.lock().is_ok_and(|pending| !pending.is_empty())
+    }
+
+    /// Resolve the probe answered by an event, returning whether it was consumed.
+    ///
+    /// Only answers to pending probes are consumed; host-initiated probes
+    /// and late answers are left for the application.
+    pub(crate) fn resolve(&self, event: &Event) -> bool {
+        let id = match event {
+            Event::Echo(echo) => echo.probe_id(),
+            _ => None,
+        };
+        let waiter = match (id, self.pending.lock()) {
+            (Some(id), Ok(mut pending)) => pending.remove(id),
+            _ => None,
+        };
+        match waiter {
+            Some(waiter) => {
+                let _ = waiter.send(Instant::now());
+                true
+            }
+            None => false,
+        }
+    }
+}
@@ -0,0 +1,731 @@
+/*! Runtime-agnostic protocol core.
+
+This provides I/O-free framing for the protocol: encoding commands into
+newline-terminated frames, and splitting incoming bytes into events.
+It does not depend on any async runtime, so it can be driven by any
+reactor (or by plain blocking I/O) which owns the VirtIO port.
+
+The tokio-based [`OgaClient`](../struct.OgaClient.html) and
+[`raw`](../raw/index.html) channel are built on top of this.
+*/
+
+use crate::commands::{json_frame, AsFrame, MAX_FRAME_SIZE};
+use crate::errors::OgaError;
+use crate::events::{self, LazyEvent};
+use crate::secret::REDACTED;
+use bytes::{Bytes, BytesMut};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+
+/// Handling of incoming frames which are not valid UTF-8.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Utf8Policy {
+    /// Replace invalid sequences with U+FFFD, then parse the frame.
+    Lossy,
+    /// Log and skip the frame.
+    Skip,
+    /// Fail with an error, terminating the stream.
+    #[default]
+    Terminate,
+}
+
+/// Incremental decoder for newline-delimited protocol frames.
+///
+/// Bytes read from the channel are pushed in, and complete events are
+/// popped out. Frames without a valid event name are skipped, and either
+/// logged or reported to the diagnostics hook.
+/// A frame cut short is skipped too, while the complete frame running into
+/// it on the same line is still decoded.
+///
+/// A stream may start in the middle of a frame (e.g. after the host side
+/// reconnected). Until a first frame is decoded, undecodable data is
+/// discarded up to the next newline; see `discarded()`.
+///
+/// On a corrupted stream, every frame may end up skipped; a limit on
+/// consecutive failures turns this into an error instead.
+///
+/// Lines longer than `MAX_FRAME_SIZE` are skipped as well, without being
+/// buffered: past the limit, data is discarded up to the next newline.
+#[derive(Clone, Debug, Default)]
+pub struct FrameDecoder {
+    buf: Vec<u8>,
+    lines: LineDecoder,
+}
+
+impl FrameDecoder {
+    /// Return a new decoder.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// How to handle frames which are not valid UTF-8 (default: terminate).
+    pub fn utf8_policy(mut self, policy: Utf8Policy) -> Self {
+        self.lines.utf8_policy = policy;
+        self
+    }
+
+    /// Maximum number of consecutive frames which fail decoding, or zero to disable (default).
+    ///
+    /// Once reached, decoding fails with a protocol error.
+    pub fn max_decode_failures(mut self, limit: usize) -> Self {
+        self.lines.max_failures = limit;
+        self
+    }
+
+    /// Deliver diagnostics about skipped or repaired frames to a hook, instead of logging them.
+    pub fn diagnostics_hook(
+        mut self,
+        hook: impl Fn(DecodeDiagnostic) + Send + Sync + 'static,
+    ) -> Self {
+        self.lines.set_diagnostics_hook(hook);
+        self
+    }
+
+    /// Number of bytes discarded so far, while resynchronizing at stream start.
+    pub fn discarded(&self) -> usize {
+        self.lines.discarded
+    }
+
+    /// Append bytes read from the channel.
+    pub fn push(&mut self, data: &[u8]) {
+        self.buf.extend_from_slice(data);
+        if !self.buf.contains(&b'\n') {
+            self.discard_oversized();
+        }
+    }
+
+    /// Number of buffered bytes, not yet decoded.
+    pub fn buffered(&self) -> usize {
+        self.buf.len()
+    }
+
+    /// Decode the next complete event, if any.
+    pub fn next_event(&mut self) -> Result<Option<LazyEvent>, OgaError> {
+        while let Some(pos) = self.buf.iter().position(|b| *b == b'\n') {
+            let frame: Vec<u8> = self.buf.drain(..=pos).collect();
+            if let Some(lazy) = self.lines.decode(&frame[..pos])? {
+                return Ok(Some(lazy));
+            }
+        }
+        self.discard_oversized();
+        Ok(None)
+    }
+
+    /// Discard a buffered unterminated line, once it grew too long.
+    fn discard_oversized(&mut self) {
+        if self.lines.discard_unterminated(&self.buf) {
+            self.buf.clear();
+        }
+    }
+}
+
+/// Kind of anomaly found while decoding incoming frames.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum DiagnosticKind {
+    /// Partial frame discarded at stream start.
+    Resync,
+    /// Frame cut short, skipped up to the complete frame following it.
+    Truncated,
+    /// Frame which is not valid UTF-8.
+    InvalidUtf8,
+    /// Frame which is not a well-formed (or known) event.
+    Unrecognized,
+    /// Frame longer than `MAX_FRAME_SIZE`, discarded up to the next newline.
+    Oversized,
+}
+
+/// Anomaly found while decoding incoming frames.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub struct DecodeDiagnostic {
+    pub kind: DiagnosticKind,
+    /// Offset of the affected frame from stream start, in bytes.
+    pub offset: u64,
+    /// Description of the error.
+    pub error: String,
+    /// Beginning of the frame, lossily decoded.
+    ///
+    /// Known events (other than `login`) are sampled with the values of
+    /// sensitive fields redacted, and malformed frames as they are. This is
+    /// left empty for other frames, as they may carry credentials.
+    pub sample: String,
+}
+
+impl DecodeDiagnostic {
+    /// Maximum length of frame samples, in bytes.
+    const SAMPLE_LEN: usize = 64;
+
+    /// Field names hinting at sensitive values, in lowercase.
+    const SENSITIVE_KEYS: &'static [&'static str] =
+        &["password", "passwd", "secret", "token", "credential"];
+
+    /// Return a diagnostic for a frame, sampling its content.
+    pub(crate) fn new(kind: DiagnosticKind, offset: u64, error: String, frame: &[u8]) -> Self {
+        let sample = Self::sample(frame).unwrap_or_default();
+        Self {
+            kind,
+            offset,
+            error,
+            sample,
+        }
+    }
+
+    /// Sample a frame, unless it may carry credentials.
+    ///
+    /// Known events are sampled with sensitive fields redacted. Frames which
+    /// are not well-formed are sampled as they are, unless they look sensitive.
+    fn sample(frame: &[u8]) -> Option<String> {
+        let mut value = match serde_json::from_slice::<serde_json::Value>(frame) {
+            Ok(value) => value,
+            Err(_) => return Self::sample_raw(frame),
+        };
+        let name = value.get("__name__")?.as_str()?;
+        if name == "login" || !events::NAMES.contains(&name) {
+            return None;
+        }
+        Self::scrub(&mut value);
+        Some(Self::prefix(value.to_string().as_bytes()))
+    }
+
+    /// Sample a malformed frame, unless it mentions logins or sensitive fields.
+    fn sample_raw(frame: &[u8]) -> Option<String> {
+        let text = String::from_utf8_lossy(frame).to_lowercase();
+        let sensitive = std::iter::once(&"login")
+            .chain(Self::SENSITIVE_KEYS)
+            .any(|key| text.contains(key));
+        match sensitive {
+            true => None,
+            false => Some(Self::prefix(frame)),
+        }
+    }
+
+    /// Return the beginning of some data, lossily decoded.
+    fn prefix(data: &[u8]) -> String {
+        let len = data.len().min(Self::SAMPLE_LEN);
+        String::from_utf8_lossy(&data[..len]).into_owned()
+    }
+
+    /// Redact the values of sensitive fields, recursively.
+    fn scrub(value: &mut serde_json::Value) {
+        match value {
+            serde_json::Value::Object(fields) => {
+                for (key, field) in fields.iter_mut() {
+                    let key = key.to_lowercase();
+                    if Self::SENSITIVE_KEYS.iter().any(|s| key.contains(s)) {
+                        *field = REDACTED.into();
+                    } else {
+                        Self::scrub(field);
+                    }
+                }
+            }
+            serde_json::Value::Array(items) => items.iter_mut().for_each(Self::scrub),
+            _ => {}
+        }
+    }
+}
+
+/// Decode diagnostic, tagged with the label of its channel.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct TaggedDiagnostic {
+    /// Label of the channel where the anomaly was found.
+    pub channel: String,
+    pub diagnostic: DecodeDiagnostic,
+}
+
+/// Callback receiving decode diagnostics.
+#[derive(Clone)]
+struct DiagnosticsHook(Arc<dyn Fn(DecodeDiagnostic) + Send + Sync>);
+
+impl std::fmt::Debug for DiagnosticsHook {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str("DiagnosticsHook")
+    }
+}
+
+/// Line decoding state, shared by decoders.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct LineDecoder {
+    pub(crate) utf8_policy: Utf8Policy,
+    /// Whether a frame boundary was found, after a first decoded frame.
+    synced: bool,
+    /// Bytes discarded while resynchronizing.
+    pub(crate) discarded: usize,
+    /// Maximum number of consecutive failures, or zero to disable.
+    pub(crate) max_failures: usize,
+    /// Number of frames which failed decoding since the last decoded one.
+    failures: usize,
+    /// Offset of the next frame from stream start.
+    position: u64,
+    /// Offset of the last frame from stream start.
+    pub(crate) frame_offset: u64,
+    /// Length and beginning of an oversized line, discarded until its newline.
+    oversized: Option<(usize, Vec<u8>)>,
+    diagnostics: Option<DiagnosticsHook>,
+}
+
+impl LineDecoder {
+    /// Deliver diagnostics to a hook, instead of logging them.
+    pub(crate) fn set_diagnostics_hook(
+        &mut self,
+        hook: impl Fn(DecodeDiagnostic) + Send + Sync + 'static,
+    ) {
+        self.diagnostics = Some(DiagnosticsHook(Arc::new(hook)));
+    }
+
+    /// Decode a single frame (without its trailing newline) into an event.
+    ///
+    /// This returns `None` for frames which are skipped, failing once too
+    /// many consecutive frames were skipped (not counting the ones discarded
+    /// while resynchronizing).
+    pub(crate) fn decode(&mut self, frame: &[u8]) -> Result<Option<LazyEvent>, OgaError> {
+        let (len, head) = match self.oversized.take() {
+            Some((len, head)) => (len + frame.len(), Some(head)),
+            None => (frame.len(), None),
+        };
+        self.frame_offset = self.position;
+        self.position += len as u64 + 1;
+
+        let (lazy, resync) = match head {
+            Some(head) => (self.skip_oversized(len, &head), false),
+            None if len > MAX_FRAME_SIZE => (self.skip_oversized(len, frame), false),
+            None => {
+                let resync = !self.synced;
+                (self.decode_frame(frame)?, resync)
+            }
+        };
+        if lazy.is_some() {
+            self.failures = 0;
+            return Ok(lazy);
+        }
+        // Partial frames at stream start are expected, not failures.
+        if resync {
+            return Ok(None);
+        }
+
+        self.failures += 1;
+        if self.max_failures > 0 && self.failures >= self.max_failures {
+            let msg = format!(
+                "protocol error: {} consecutive frames failed decoding",
+                self.failures
+            );
+            return Err(msg.into());
+        }
+        Ok(None)
+    }
+
+    /// Discard the unterminated data of a line, once it grew past `MAX_FRAME_SIZE`.
+    ///
+    /// This returns whether `data` was consumed; the rest of the line is then
+    /// expected to be decoded once its newline arrives.
+    pub(crate) fn discard_unterminated(&mut self, data: &[u8]) -> bool {
+        if self.oversized.is_none() && data.len() <= MAX_FRAME_SIZE {
+            return false;
+        }
+        let (len, head) = self.oversized.get_or_insert_with(Default::default);
+        if head.is_empty() {
+            let sample = data.len().min(DecodeDiagnostic::SAMPLE_LEN);
+            head.extend_from_slice(&data[..sample]);
+        }
+        *len += data.len();
+        true
+    }
+
+    /// Skip a line longer than `MAX_FRAME_SIZE`.
+    fn skip_oversized(&self, len: usize, head: &[u8]) -> Option<LazyEvent> {
+        let msg = format!("oversized frame: {} bytes (max {})", len, MAX_FRAME_SIZE);
+        self.report_skipped(DiagnosticKind::Oversized, msg, head);
+        None
+    }
+
+    /// Decode a single frame, resynchronizing at stream start.
+    ///
+    /// Before the first decoded frame, a line which cannot be decoded is
+    /// assumed to be the tail of a partial frame, and is discarded.
+    fn decode_frame(&mut self, frame: &[u8]) -> Result<Option<LazyEvent>, OgaError> {
+        if self.synced {
+            return self.decode_line(frame);
+        }
+
+        let lazy = std::str::from_utf8(frame)
+            .ok()
+            .and_then(|line| LazyEvent::parse_frame(line).ok());
+        match lazy {
+            Some(lazy) => {
+                self.synced = true;
+                Ok(Some(lazy))
+            }
+            None => {
+                // Account for the newline too.
+                self.discarded += frame.len() + 1;
+                let msg = format!(
+                    "discarded {} bytes of partial frame at stream start",
+                    frame.len() + 1
+                );
+                log::debug!("{}", msg);
+                self.report(DiagnosticKind::Resync, msg, frame);
+                Ok(None)
+            }
+        }
+    }
+
+    /// Decode a single frame into an event, reporting skipped frames.
+    fn decode_line(&self, frame: &[u8]) -> Result<Option<LazyEvent>, OgaError> {
+        let line = match (std::str::from_utf8(frame), self.utf8_policy) {
+            (Ok(line), _) => Ok(line.into()),
+            (Err(_), Utf8Policy::Lossy) => Ok(String::from_utf8_lossy(frame)),
+            (Err(e), _) => Err(e),
+        };
+        let (kind, msg) = match line.as_deref().map(LazyEvent::parse_frame) {
+            Ok(Ok(lazy)) => return Ok(Some(lazy)),
+            Ok(Err(e)) => (
+                DiagnosticKind::Unrecognized,
+                format!("unrecognized event: {}", e.0),
+            ),
+            Err(e) => (
+                DiagnosticKind::InvalidUtf8,
+                format!("invalid UTF-8 frame: {}", e),
+            ),
+        };
+
+        // A frame cut short runs into the next one, which can still be recovered.
+        if let Some((pos, lazy)) = recover_frame(frame) {
+            let msg = format!(
+                "skipped {} bytes of truncated frame before '{}' event",
+                pos,
+                lazy.name()
+            );
+            log::debug!("{}", msg);
+            self.report(DiagnosticKind::Truncated, msg, frame);
+            return Ok(Some(lazy));
+        }
+
+        if kind == DiagnosticKind::InvalidUtf8 && self.utf8_policy == Utf8Policy::Terminate {
+            return Err(msg.into());
+        }
+        self.report_skipped(kind, msg, frame);
+        Ok(None)
+    }
+
+    /// Deliver a diagnostic for the current frame, if a hook is set.
+    fn report(&self, kind: DiagnosticKind, error: String, frame: &[u8]) {
+        if let Some(hook) = &self.diagnostics {
+            (hook.0)(DecodeDiagnostic::new(kind, self.frame_offset, error, frame));
+        }
+    }
+
+    /// Deliver a diagnostic for a skipped frame, or log it if no hook is set.
+    pub(crate) fn report_skipped(&self, kind: DiagnosticKind, error: String, frame: &[u8]) {
+        match &self.diagnostics {
+            Some(_) => {
+                log::debug!("skipped frame at offset {}: {}", self.frame_offset, error);
+                self.report(kind, error, frame);
+            }
+            None => {
+                let diag = DecodeDiagnostic::new(kind, self.frame_offset, error, frame);
+                log::warn!(
+                    "transient error, skipped frame: {} ('{}')",
+                    diag.error,
+                    diag.sample
+                );
+            }
+        }
+    }
+}
+
+/// Maximum number of frame starts tried, when recovering from a truncated frame.
+const MAX_RECOVERY_ATTEMPTS: usize = 64;
+
+/// Recover a complete frame at the end of a line, following a truncated one.
+///
+/// Each `{` past the start of the line is tried in order as the start of
+/// a frame, and the first one leading to a well-formed event wins. This
+/// returns the event along with its offset in the line.
+fn recover_frame(frame: &[u8]) -> Option<(usize, LazyEvent)> {
+    frame
+        .iter()
+        .enumerate()
+        .skip(1)
+        .filter(|(_, b)| **b == b'{')
+        .take(MAX_RECOVERY_ATTEMPTS)
+        .find_map(|(pos, _)| {
+            let line = std::str::from_utf8(&frame[pos..]).ok()?;
+            let lazy = LazyEvent::parse_frame(line).ok()?;
+            Some((pos, lazy))
+        })
+}
+
+/// Encode a command as a single newline-terminated frame.
+///
+/// This guards the line-based framing against `as_frame()` implementations
+/// emitting embedded newlines: valid JSON is re-encoded in compact form,
+/// anything else is rejected.
+pub fn encode_frame(cmd: &dyn AsFrame) -> Result<Bytes, OgaError> {
+    let data = cmd.as_frame()?;
+    let err = match check_frame(cmd.name(), &data) {
+        Ok(_) => return Ok(data),
+        Err(e) => e,
+    };
+
+    let body = data.strip_suffix(b"\n").unwrap_or(&data);
+    let value: serde_json::Value = serde_json::from_slice(body).map_err(|_| err)?;
+    let msg = json_frame(&value)?;
+    check_frame(cmd.name(), &msg)?;
+    log::debug!("re-encoded '{}' frame in compact form", cmd.name());
+    Ok(msg)
+}
+
+/// Encode a command as a single newline-terminated frame, appending it to a buffer.
+///
+/// This is the streaming counterpart of `encode_frame()`, performing the
+/// same checks. On errors, the buffer is left unchanged.
+pub fn write_frame(cmd: &dyn AsFrame, dst: &mut BytesMut) -> Result<(), OgaError> {
+    let start = dst.len();
+    let res = cmd
+        .write_frame(dst)
+        .and_then(|_| check_frame(cmd.name(), &dst[start..]));
+    if res.is_ok() {
+        return Ok(());
+    }
+
+    // Go through the regular path, which re-encodes non-compact frames.
+    dst.truncate(start);
+    let frame = encode_frame(cmd)?;
+    dst.extend_from_slice(&frame);
+    Ok(())
+}
+
+/// Check that an encoded frame can be safely written to the line-based channel.
+///
+/// Frames must fit within `MAX_FRAME_SIZE`, and must not contain control
+/// characters except for the single trailing newline.
+fn check_frame(name: &str, data: &[u8]) -> Result<(), OgaError> {
+    if data.len() > MAX_FRAME_SIZE {
+        let msg = format!(
+            "oversized '{}' frame: {} bytes (max {})",
+            name,
+            data.len(),
+            MAX_FRAME_SIZE
+        );
+        return Err(msg.into());
+    }
+
+    let body = data
+        .strip_suffix(b"\n")
+        .ok_or_else(|| format!("unterminated '{}' frame", name))?;
+    if let Some(pos) = body.iter().position(|b| *b < 0x20) {
+        let msg = format!(
+            "invalid '{}' frame: control character 0x{:02x} at offset {}",
+            name, body[pos], pos
+        );
+        return Err(msg.into());
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    const LOCK_SCREEN: &[u8] = b"{\"__name__\":\"lock-screen\"}\n";
+
+    /// Return a decoder, along with the diagnostics it delivers.
+    fn decoder() -> (FrameDecoder, Arc<Mutex<Vec<DecodeDiagnostic>>>) {
+        let diagnostics = Arc::new(Mutex::new(Vec::new()));
+        let sink = diagnostics.clone();
+        let decoder = FrameDecoder::new().diagnostics_hook(move |diagnostic| {
+            sink.lock().unwrap().push(diagnostic);
+        });
+        (decoder, diagnostics)
+    }
+
+    #[test]
+    fn trip_and_reset_breaker() {
+        let mut decoder = FrameDecoder::new().max_decode_failures(3);
+        decoder.push(LOCK_SCREEN);
+        assert!(decoder.next_event().unwrap().is_some());
+
+        // A decoded frame resets the count of consecutive failures.
+        decoder.push(b"garbage\n{\"foo\":1}\n");
+        decoder.push(LOCK_SCREEN);
+        assert!(decoder.next_event().unwrap().is_some());
+        decoder.push(b"garbage\n{\"foo\":1}\n");
+        assert!(decoder.next_event().unwrap().is_none());
+
+        decoder.push(b"garbage\n");
+        let err = decoder.next_event().unwrap_err();
+        assert!(err.0.contains("3 consecutive frames"), "{}", err);
+    }
+
+    #[test]
+    fn count_resync_discards() {
+        let (mut decoder, diagnostics) = decoder();
+        let partial = b"me\":\"lock-screen\"}\n";
+        let junk = b"junk\n";
+        decoder.push(partial);
+        decoder.push(junk);
+        decoder.push(LOCK_SCREEN);
+        assert!(decoder.next_event().unwrap().is_some());
+        assert_eq!(decoder.discarded(), partial.len() + junk.len());
+
+        // Once synchronized, bad frames are not discarded as partial ones.
+        decoder.push(junk);
+        decoder.push(LOCK_SCREEN);
+        assert!(decoder.next_event().unwrap().is_some());
+        assert_eq!(decoder.discarded(), partial.len() + junk.len());
+
+        let kinds: Vec<_> = diagnostics.lock().unwrap().iter().map(|d| d.kind).collect();
+        assert_eq!(
+            kinds,
+            [
+                DiagnosticKind::Resync,
+                DiagnosticKind::Resync,
+                DiagnosticKind::Unrecognized
+            ]
+        );
+    }
+
+    #[test]
+    fn resync_does_not_trip_breaker() {
+        let mut decoder = FrameDecoder::new().max_decode_failures(1);
+        decoder.push(b"me\":\"lock-screen\"}\n");
+        decoder.push(b"junk\n");
+        decoder.push(LOCK_SCREEN);
+        assert!(decoder.next_event().unwrap().is_some());
+
+        decoder.push(b"junk\n");
+        let err = decoder.next_event().unwrap_err();
+        assert!(err.0.contains("1 consecutive frames"), "{}", err);
+    }
+
+    #[test]
+    fn deliver_diagnostics() {
+        let (decoder, diagnostics) = decoder();
+        let mut decoder = decoder.utf8_policy(Utf8Policy::Skip);
+        let truncated = b"{\"__name__\":\"lo{\"__name__\":\"lock-screen\"}\n";
+        let invalid = b"{\"__name__\":\"\xff\"}\n";
+        let unknown = b"{\"foo\":1}\n";
+        decoder.push(LOCK_SCREEN);
+        decoder.push(truncated);
+        decoder.push(invalid);
+        decoder.push(unknown);
+        decoder.push(LOCK_SCREEN);
+        let mut names = vec![];
+        while let Some(lazy) = decoder.next_event().unwrap() {
+            names.push(lazy.name().to_string());
+        }
+        assert_eq!(names, ["lock-screen"; 3]);
+
+        let diagnostics = diagnostics.lock().unwrap();
+        let found: Vec<_> = diagnostics.iter().map(|d| (d.kind, d.offset)).collect();
+        let mut offset = LOCK_SCREEN.len();
+        let mut expected = vec![(DiagnosticKind::Truncated, offset as u64)];
+        offset += truncated.len();
+        expected.push((DiagnosticKind::InvalidUtf8, offset as u64));
+        offset += invalid.len();
+        expected.push((DiagnosticKind::Unrecognized, offset as u64));
+        assert_eq!(found, expected);
+        assert!(diagnostics[0].sample.starts_with(r#"{"__name__":"lo{"#));
+        assert_eq!(diagnostics[1].sample, "{\"__name__\":\"\u{fffd}\"}");
+        assert_eq!(diagnostics[2].sample, "");
+    }
+
+    #[test]
+    fn redact_samples() {
+        let sample = |frame: &str| {
+            let diag = DecodeDiagnostic::new(
+                DiagnosticKind::Unrecognized,
+                0,
+                String::new(),
+                frame.as_bytes(),
+            );
+            diag.sample
+        };
+
+        assert_eq!(
+            sample(r#"{"__name__":"shutdown","timeout":"soon"}"#),
+            r#"{"__name__":"shutdown","timeout":"soon"}"#
+        );
+        assert_eq!(
+            sample(r#"{"__name__":"echo","a":{"Token":"t"}}"#),
+            r#"{"__name__":"echo","a":{"Token":"<redacted>"}}"#
+        );
+        assert_eq!(
+            sample(r#"{"__name__":"echo","b":[{"x":1,"PassWd":2}]}"#),
+            r#"{"__name__":"echo","b":[{"PassWd":"<redacted>","x":1}]}"#
+        );
+        let long = format!(r#"{{"__name__":"echo","data":"{}"}}"#, "x".repeat(100));
+        assert_eq!(sample(&long).len(), DecodeDiagnostic::SAMPLE_LEN);
+
+        // Credentials may hide anywhere in other frames.
+        assert_eq!(
+            sample(r#"{"__name__":"login","username":"u","pw":"p"}"#),
+            ""
+        );
+        assert_eq!(sample(r#"{"__name__":"custom","pw":"p"}"#), "");
+        assert_eq!(sample(r#"{"pw":"p"}"#), "");
+
+        // Malformed frames are sampled as they are, unless they look sensitive.
+        assert_eq!(
+            sample(r#"{"__name__":"shutdown","message":"b"#),
+            r#"{"__name__":"shutdown","message":"b"#
+        );
+        assert_eq!(sample(&"x".repeat(100)).len(), DecodeDiagnostic::SAMPLE_LEN);
+        assert_eq!(sample(r#"{"__name__":"LOGIN","user":"u"#), "");
+        assert_eq!(sample(r#"{"__name__":"x","apiToken":"t"#), "");
+    }
+
+    #[test]
+    fn discard_oversized_frames() {
+        let (mut decoder, diagnostics) = decoder();
+        decoder.push(LOCK_SCREEN);
+        assert!(decoder.next_event().unwrap().is_some());
+
+        // An unterminated line is not buffered past the limit.
+        let chunk = vec![b'x'; 64 * 1024];
+        for _ in 0..20 {
+            decoder.push(&chunk);
+            assert!(decoder.next_event().unwrap().is_none());
+            assert!(decoder.buffered() <= MAX_FRAME_SIZE);
+        }
+        decoder.push(b"xx\n");
+        decoder.push(LOCK_SCREEN);
+        let lazy = decoder.next_event().unwrap().unwrap();
+        assert_eq!(lazy.name(), "lock-screen");
+        assert_eq!(decoder.buffered(), 0);
+
+        // A complete overlong line is skipped too.
+        let mut line = vec![b'x'; MAX_FRAME_SIZE + 1];
+        line.push(b'\n');
+        decoder.push(&line);
+        decoder.push(LOCK_SCREEN);
+        assert!(decoder.next_event().unwrap().is_some());
+
+        let diagnostics = diagnostics.lock().unwrap();
+        let oversized = 20 * chunk.len() + 2;
+        assert_eq!(diagnostics.len(), 2);
+        assert_eq!(diagnostics[0].kind, DiagnosticKind::Oversized);
+        assert_eq!(diagnostics[0].offset, LOCK_SCREEN.len() as u64);
+        assert!(diagnostics[0].error.contains(&oversized.to_string()));
+        let sample = "x".repeat(DecodeDiagnostic::SAMPLE_LEN);
+        assert_eq!(diagnostics[0].sample, sample);
+        assert_eq!(diagnostics[1].kind, DiagnosticKind::Oversized);
+        let offset = 2 * LOCK_SCREEN.len() + oversized + 1;
+        assert_eq!(diagnostics[1].offset, offset as u64);
+    }
+
+    #[test]
+    fn oversized_frames_count_as_failures() {
+        let mut decoder = FrameDecoder::new().max_decode_failures(2);
+        decoder.push(LOCK_SCREEN);
+        assert!(decoder.next_event().unwrap().is_some());
+        for _ in 0..2 {
+            decoder.push(&vec![b'x'; MAX_FRAME_SIZE + 1]);
+            decoder.push(b"\n");
+        }
+        decoder.next_event().unwrap_err();
+    }
+}
@@ -0,0 +1,203 @@
+/*! Low-level framed access to the protocol channel.
+
+This provides direct access to the opened VirtIO port as a framed
+`Stream` of host events and `Sink` of guest commands, without any of
+the internal tasks (heartbeats, dispatching, broadcasting) which are
+run by [`OgaClient`](../struct.OgaClient.html).
+
+Consumers are responsible for driving the protocol on their own,
+including sending periodic heartbeats.
+*/
+
+use crate::commands::AsFrame;
+use crate::errors::OgaError;
+use crate::events::{Event, LazyEvent};
+use crate::protocol;
+use crate::protocol::DiagnosticKind;
+pub use crate::protocol::{DecodeDiagnostic, Utf8Policy};
+use crate::virtio::VirtioPort;
+use bytes::BytesMut;
+use futures::{Sink, Stream};
+use std::path::Path;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use tokio_util::codec::{Decoder, Encoder, Framed};
+
+/// Codec for newline-delimited protocol frames.
+///
+/// Frames which cannot be parsed as known events are skipped, and either
+/// logged or reported to the diagnostics hook.
+/// Like `protocol::FrameDecoder`, this resynchronizes on a partial frame
+/// at stream start, and discards lines longer than `MAX_FRAME_SIZE`.
+#[derive(Clone, Debug, Default)]
+pub struct OgaCodec {
+    lines: protocol::LineDecoder,
+}
+
+impl OgaCodec {
+    /// Return a new codec.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// How to handle frames which are not valid UTF-8 (default: terminate).
+    pub fn utf8_policy(mut self, policy: Utf8Policy) -> Self {
+        self.lines.utf8_policy = policy;
+        self
+    }
+
+    /// Maximum number of consecutive frames which fail decoding, or zero to disable (default).
+    ///
+    /// Once reached, decoding fails with a protocol error.
+    pub fn max_decode_failures(mut self, limit: usize) -> Self {
+        self.lines.max_failures = limit;
+        self
+    }
+
+    /// Deliver diagnostics about skipped or repaired frames to a hook, instead of logging them.
+    pub fn diagnostics_hook(
+        mut self,
+        hook: impl Fn(DecodeDiagnostic) + Send + Sync + 'static,
+    ) -> Self {
+        self.lines.set_diagnostics_hook(hook);
+        self
+    }
+
+    /// Number of bytes discarded so far, while resynchronizing at stream start.
+    pub fn discarded(&self) -> usize {
+        self.lines.discarded
+    }
+
+    /// Report an event which failed parsing, as a skipped frame.
+    fn report_unrecognized(&self, lazy: &LazyEvent, err: OgaError) {
+        let msg = format!("unrecognized '{}' event: {}", lazy.name(), err.0);
+        self.lines
+            .report_skipped(DiagnosticKind::Unrecognized, msg, lazy.raw().as_bytes());
+    }
+}
+
+impl OgaCodec {
+    /// Decode the next frame, only parsing its event name.
+    fn decode_lazy(&mut self, src: &mut BytesMut) -> Result<Option<LazyEvent>, OgaError> {
+        while let Some(pos) = src.iter().position(|b| *b == b'\n') {
+            let frame = src.split_to(pos + 1);
+            if let Some(lazy) = self.lines.decode(&frame[..pos])? {
+                return Ok(Some(lazy));
+            }
+        }
+        // Do not buffer an overlong line, only account for it.
+        if self.lines.discard_unterminated(src) {
+            src.clear();
+        }
+        Ok(None)
+    }
+}
+
+impl Decoder for OgaCodec {
+    type Item = Event;
+    type Error = OgaError;
+
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
+        while let Some(lazy) = self.decode_lazy(src)? {
+            match Event::parse_frame(lazy.raw().as_bytes()) {
+                Ok(event) => return Ok(Some(event)),
+                Err(e) => self.report_unrecognized(&lazy, e),
+            }
+        }
+        Ok(None)
+    }
+}
+
+/// Codec for newline-delimited protocol frames, with lazily-parsed events.
+///
+/// Frames without a valid event name are logged and skipped; parsing
+/// the rest of each event is left to consumers.
+#[derive(Clone, Debug, Default)]
+pub struct LazyCodec {
+    inner: OgaCodec,
+}
+
+impl LazyCodec {
+    /// Return a new codec, wrapping the given one.
+    pub fn new(inner: OgaCodec) -> Self {
+        Self { inner }
+    }
+
+    /// Number of bytes discarded so far, while resynchronizing at stream start.
+    pub fn discarded(&self) -> usize {
+        self.inner.discarded()
+    }
+
+    /// Deliver diagnostics about skipped or repaired frames to a hook, instead of logging them.
+    pub fn diagnostics_hook(self, hook: impl Fn(DecodeDiagnostic) + Send + Sync + 'static) -> Self {
+        Self::new(self.inner.diagnostics_hook(hook))
+    }
+
+    /// Offset of the last decoded frame from stream start, in bytes.
+    pub(crate) fn frame_offset(&self) -> u64 {
+        self.inner.lines.frame_offset
+    }
+}
+
+impl Decoder for LazyCodec {
+    type Item = LazyEvent;
+    type Error = OgaError;
+
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
+        self.inner.decode_lazy(src)
+    }
+}
+
+impl Encoder<Box<dyn AsFrame>> for OgaCodec {
+    type Error = OgaError;
+
+    fn encode(&mut self, item: Box<dyn AsFrame>, dst: &mut BytesMut) -> Result<(), Self::Error> {
+        item.validate()?;
+        protocol::write_frame(item.as_ref(), dst)
+    }
+}
+
+/// Framed protocol channel over a VirtIO serial port.
+///
+/// This is a `Stream` of host events and a `Sink` of guest commands.
+#[derive(Debug)]
+pub struct RawChannel {
+    inner: Framed<VirtioPort, OgaCodec>,
+}
+
+/// Open a framed protocol channel on the VirtIO serial port at given path.
+///
+/// This must be called from within a tokio runtime context.
+pub fn open(path: impl AsRef<Path>) -> Result<RawChannel, OgaError> {
+    let dev = VirtioPort::open(path)?;
+    let inner = Framed::new(dev, OgaCodec::new());
+    Ok(RawChannel { inner })
+}
+
+impl Stream for RawChannel {
+    type Item = Result<Event, OgaError>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        Pin::new(&mut self.inner).poll_next(cx)
+    }
+}
+
+impl Sink<Box<dyn AsFrame>> for RawChannel {
+    type Error = OgaError;
+
+    fn poll_ready(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Pin::new(&mut self.inner).poll_ready(cx)
+    }
+
+    fn start_send(mut self: Pin<&mut Self>, item: Box<dyn AsFrame>) -> Result<(), Self::Error> {
+        Pin::new(&mut self.inner).start_send(item)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Pin::new(&mut self.inner).poll_close(cx)
+    }
+}
@@ -0,0 +1,76 @@
+//! In-memory retry queue for commands, across reconnections.
+
+use crate::errors::OgaError;
+use crate::FramePlusChan;
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+
+/// Queue of commands which were not written when a client terminated.
+///
+/// When configured on a builder, commands pending at client termination are
+/// kept here instead of failing, and retransmitted by the next `connect()`
+/// on a builder sharing the same queue (clones share their content).
+/// Senders keep waiting for the outcome until then. Commands partially
+/// written to the device are never retransmitted, and commands sent after
+/// termination fail right away.
+#[derive(Clone, Debug)]
+pub struct RetryQueue {
+    capacity: usize,
+    pending: Arc<Mutex<VecDeque<FramePlusChan>>>,
+}
+
+impl RetryQueue {
+    /// Return an empty queue, holding at most `capacity` commands.
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            pending: Arc::new(Mutex::new(VecDeque::new())),
+        }
+    }
+
+    /// Number of commands waiting for retransmission.
+    pub fn len(&self) -> usize {
+        self.pending.lock().map(|q| q.len()).unwrap_or(0)
+    }
+
+    /// Whether no commands are waiting for retransmission.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Queue a command for retransmission, failing it if the queue is full.
+    ///
+    /// Flush barriers are not retransmitted, and fail right away.
+    pub(crate) fn push(&self, item: FramePlusChan) {
+        if item.0.is_marker() {
+            let _ = item
+                .1
+                .send(Err(OgaError::from("client terminated before flushing")));
+            return;
+        }
+        let mut pending = match self.pending.lock() {
+            Ok(pending) => pending,
+            Err(_) => return,
+        };
+        if pending.len() >= self.capacity {
+            let (cmd, chan) = item;
+            log::warn!("retry queue full, dropped '{}' command", cmd.name);
+            let _ = chan.send(Err(OgaError::from("retry queue full")));
+            return;
+        }
+        log::debug!("queued '{}' command for retransmission", item.0.name);
+        pending.push_back(item);
+    }
+
+    /// Put back a command at the head of the queue, after a failed retransmission.
+    pub(crate) fn push_front(&self, item: FramePlusChan) {
+        if let Ok(mut pending) = self.pending.lock() {
+            pending.push_front(item);
+        }
+    }
+
+    /// Take the next command to retransmit.
+    pub(crate) fn pop(&self) -> Option<FramePlusChan> {
+        self.pending.lock().ok()?.pop_front()
+    }
+}
@@ -0,0 +1,91 @@
+//! Sensitive values.
+
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use zeroize::Zeroizing;
+
+/// Placeholder emitted in place of serialized secrets.
+pub(crate) const REDACTED: &str = "<redacted>";
+
+/// Sensitive string value (e.g. a password).
+///
+/// The content is wiped from memory on drop, and never shown in `Debug` output.
+/// Serialization emits a redacted placeholder; the plaintext can only be
+/// serialized through an explicit `exposed()` wrapper.
+#[derive(Clone, PartialEq, Eq)]
+pub struct Secret(Zeroizing<String>);
+
+impl Secret {
+    /// Wrap a sensitive string value.
+    pub fn new(value: String) -> Self {
+        Self(Zeroizing::new(value))
+    }
+
+    /// Access the sensitive content.
+    pub fn expose(&self) -> &str {
+        self.0.as_str()
+    }
+
+    /// Return a wrapper which serializes the plaintext content.
+    pub fn exposed(&self) -> ExposedSecret<'_> {
+        ExposedSecret(self)
+    }
+}
+
+/// Serializable view of a secret, exposing its plaintext content.
+///
+/// This is returned by `Secret::exposed()`.
+#[derive(Clone, Copy)]
+pub struct ExposedSecret<'a>(&'a Secret);
+
+impl std::fmt::Debug for ExposedSecret<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+impl Serialize for ExposedSecret<'_> {
+    fn serialize<S: Serializer>(&self, ser: S) -> Result<S::Ok, S::Error> {
+        ser.serialize_str(self.0.expose())
+    }
+}
+
+impl From<String> for Secret {
+    fn from(arg: String) -> Self {
+        Self::new(arg)
+    }
+}
+
+impl std::fmt::Debug for Secret {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str("Secret(<redacted>)")
+    }
+}
+
+impl<'de> Deserialize<'de> for Secret {
+    fn deserialize<D: Deserializer<'de>>(de: D) -> Result<Self, D::Error> {
+        String::deserialize(de).map(Self::new)
+    }
+}
+
+impl Serialize for Secret {
+    fn serialize<S: Serializer>(&self, ser: S) -> Result<S::Ok, S::Error> {
+        ser.serialize_str(REDACTED)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn serialize_redacted() {
+        let secret = Secret::new("hunter2".to_string());
+        let json = serde_json::to_string(&secret).unwrap();
+        assert_eq!(json, r#""<redacted>""#);
+        assert_eq!(format!("{:?}", secret), "Secret(<redacted>)");
+        assert_eq!(format!("{:?}", secret.exposed()), "Secret(<redacted>)");
+
+        let json = serde_json::to_string(&secret.exposed()).unwrap();
+        assert_eq!(json, r#""hunter2""#);
+    }
+}
@@ -0,0 +1,74 @@
+//! Runtime statistics for a client.
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+/// Fill level of an internal queue.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub struct QueueStats {
+    /// Maximum number of entries.
+    pub capacity: usize,
+    /// Current number of entries.
+    pub depth: usize,
+    /// Highest number of entries observed so far.
+    pub high_water: usize,
+}
+
+/// Snapshot of client statistics.
+///
+/// Queues steadily close to their capacity indicate chronic backpressure,
+/// which eventually results in stalled senders or dropped events.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub struct ClientStats {
+    /// Commands from the application, waiting for dispatching.
+    ///
+    /// Each sender has its own queue; this reports the fullest one.
+    pub commands: QueueStats,
+    /// Commands for the primary channel, waiting to be written.
+    pub outgoing: QueueStats,
+    /// Events from all channels, waiting for dispatching.
+    pub incoming: QueueStats,
+    /// Primary events, not yet received by the slowest subscriber.
+    pub events: QueueStats,
+    /// Tagged events, not yet received by the slowest subscriber.
+    pub tagged_events: QueueStats,
+}
+
+/// High-water marks of internal queues, shared between client and tasks.
+#[derive(Debug, Default)]
+pub(crate) struct Gauges {
+    pub(crate) commands: HighWater,
+    pub(crate) outgoing: HighWater,
+    pub(crate) incoming: HighWater,
+    pub(crate) events: HighWater,
+    pub(crate) tagged_events: HighWater,
+}
+
+impl Gauges {
+    /// Return a new set of shared gauges.
+    pub(crate) fn shared() -> Arc<Self> {
+        Arc::new(Self::default())
+    }
+}
+
+/// Highest observed depth of a queue.
+#[derive(Debug, Default)]
+pub(crate) struct HighWater(AtomicUsize);
+
+impl HighWater {
+    /// Record an observed queue depth.
+    pub(crate) fn observe(&self, depth: usize) {
+        self.0.fetch_max(depth, Ordering::Relaxed);
+    }
+
+    /// Return a snapshot for a queue with the given capacity and current depth.
+    pub(crate) fn snapshot(&self, capacity: usize, depth: usize) -> QueueStats {
+        self.observe(depth);
+        QueueStats {
+            capacity,
+            depth,
+            high_water: self.0.load(Ordering::Relaxed),
+        }
+    }
+}
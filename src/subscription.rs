@@ -0,0 +1,209 @@
+//! Event subscriptions.
+
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::sync::Mutex;
+use tokio::sync::broadcast;
+use tokio::sync::broadcast::error::RecvError;
+
+/// How subscriptions resume after lagging behind and missing events.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum LagPolicy {
+    /// Silently skip missed events.
+    Skip,
+    /// Deliver a `Received::EventsDropped` marker, then resume.
+    #[default]
+    Marker,
+    /// End the subscription.
+    Terminate,
+}
+
+/// Item delivered by an event subscription.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Received<T> {
+    /// Event from the host.
+    Event(T),
+    /// Count of events missed because of lagging behind.
+    EventsDropped(u64),
+}
+
+impl<T> Received<T> {
+    /// Return the event, if this is not a marker.
+    pub fn into_event(self) -> Option<T> {
+        match self {
+            Received::Event(ev) => Some(ev),
+            Received::EventsDropped(_) => None,
+        }
+    }
+}
+
+/// Subscription to events from the host.
+#[derive(Debug)]
+pub struct EventReceiver<T> {
+    inner: broadcast::Receiver<T>,
+    policy: LagPolicy,
+    terminated: bool,
+    /// Events received before subscribing, to be delivered first.
+    replay: VecDeque<T>,
+}
+
+impl<T: Clone> EventReceiver<T> {
+    pub(crate) fn new(inner: broadcast::Receiver<T>, policy: LagPolicy) -> Self {
+        Self {
+            inner,
+            policy,
+            terminated: false,
+            replay: VecDeque::new(),
+        }
+    }
+
+    /// Receive the next item, following the lag policy.
+    ///
+    /// This returns `None` once the subscription has ended, either because
+    /// the client terminated or because of lagging under `LagPolicy::Terminate`.
+    pub async fn recv(&mut self) -> Option<Received<T>> {
+        if let Some(ev) = self.replay.pop_front() {
+            return Some(Received::Event(ev));
+        }
+        if self.terminated {
+            return None;
+        }
+        loop {
+            match self.inner.recv().await {
+                Ok(ev) => return Some(Received::Event(ev)),
+                Err(RecvError::Closed) => break,
+                Err(RecvError::Lagged(n)) => match self.policy {
+                    LagPolicy::Skip => log::trace!("subscriber skipped {} events", n),
+                    LagPolicy::Marker => return Some(Received::EventsDropped(n)),
+                    LagPolicy::Terminate => {
+                        log::warn!("subscription terminated after missing {} events", n);
+                        break;
+                    }
+                },
+            }
+        }
+        self.terminated = true;
+        None
+    }
+
+    /// Receive the next event, skipping markers.
+    pub async fn recv_event(&mut self) -> Option<T> {
+        loop {
+            if let Received::Event(ev) = self.recv().await? {
+                return Some(ev);
+            }
+        }
+    }
+}
+
+/// Events received before the first subscription, replayed to it.
+///
+/// Without subscribers, events sent to a broadcast channel are lost. This
+/// holds the first ones instead, until the first subscription takes them.
+#[derive(Debug)]
+pub(crate) struct ReplayBuffer<T> {
+    capacity: usize,
+    /// Held events, until taken by the first subscription.
+    events: Mutex<Option<VecDeque<T>>>,
+}
+
+impl<T: Clone> Default for ReplayBuffer<T> {
+    fn default() -> Self {
+        Self::new(0)
+    }
+}
+
+impl<T: Clone> ReplayBuffer<T> {
+    /// Return a buffer holding up to `capacity` events, or a disabled one if zero.
+    pub(crate) fn new(capacity: usize) -> Self {
+        let events = match capacity {
+            0 => None,
+            n => Some(VecDeque::with_capacity(n)),
+        };
+        Self {
+            capacity,
+            events: Mutex::new(events),
+        }
+    }
+
+    /// Hold an event sent to a channel, if nobody ever subscribed to it yet.
+    ///
+    /// This returns whether the event was consumed, in which case it must
+    /// not be sent. Events past the buffer capacity are dropped.
+    pub(crate) fn hold(&self, chan: &broadcast::Sender<T>, event: &T) -> bool {
+        let mut events = match self.events.lock() {
+            Ok(events) => events,
+            Err(_) => return false,
+        };
+        match events.as_mut() {
+            Some(held) if chan.receiver_count() == 0 => {
+                if held.len() < self.capacity {
+                    held.push_back(event.clone());
+                } else {
+                    log::debug!("no subscribers yet, dropped early event");
+                }
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Whether events are still being held, waiting for the first subscription.
+    pub(crate) fn is_holding(&self) -> bool {
+        self.events.lock().is_ok_and(|events| events.is_some())
+    }
+
+    /// Subscribe to a channel, replaying held events to the first subscription.
+    pub(crate) fn subscribe(
+        &self,
+        chan: &broadcast::Sender<T>,
+        policy: LagPolicy,
+    ) -> EventReceiver<T> {
+        let mut events = self.events.lock().ok();
+        let mut receiver = EventReceiver::new(chan.subscribe(), policy);
+        if let Some(held) = events.as_mut().and_then(|events| events.take()) {
+            receiver.replay = held;
+        }
+        receiver
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn replay_to_first_subscriber_only() {
+        let chan = {
+            let bcast = broadcast::channel(8);
+            drop(bcast.1);
+            bcast.0
+        };
+        let early = ReplayBuffer::new(2);
+        for ev in 1..=3 {
+            assert!(early.hold(&chan, &ev));
+        }
+
+        let mut first = early.subscribe(&chan, LagPolicy::default());
+        let mut second = early.subscribe(&chan, LagPolicy::default());
+        assert!(!early.is_holding());
+        assert!(!early.hold(&chan, &4));
+        chan.send(4).unwrap();
+
+        // The third event was past the buffer capacity.
+        for ev in [1, 2, 4] {
+            assert_eq!(first.recv_event().await, Some(ev));
+        }
+        assert_eq!(second.recv_event().await, Some(4));
+        assert!(second.replay.is_empty());
+    }
+
+    #[test]
+    fn disabled_replay() {
+        let (chan, _) = broadcast::channel(8);
+        let early = ReplayBuffer::<u8>::new(0);
+        assert!(!early.is_holding());
+        assert!(!early.hold(&chan, &1));
+    }
+}
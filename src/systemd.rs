@@ -0,0 +1,109 @@
+/*! systemd service integration.
+
+This implements the `sd_notify` protocol for service readiness
+notifications, without linking to libsystemd.
+
+References:
+ * <https://www.freedesktop.org/software/systemd/man/sd_notify.html>
+*/
+
+use crate::errors::OgaError;
+use serde::{Deserialize, Serialize};
+use std::os::linux::net::SocketAddrExt;
+use std::os::unix::net::{SocketAddr, UnixDatagram};
+use tokio::sync::watch;
+use tokio::time::{self, Duration, Instant};
+
+/// Environment variable with the path to the notification socket.
+static ENV_NOTIFY_SOCKET: &str = "NOTIFY_SOCKET";
+/// Environment variable with the watchdog timeout, in microseconds.
+static ENV_WATCHDOG_USEC: &str = "WATCHDOG_USEC";
+/// Environment variable with the PID expected to send watchdog keepalives.
+static ENV_WATCHDOG_PID: &str = "WATCHDOG_PID";
+/// Shortest interval between watchdog keepalives.
+const MIN_KEEPALIVE_PERIOD: Duration = Duration::from_millis(1);
+
+/// When to notify service readiness (`READY=1`) to systemd.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum NotifyReady {
+    /// Never notify readiness.
+    #[default]
+    Disabled,
+    /// Notify readiness once the client has connected.
+    OnConnect,
+    /// Notify readiness once the first event from the host has been received.
+    OnFirstEvent,
+}
+
+/// Send a state notification to the service manager.
+///
+/// This returns `false` if not running under a service manager
+/// (i.e. `NOTIFY_SOCKET` is not set).
+pub fn notify(state: &str) -> Result<bool, OgaError> {
+    let path = match std::env::var_os(ENV_NOTIFY_SOCKET) {
+        Some(p) if !p.is_empty() => p,
+        _ => return Ok(false),
+    };
+
+    let addr = match path.to_str().and_then(|p| p.strip_prefix('@')) {
+        Some(name) => SocketAddr::from_abstract_name(name.as_bytes()),
+        None => SocketAddr::from_pathname(&path),
+    }
+    .map_err(|e| format!("invalid notification socket address: {}", e))?;
+
+    let sock = UnixDatagram::unbound()
+        .map_err(|e| format!("failed to create notification socket: {}", e))?;
+    sock.send_to_addr(state.as_bytes(), &addr)
+        .map_err(|e| format!("failed to send notification '{}': {}", state, e))?;
+    Ok(true)
+}
+
+/// Notify service readiness, logging any failure.
+pub(crate) fn notify_ready() {
+    match notify("READY=1") {
+        Ok(true) => log::debug!("notified readiness to service manager"),
+        Ok(false) => log::trace!("no service manager, skipped readiness notification"),
+        Err(e) => log::warn!("{}", e),
+    }
+}
+
+/// Return the watchdog timeout configured by the service manager, if any.
+pub(crate) fn watchdog_timeout() -> Option<Duration> {
+    if let Ok(pid) = std::env::var(ENV_WATCHDOG_PID) {
+        if pid.trim() != std::process::id().to_string() {
+            return None;
+        }
+    }
+
+    let usec: u64 = std::env::var(ENV_WATCHDOG_USEC).ok()?.trim().parse().ok()?;
+    if usec == 0 {
+        return None;
+    }
+    Some(Duration::from_micros(usec))
+}
+
+/// Send watchdog keepalives, as long as heartbeats are being delivered.
+///
+/// Keepalives are sent at half the watchdog timeout (at most once per
+/// millisecond), but only while the last heartbeat was delivered within
+/// `max_silence`. A wedged client thus stops feeding the watchdog and gets
+/// restarted by the service manager.
+pub(crate) async fn run_watchdog(
+    pulse: watch::Receiver<Instant>,
+    timeout: Duration,
+    max_silence: Duration,
+) {
+    let mut ticker = time::interval((timeout / 2).max(MIN_KEEPALIVE_PERIOD));
+    loop {
+        ticker.tick().await;
+        let last_beat = *pulse.borrow();
+        if last_beat.elapsed() > max_silence {
+            log::warn!("heartbeats stalled, withholding watchdog keepalive");
+            continue;
+        }
+        if let Err(e) = notify("WATCHDOG=1") {
+            log::warn!("{}", e);
+        }
+    }
+}
@@ -1,23 +1,59 @@
-use crate::events::Event;
+use crate::events::{Event, TaggedEvent};
+use crate::hooks::{DeadLetterSink, EventMiddleware};
+use crate::ping::PingTracker;
+use crate::protocol::{DecodeDiagnostic, DiagnosticKind, TaggedDiagnostic};
+use crate::retry::RetryQueue;
+use crate::stats::Gauges;
+use crate::subscription::ReplayBuffer;
+use crate::tasks::{CommandSources, IncomingEvent};
+use crate::PRIMARY_CHANNEL;
 use crate::{FramePlusChan, OgaError};
 use futures::future::{AbortHandle, AbortRegistration, Abortable};
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
 use tokio::sync::{broadcast, mpsc};
+use tokio::time::{Duration, Instant};
+
+/// Settings for event delivery.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct DispatcherSettings {
+    /// Capacity of the events broadcast channels.
+    pub(crate) events_buffer: usize,
+    pub(crate) middleware: Vec<EventMiddleware>,
+    pub(crate) dedup_window: Duration,
+    pub(crate) dead_letters: Option<DeadLetterSink>,
+    /// Subscribers with their own queue, receiving events with backpressure.
+    pub(crate) queued_subscribers: Arc<Mutex<Vec<mpsc::Sender<TaggedEvent>>>>,
+    /// Queue for commands left unforwarded on termination.
+    pub(crate) retry_queue: Option<RetryQueue>,
+    pub(crate) gauges: Arc<Gauges>,
+    /// Pending echo probes, whose answers are consumed here.
+    pub(crate) pings: PingTracker,
+    /// Primary channel events held for the first subscriber.
+    pub(crate) early_events: Arc<ReplayBuffer<Event>>,
+    /// Channel for diagnostics about events which failed parsing.
+    pub(crate) diagnostics: Option<broadcast::Sender<TaggedDiagnostic>>,
+}
 
 #[derive(Debug)]
 pub(crate) struct DispatcherTask {
     abort: AbortRegistration,
-    chan_from_app: mpsc::Receiver<FramePlusChan>,
-    chan_from_manager: mpsc::Receiver<Event>,
+    chan_from_app: CommandSources,
+    chan_from_manager: mpsc::Receiver<IncomingEvent>,
     chan_to_app: broadcast::Sender<Event>,
+    chan_to_app_tagged: broadcast::Sender<TaggedEvent>,
     chan_to_manager: mpsc::Sender<FramePlusChan>,
+    settings: DispatcherSettings,
 }
 
 impl DispatcherTask {
     pub(crate) fn new(
-        chan_from_app: mpsc::Receiver<FramePlusChan>,
-        chan_from_manager: mpsc::Receiver<Event>,
+        chan_from_app: CommandSources,
+        chan_from_manager: mpsc::Receiver<IncomingEvent>,
         chan_to_app: broadcast::Sender<Event>,
+        chan_to_app_tagged: broadcast::Sender<TaggedEvent>,
         chan_to_manager: mpsc::Sender<FramePlusChan>,
+        settings: DispatcherSettings,
     ) -> (Self, AbortHandle) {
         let (handle, reg) = AbortHandle::new_pair();
         let task = Self {
@@ -25,7 +61,9 @@ impl DispatcherTask {
             chan_from_app,
             chan_from_manager,
             chan_to_app,
+            chan_to_app_tagged,
             chan_to_manager,
+            settings,
         };
 
         (task, handle)
@@ -33,13 +71,33 @@ impl DispatcherTask {
 
     /// Run this task.
     pub(crate) async fn run(self) -> OgaError {
+        let Self {
+            abort,
+            mut chan_from_app,
+            chan_from_manager,
+            chan_to_app,
+            chan_to_app_tagged,
+            chan_to_manager,
+            settings,
+        } = self;
+        let retry_queue = settings.retry_queue.clone();
         let exit = Self::process(
-            self.chan_from_app,
-            self.chan_from_manager,
-            self.chan_to_app,
-            self.chan_to_manager,
+            &mut chan_from_app,
+            chan_from_manager,
+            chan_to_app,
+            chan_to_app_tagged,
+            chan_to_manager,
+            settings,
         );
-        let res = Abortable::new(exit, self.abort).await;
+        let res = Abortable::new(exit, abort).await;
+
+        // Keep pending commands for retransmission, instead of failing them.
+        if let Some(retry) = &retry_queue {
+            for input in chan_from_app.drain() {
+                retry.push(input);
+            }
+        }
+
         match res {
             Ok(Err(exit)) => exit,
             Ok(Ok(_)) => unreachable!(),
@@ -49,24 +107,275 @@ impl DispatcherTask {
 
     /// Run the core processing logic for this task.
     pub(crate) async fn process(
-        mut from_app: mpsc::Receiver<FramePlusChan>,
-        mut from_manager: mpsc::Receiver<Event>,
+        from_app: &mut CommandSources,
+        mut from_manager: mpsc::Receiver<IncomingEvent>,
         to_app: broadcast::Sender<Event>,
-        mut to_manager: mpsc::Sender<FramePlusChan>,
+        to_app_tagged: broadcast::Sender<TaggedEvent>,
+        to_manager: mpsc::Sender<FramePlusChan>,
+        settings: DispatcherSettings,
     ) -> Result<(), OgaError> {
-        loop {
-            tokio::select! {
-                msg = from_manager.recv() => {
-                    let event = msg.ok_or_else(|| OgaError::from("from_manager sender dropped"))?;
-                    let _ = to_app.send(event);
-                },
-                msg = from_app.recv() => {
-                    let cmd = msg.ok_or_else(|| OgaError::from("from_app sender dropped"))?;
-                    to_manager.send(cmd)
-                        .await
-                        .map_err(|e| OgaError::from(e.to_string()))?;
-                },
+        let mut dedup = DedupFilter::new(settings.dedup_window);
+        let mut dead_letters = settings
+            .dead_letters
+            .clone()
+            .map(|sink| DeadLetterTracker::new(sink, settings.events_buffer));
+
+        // Events and commands flow independently, so that slow event
+        // consumers do not hold back commands.
+        let events = async {
+            loop {
+                let msg = from_manager.recv().await;
+                let incoming = msg.ok_or_else(|| OgaError::from("from_manager sender dropped"))?;
+                settings.gauges.incoming.observe(from_manager.len() + 1);
+                if !Self::is_wanted(&settings, &to_app, &to_app_tagged, &incoming) {
+                    log::trace!("no consumers for '{}' event, dropped", incoming.lazy.name());
+                    continue;
+                }
+                let tagged = match Self::parse_event(&settings, incoming) {
+                    Some(tagged) => tagged,
+                    None => continue,
+                };
+                if tagged.channel == PRIMARY_CHANNEL && settings.pings.resolve(&tagged.event) {
+                    log::trace!("received answer to echo probe");
+                    continue;
+                }
+                if dedup.is_duplicate(&tagged) {
+                    log::trace!("suppressed duplicate '{}' event", tagged.event.name());
+                    continue;
+                }
+                let tagged = match Self::apply_middleware(&settings.middleware, tagged).await {
+                    Some(tagged) => tagged,
+                    None => continue,
+                };
+                Self::deliver_queued(&settings.queued_subscribers, &tagged).await;
+                if to_app_tagged.receiver_count() > 0 {
+                    if let Some(tracker) = dead_letters.as_mut() {
+                        tracker.track_tagged(to_app_tagged.len(), &tagged);
+                    }
+                    let _ = to_app_tagged.send(tagged.clone());
+                    settings.gauges.tagged_events.observe(to_app_tagged.len());
+                }
+                if tagged.channel != PRIMARY_CHANNEL
+                    || settings.early_events.hold(&to_app, &tagged.event)
+                {
+                    continue;
+                }
+                if to_app.receiver_count() > 0 {
+                    if let Some(tracker) = dead_letters.as_mut() {
+                        tracker.track_primary(to_app.len(), &tagged);
+                    }
+                    let _ = to_app.send(tagged.event);
+                    settings.gauges.events.observe(to_app.len());
+                }
             }
+        };
+        let commands = async {
+            loop {
+                let msg = from_app.recv().await;
+                let cmd = msg.ok_or_else(|| OgaError::from("from_app sender dropped"))?;
+                settings.gauges.commands.observe(from_app.max_depth() + 1);
+                if let Err(e) = to_manager.send(cmd).await {
+                    if let Some(retry) = &settings.retry_queue {
+                        retry.push(e.0);
+                    }
+                    return Err(OgaError::from("to_manager receiver dropped"));
+                }
+            }
+        };
+
+        tokio::select! {
+            res = events => res,
+            res = commands => res,
         }
     }
+
+    /// Check whether anything consumes an event, thus whether it is worth parsing.
+    fn is_wanted(
+        settings: &DispatcherSettings,
+        to_app: &broadcast::Sender<Event>,
+        to_app_tagged: &broadcast::Sender<TaggedEvent>,
+        incoming: &IncomingEvent,
+    ) -> bool {
+        let primary = incoming.channel == PRIMARY_CHANNEL;
+        let has_queued = settings
+            .queued_subscribers
+            .lock()
+            .is_ok_and(|subs| !subs.is_empty());
+        !settings.middleware.is_empty()
+            || has_queued
+            || to_app_tagged.receiver_count() > 0
+            || (primary && to_app.receiver_count() > 0)
+            || (primary && settings.early_events.is_holding())
+            || (primary && incoming.lazy.name() == "echo" && settings.pings.has_pending())
+    }
+
+    /// Fully parse an event, reporting it as unrecognized on failure.
+    fn parse_event(settings: &DispatcherSettings, incoming: IncomingEvent) -> Option<TaggedEvent> {
+        let channel = incoming.channel.clone();
+        let offset = incoming.offset;
+        let lazy = incoming.lazy.clone();
+        let err = match incoming.into_tagged() {
+            Ok(tagged) => return Some(tagged),
+            Err(e) => e,
+        };
+
+        let msg = format!("unrecognized '{}' event: {}", lazy.name(), err.0);
+        let diagnostic = DecodeDiagnostic::new(
+            DiagnosticKind::Unrecognized,
+            offset,
+            msg,
+            lazy.raw().as_bytes(),
+        );
+        match &settings.diagnostics {
+            Some(chan) => {
+                log::debug!("skipped frame at offset {}: {}", offset, diagnostic.error);
+                let _ = chan.send(TaggedDiagnostic {
+                    channel,
+                    diagnostic,
+                });
+            }
+            None => log::warn!(
+                "transient error, skipped frame: {} ('{}')",
+                diagnostic.error,
+                diagnostic.sample
+            ),
+        }
+        None
+    }
+
+    /// Deliver an event to all queued subscribers, waiting for queue space.
+    async fn deliver_queued(
+        subscribers: &Mutex<Vec<mpsc::Sender<TaggedEvent>>>,
+        tagged: &TaggedEvent,
+    ) {
+        let current = match subscribers.lock() {
+            Ok(subs) => subs.clone(),
+            Err(_) => return,
+        };
+        if current.is_empty() {
+            return;
+        }
+
+        let mut closed = false;
+        for sub in current {
+            closed |= sub.send(tagged.clone()).await.is_err();
+        }
+        if closed {
+            if let Ok(mut subs) = subscribers.lock() {
+                subs.retain(|sub| !sub.is_closed());
+            }
+        }
+    }
+
+    /// Pass an event through the middleware chain.
+    async fn apply_middleware(
+        middleware: &[EventMiddleware],
+        tagged: TaggedEvent,
+    ) -> Option<TaggedEvent> {
+        let mut tagged = tagged;
+        for mw in middleware {
+            let name = tagged.event.name();
+            tagged = match mw.call(tagged).await {
+                Some(tagged) => tagged,
+                None => {
+                    log::debug!("'{}' event suppressed by middleware", name);
+                    return None;
+                }
+            };
+        }
+        Some(tagged)
+    }
+}
+
+/// Filter for duplicate events (same channel, kind and payload) within a time window.
+#[derive(Debug)]
+struct DedupFilter {
+    window: Duration,
+    recent: Vec<(TaggedEvent, Instant)>,
+}
+
+impl DedupFilter {
+    /// Return a filter for the given window; a zero window disables filtering.
+    fn new(window: Duration) -> Self {
+        Self {
+            window,
+            recent: Vec::new(),
+        }
+    }
+
+    /// Check whether an event duplicates a recent one, otherwise record it.
+    fn is_duplicate(&mut self, tagged: &TaggedEvent) -> bool {
+        if self.window == Duration::from_secs(0) {
+            return false;
+        }
+
+        let now = Instant::now();
+        let window = self.window;
+        self.recent
+            .retain(|(_, seen)| now.saturating_duration_since(*seen) < window);
+        let duplicate = self
+            .recent
+            .iter()
+            .any(|(ev, _)| ev.channel == tagged.channel && ev.event == tagged.event);
+        if !duplicate {
+            self.recent.push((tagged.clone(), now));
+        }
+        duplicate
+    }
+}
+
+/// Tracker for events overwritten in broadcast channels, because of lagging subscribers.
+///
+/// This shadows the content of each broadcast channel, in order to know which
+/// event gets evicted when sending into a full channel.
+#[derive(Debug)]
+struct DeadLetterTracker {
+    sink: DeadLetterSink,
+    capacity: usize,
+    primary: VecDeque<TaggedEvent>,
+    tagged: VecDeque<TaggedEvent>,
+}
+
+impl DeadLetterTracker {
+    /// Return a tracker for broadcast channels with the given capacity.
+    fn new(sink: DeadLetterSink, capacity: usize) -> Self {
+        // Broadcast channels round their capacity up to a power of two.
+        let capacity = capacity.next_power_of_two();
+        Self {
+            sink,
+            capacity,
+            primary: VecDeque::with_capacity(capacity),
+            tagged: VecDeque::with_capacity(capacity),
+        }
+    }
+
+    /// Track an event about to be sent to the primary events channel, with `queued` pending values.
+    fn track_primary(&mut self, queued: usize, tagged: &TaggedEvent) {
+        if let Some(evicted) = Self::shadow(&mut self.primary, self.capacity, queued, tagged) {
+            self.sink.record(evicted);
+        }
+    }
+
+    /// Track an event about to be sent to the tagged events channel, with `queued` pending values.
+    fn track_tagged(&mut self, queued: usize, tagged: &TaggedEvent) {
+        if let Some(evicted) = Self::shadow(&mut self.tagged, self.capacity, queued, tagged) {
+            self.sink.record(evicted);
+        }
+    }
+
+    /// Shadow a send, returning the evicted event if the channel is full.
+    fn shadow(
+        ring: &mut VecDeque<TaggedEvent>,
+        capacity: usize,
+        queued: usize,
+        tagged: &TaggedEvent,
+    ) -> Option<TaggedEvent> {
+        let evicted = if ring.len() >= capacity {
+            ring.pop_front()
+        } else {
+            None
+        };
+        ring.push_back(tagged.clone());
+        evicted.filter(|_| queued >= capacity)
+    }
 }
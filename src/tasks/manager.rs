@@ -1,29 +1,122 @@
-use crate::events::Event;
-use crate::virtio::VirtioPort;
-use crate::{FramePlusChan, OgaError};
+use crate::commands::{EchoProbe, API_VERSION};
+use crate::events::{Event, LazyEvent, TaggedEvent};
+use crate::health::{HealthEvent, IdlePolicy};
+use crate::hooks::{AuditHook, CommandRecord, DeadLetterSink};
+use crate::ping::PingTracker;
+use crate::protocol::TaggedDiagnostic;
+use crate::raw::LazyCodec;
+use crate::retry::RetryQueue;
+use crate::stats::Gauges;
+use crate::virtio::{FrameWriter, VirtioPort};
+use crate::{DeliveryReceipt, EncodedCommand, FramePlusChan, OgaError, PRIMARY_CHANNEL};
+use bytes::Bytes;
 use futures::future::{AbortHandle, AbortRegistration, Abortable};
-use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader, PollEvented, WriteHalf};
-use tokio::sync::mpsc;
+use futures::StreamExt;
+use std::collections::{BTreeSet, VecDeque};
+use std::sync::atomic::{AtomicU8, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+use tokio::io::WriteHalf;
+use tokio::sync::{broadcast, mpsc, watch};
+use tokio::time::{self, Duration, Instant};
+use tokio_util::codec::FramedRead;
+
+/// Event read from a channel, not yet fully parsed.
+///
+/// Events are only parsed by the dispatcher, once some consumer needs them.
+#[derive(Clone, Debug)]
+pub(crate) struct IncomingEvent {
+    /// Label of the channel where this event was received.
+    pub(crate) channel: String,
+    pub(crate) lazy: LazyEvent,
+    /// Whether to retain the original frame content.
+    pub(crate) retain_raw: bool,
+    /// Offset of the frame from stream start, in bytes.
+    pub(crate) offset: u64,
+}
+
+impl IncomingEvent {
+    /// Parse this into a tagged event.
+    ///
+    /// Login frames carry credentials, thus they are never retained.
+    pub(crate) fn into_tagged(self) -> Result<TaggedEvent, OgaError> {
+        let raw = match self.lazy.name() {
+            "login" => None,
+            _ if self.retain_raw => Some(Bytes::copy_from_slice(self.lazy.raw().as_bytes())),
+            _ => None,
+        };
+        let tagged = TaggedEvent {
+            channel: self.channel,
+            event: self.lazy.into_event()?,
+            raw,
+        };
+        Ok(tagged)
+    }
+}
+
+/// Per-channel settings for a manager.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct ManagerSettings {
+    /// Label of the managed channel.
+    pub(crate) channel: String,
+    pub(crate) codec: LazyCodec,
+    pub(crate) ignored_events: BTreeSet<String>,
+    pub(crate) retain_raw: bool,
+    /// Initial capacity of the read buffer.
+    pub(crate) read_buffer: usize,
+    pub(crate) audit_hook: Option<AuditHook>,
+    /// Maximum number of events pending for dispatching.
+    pub(crate) backlog: usize,
+    /// Sink for events dropped from a full backlog.
+    pub(crate) dead_letters: Option<DeadLetterSink>,
+    /// Subscribers with their own queue; reading stops on a full backlog while any exists.
+    pub(crate) queued_subscribers: Arc<Mutex<Vec<mpsc::Sender<TaggedEvent>>>>,
+    /// Maximum time for writing a frame, or zero to disable.
+    pub(crate) write_timeout: Duration,
+    /// Period without readable data before the channel is idle, or zero to disable.
+    pub(crate) read_idle_timeout: Duration,
+    pub(crate) read_idle_policy: IdlePolicy,
+    /// Pending echo probes, on the primary channel only.
+    pub(crate) pings: Option<PingTracker>,
+    /// API version negotiated on the primary channel, shared by all channels.
+    pub(crate) api_version: Arc<AtomicU8>,
+    /// Channel for health notifications.
+    pub(crate) health: Option<broadcast::Sender<HealthEvent>>,
+    /// Channel for decode diagnostics.
+    pub(crate) diagnostics: Option<broadcast::Sender<TaggedDiagnostic>>,
+    /// Queue for commands left unwritten on termination.
+    pub(crate) retry_queue: Option<RetryQueue>,
+    /// Gauges for the primary channel queue.
+    pub(crate) gauges: Option<Arc<Gauges>>,
+    /// Time of the last frame received on the primary channel.
+    pub(crate) last_frame: Option<Arc<watch::Sender<Instant>>>,
+}
 
 #[derive(Debug)]
 pub(crate) struct ManagerTask {
     abort: AbortRegistration,
-    dev: PollEvented<VirtioPort>,
+    dev: VirtioPort,
+    settings: ManagerSettings,
     chan_incoming: mpsc::Receiver<FramePlusChan>,
-    chan_outgoing: mpsc::Sender<Event>,
+    chan_priority: Option<mpsc::Receiver<FramePlusChan>>,
+    chan_outgoing: mpsc::Sender<IncomingEvent>,
 }
 
 impl ManagerTask {
     pub(crate) fn new(
-        dev: PollEvented<VirtioPort>,
+        dev: VirtioPort,
+        settings: ManagerSettings,
         chan_incoming: mpsc::Receiver<FramePlusChan>,
-        chan_outgoing: mpsc::Sender<Event>,
+        chan_priority: Option<mpsc::Receiver<FramePlusChan>>,
+        chan_outgoing: mpsc::Sender<IncomingEvent>,
     ) -> (Self, AbortHandle) {
         let (handle, reg) = futures::future::AbortHandle::new_pair();
         let task = Self {
             abort: reg,
             dev,
+            settings,
             chan_incoming,
+            chan_priority,
             chan_outgoing,
         };
 
@@ -32,10 +125,32 @@ impl ManagerTask {
 
     /// Run this task.
     pub(crate) async fn run(self) -> OgaError {
-        let exit = Self::process(self.dev, self.chan_incoming, self.chan_outgoing);
-        let res = Abortable::new(exit, self.abort).await;
+        let Self {
+            abort,
+            dev,
+            settings,
+            mut chan_incoming,
+            chan_priority,
+            chan_outgoing,
+        } = self;
+        let exit = Self::process(
+            dev,
+            &settings,
+            &mut chan_incoming,
+            chan_priority,
+            chan_outgoing,
+        );
+        let res = Abortable::new(exit, abort).await;
         log::trace!("manager done: {:?}", res);
 
+        // Keep pending commands for retransmission, instead of failing them.
+        if let Some(retry) = &settings.retry_queue {
+            chan_incoming.close();
+            while let Ok(input) = chan_incoming.try_recv() {
+                retry.push(input);
+            }
+        }
+
         match res {
             Ok(Ok(_)) => unreachable!("manager cannot ever complete with success"),
             Ok(Err(exit)) => exit,
@@ -45,78 +160,559 @@ impl ManagerTask {
 
     /// Run the core processing logic for this task.
     pub(crate) async fn process(
-        dev: PollEvented<VirtioPort>,
-        mut incoming_cmd: mpsc::Receiver<FramePlusChan>,
-        mut outgoing_event: mpsc::Sender<Event>,
+        dev: VirtioPort,
+        settings: &ManagerSettings,
+        incoming_cmd: &mut mpsc::Receiver<FramePlusChan>,
+        mut priority_cmd: Option<mpsc::Receiver<FramePlusChan>>,
+        outgoing_event: mpsc::Sender<IncomingEvent>,
     ) -> Result<(), OgaError> {
-        // Split the virtio port; the read half gets buffered and polled
-        // for incoming events.
+        // Split the virtio port; the read half gets framed and polled
+        // for incoming events, the write half only writes whole frames.
         let (mut dev_rd, mut dev_wr) = {
-            let (rd, wr) = tokio::io::split(dev);
-            let line_rd = BufReader::new(rd).lines();
-            (line_rd, wr)
+            let (rd, wr) = dev.split();
+            let frame_rd =
+                FramedRead::with_capacity(rd, Self::codec(settings), settings.read_buffer);
+            (frame_rd, FrameWriter::new(wr))
         };
 
+        // Events waiting for the dispatcher. Without queued subscribers, the
+        // device keeps being read while consumers are slow, dropping the oldest
+        // events on overflow. Otherwise, reading pauses until the backlog drains.
+        let mut backlog: VecDeque<IncomingEvent> = VecDeque::new();
+
+        let idle_check = settings.read_idle_timeout > Duration::from_secs(0);
+        let mut idle_deadline = Instant::now() + settings.read_idle_timeout;
+        // Identifier of the last idle probe, still possibly unanswered.
+        let mut idle_probe = None;
+        // Partial frames can only be discarded before the first event.
+        let mut resync_pending = true;
+
         // Endless core loop; manager never completes with success.
         loop {
             tokio::select! {
-                msg = dev_rd.next_line() => {
+                biased;
+
+                // Priority commands skip ahead of everything else.
+                Some(input) = Self::recv_priority(&mut priority_cmd) => {
+                    log::trace!("manager got priority command from consumer");
+                    Self::forward_command(&mut dev_wr, settings, input).await?;
+                },
+
+                permit = outgoing_event.reserve(), if !backlog.is_empty() => {
+                    let permit = permit.map_err(|e| OgaError::from(e.to_string()))?;
+                    if let Some(incoming) = backlog.pop_front() {
+                        log::trace!("forwarded '{}' event from '{}'", incoming.lazy.name(), settings.channel);
+                        permit.send(incoming);
+                    }
+                },
+
+                msg = dev_rd.next(), if !Self::is_backpressured(settings, &backlog) => {
                     log::trace!("manager got event from virtio port");
-                    let line = msg
-                        .map_err(|e| OgaError::from(e.to_string()))?
-                        .ok_or_else(|| OgaError::from("manager: end of unix socket stream"))?;
+                    let lazy = msg
+                        .ok_or_else(|| OgaError::from("manager: end of unix socket stream"))??;
+                    idle_deadline = Instant::now() + settings.read_idle_timeout;
+                    if let Some(last_frame) = &settings.last_frame {
+                        last_frame.send_replace(Instant::now());
+                    }
+
+                    if resync_pending {
+                        resync_pending = false;
+                        Self::on_resync(settings, dev_rd.decoder().discarded());
+                    }
+
+                    Self::negotiate_version(settings, &lazy);
+                    if let Some(incoming) = Self::tag_event(settings, dev_rd.decoder(), lazy) {
+                        Self::enqueue_event(&mut backlog, settings, incoming);
+                    }
+                },
 
-                    Self::forward_event(&mut outgoing_event, line).await?;
+                _ = time::sleep_until(idle_deadline), if idle_check => {
+                    idle_deadline = Instant::now() + settings.read_idle_timeout;
+                    Self::on_read_idle(&mut dev_wr, settings, &mut idle_probe).await?;
                 },
 
                 msg = incoming_cmd.recv() => {
                     log::trace!("manager got command from consumer");
                     let input = msg
                         .ok_or_else(|| OgaError::from("manager: end of incoming stream"))?;
+                    if let Some(gauges) = &settings.gauges {
+                        gauges.outgoing.observe(incoming_cmd.len() + 1);
+                    }
 
-                    Self::forward_command(&mut dev_wr, input).await?;
+                    Self::forward_command(&mut dev_wr, settings, input).await?;
                 }
             }
         }
     }
 
+    /// Receive the next priority command, if a priority channel is available.
+    async fn recv_priority(
+        chan: &mut Option<mpsc::Receiver<FramePlusChan>>,
+    ) -> Option<FramePlusChan> {
+        match chan {
+            Some(chan) => chan.recv().await,
+            None => None,
+        }
+    }
+
     /// Forward a command (consumer -> host).
+    ///
+    /// Commands not supported by the host API version are rejected back to
+    /// their sender, without being written (see `check_api_version()`).
     async fn forward_command(
-        dev_wr: &mut WriteHalf<PollEvented<VirtioPort>>,
+        dev_wr: &mut FrameWriter<WriteHalf<VirtioPort>>,
+        settings: &ManagerSettings,
         input: FramePlusChan,
     ) -> Result<(), OgaError> {
         let (cmd, chan) = input;
-        let data = cmd.as_frame()?;
-        dev_wr
-            .write_all(&data)
-            .await
-            .map_err(|e| OgaError::from(e.to_string()))?;
-        dev_wr.flush().await.unwrap();
-        let _ = chan.send(Ok(()));
 
-        log::trace!("forwarded command: {:?}", cmd);
+        // Barriers are acknowledged in order, without being written.
+        if cmd.is_marker() {
+            let now = Instant::now();
+            let _ = chan.send(Ok(DeliveryReceipt::new(cmd.queued_at, now)));
+            return Ok(());
+        }
+
+        let api_version = settings.api_version.load(Ordering::Relaxed);
+        if let Err(e) = check_api_version(&cmd.name, cmd.min_api_version, api_version) {
+            log::debug!("rejected command on '{}': {}", settings.channel, e.0);
+            Self::audit(settings, &cmd, Err(e.0.clone()));
+            let _ = chan.send(Err(e));
+            return Ok(());
+        }
+
+        let started = Instant::now();
+        let res = Self::write_frame(dev_wr, settings, &cmd.frame).await;
+        Self::audit(
+            settings,
+            &cmd,
+            res.as_ref().map(|_| ()).map_err(|e| e.to_string()),
+        );
+        if let Err(e) = res {
+            match &settings.retry_queue {
+                // A torn frame cannot be retransmitted as a whole.
+                Some(retry) if !dev_wr.wrote_any() => retry.push((cmd, chan)),
+                _ => {
+                    let msg = format!("failed to write '{}' command: {}", cmd.name, e);
+                    let _ = chan.send(Err(OgaError::from(msg)));
+                }
+            }
+            return Err(OgaError::from(e.to_string()));
+        }
+        let _ = chan.send(Ok(DeliveryReceipt::new(cmd.queued_at, started)));
+
+        log::trace!("forwarded '{}' command: {:?}", cmd.name, cmd.frame);
         Ok(())
     }
 
-    /// Forward an event (host -> consumers).
-    async fn forward_event(
-        outgoing_ch: &mut mpsc::Sender<Event>,
-        line: String,
-    ) -> Result<(), OgaError> {
-        let event = match Event::parse_frame(line.as_bytes()) {
-            Ok(val) => val,
+    /// Record the outcome of a command, if auditing is enabled.
+    fn audit(settings: &ManagerSettings, cmd: &EncodedCommand, result: Result<(), String>) {
+        if let Some(hook) = &settings.audit_hook {
+            hook.call(&CommandRecord {
+                channel: settings.channel.clone(),
+                name: cmd.name.clone(),
+                size: cmd.frame.len(),
+                timestamp: SystemTime::now(),
+                result,
+            });
+        }
+    }
+
+    /// Track the API version announced by the host, through `api-version` and `refresh` events.
+    ///
+    /// Only the primary channel carries announcements; the negotiated version
+    /// applies to all channels.
+    fn negotiate_version(settings: &ManagerSettings, lazy: &LazyEvent) {
+        if settings.channel != PRIMARY_CHANNEL {
+            return;
+        }
+        let announced = match lazy.name() {
+            "api-version" | "refresh" => match lazy.event() {
+                Ok(Event::ApiVersion(ev)) => ev.api_version,
+                Ok(Event::Refresh(ev)) => ev.api_version,
+                _ => return,
+            },
+            _ => return,
+        };
+        let negotiated = announced.min(API_VERSION);
+        let previous = settings.api_version.swap(negotiated, Ordering::Relaxed);
+        if negotiated != previous {
+            log::debug!(
+                "negotiated API version {} (host: {})",
+                negotiated,
+                announced
+            );
+        }
+    }
+
+    /// Write a whole frame, within the configured write timeout.
+    async fn write_frame(
+        dev_wr: &mut FrameWriter<WriteHalf<VirtioPort>>,
+        settings: &ManagerSettings,
+        frame: &Bytes,
+    ) -> std::io::Result<()> {
+        if settings.write_timeout == Duration::from_secs(0) {
+            return dev_wr.write_frame(frame).await;
+        }
+
+        match time::timeout(settings.write_timeout, dev_wr.write_frame(frame)).await {
+            Ok(res) => res,
             Err(_) => {
-                log::warn!("transient error, received unrecognized event: '{}'", &line);
+                if let Some(health) = &settings.health {
+                    let _ = health.send(HealthEvent::WriteStalled(settings.write_timeout));
+                }
+                let msg = format!(
+                    "write to '{}' timed out after {:?}, host not draining the channel",
+                    settings.channel, settings.write_timeout
+                );
+                Err(std::io::Error::new(std::io::ErrorKind::TimedOut, msg))
+            }
+        }
+    }
+
+    /// React to a channel without readable data.
+    ///
+    /// Probes are `echo` commands, only sent on the primary channel; their
+    /// answers are consumed by the dispatcher, and never reach consumers.
+    async fn on_read_idle(
+        dev_wr: &mut FrameWriter<WriteHalf<VirtioPort>>,
+        settings: &ManagerSettings,
+        idle_probe: &mut Option<String>,
+    ) -> Result<(), OgaError> {
+        log::info!(
+            "no data from '{}' for {:?}",
+            settings.channel,
+            settings.read_idle_timeout
+        );
+        if let Some(health) = &settings.health {
+            let idle = HealthEvent::ReadIdle(settings.channel.clone(), settings.read_idle_timeout);
+            let _ = health.send(idle);
+        }
+
+        if settings.read_idle_policy != IdlePolicy::Probe {
+            return Ok(());
+        }
+        let pings = match &settings.pings {
+            Some(pings) => pings,
+            None => {
+                log::trace!(
+                    "not probing '{}', not the primary channel",
+                    settings.channel
+                );
                 return Ok(());
             }
         };
-
-        outgoing_ch
-            .send(event.clone())
+        if let Some(id) = idle_probe.take() {
+            pings.cancel(&id);
+        }
+        // Nobody waits for the answer, it only has to be consumed.
+        let (id, _answer) = pings.register();
+        *idle_probe = Some(id.clone());
+        let probe = EncodedCommand::encode(&EchoProbe { id })?;
+        Self::write_frame(dev_wr, settings, &probe.frame)
             .await
-            .map_err(|e| OgaError::from(e.to_string()))?;
-
-        log::trace!("forwarded event: {}", event);
+            .map_err(|e| OgaError::from(format!("failed to probe idle channel: {}", e)))?;
+        log::trace!("probed idle channel '{}'", settings.channel);
         Ok(())
     }
+
+    /// Report a partial frame discarded at stream start, if any.
+    fn on_resync(settings: &ManagerSettings, discarded: usize) {
+        if discarded == 0 {
+            return;
+        }
+        log::info!(
+            "resynchronized '{}' after discarding {} bytes of partial frame",
+            settings.channel,
+            discarded
+        );
+        if let Some(health) = &settings.health {
+            let _ = health.send(HealthEvent::Resynced(settings.channel.clone(), discarded));
+        }
+    }
+
+    /// Return a codec for the channel, delivering its decode diagnostics.
+    fn codec(settings: &ManagerSettings) -> LazyCodec {
+        let diagnostics = match &settings.diagnostics {
+            Some(chan) => chan.clone(),
+            None => return settings.codec.clone(),
+        };
+        let channel = settings.channel.clone();
+        settings.codec.clone().diagnostics_hook(move |diagnostic| {
+            let tagged = TaggedDiagnostic {
+                channel: channel.clone(),
+                diagnostic,
+            };
+            let _ = diagnostics.send(tagged);
+        })
+    }
+
+    /// Tag an event (host -> consumers), unless it gets dropped.
+    ///
+    /// Ignored events are dropped here; parsing the rest is left to the dispatcher.
+    fn tag_event(
+        settings: &ManagerSettings,
+        codec: &LazyCodec,
+        lazy: LazyEvent,
+    ) -> Option<IncomingEvent> {
+        if settings.ignored_events.contains(lazy.name()) {
+            log::trace!("dropped ignored event: {}", lazy.name());
+            return None;
+        }
+
+        let incoming = IncomingEvent {
+            channel: settings.channel.clone(),
+            lazy,
+            retain_raw: settings.retain_raw,
+            offset: codec.frame_offset(),
+        };
+        Some(incoming)
+    }
+
+    /// Whether reading must pause, because of a full backlog and lossless subscribers.
+    fn is_backpressured(settings: &ManagerSettings, backlog: &VecDeque<IncomingEvent>) -> bool {
+        if backlog.len() < settings.backlog {
+            return false;
+        }
+        settings
+            .queued_subscribers
+            .lock()
+            .is_ok_and(|subs| !subs.is_empty())
+    }
+
+    /// Queue an event for the dispatcher, dropping the oldest one if the backlog is full.
+    ///
+    /// Events which must not be lost (`shutdown`, `login` and `refresh`) are
+    /// never dropped; repeated `refresh` requests are coalesced instead.
+    fn enqueue_event(
+        backlog: &mut VecDeque<IncomingEvent>,
+        settings: &ManagerSettings,
+        incoming: IncomingEvent,
+    ) {
+        if backlog.len() >= settings.backlog
+            && incoming.lazy.name() == "refresh"
+            && backlog.iter().any(|queued| queued.lazy.name() == "refresh")
+        {
+            log::debug!("coalesced 'refresh' event from '{}'", settings.channel);
+            return;
+        }
+
+        backlog.push_back(incoming);
+        if backlog.len() <= settings.backlog {
+            return;
+        }
+        let evicted = backlog
+            .iter()
+            .position(|queued| !is_protected(queued.lazy.name()))
+            .and_then(|pos| backlog.remove(pos));
+        if let Some(evicted) = evicted {
+            log::warn!(
+                "dropped '{}' event from '{}', consumers too slow",
+                evicted.lazy.name(),
+                settings.channel
+            );
+            if let Some(sink) = &settings.dead_letters {
+                // Events which cannot be parsed would have been dropped anyway.
+                if let Ok(tagged) = evicted.into_tagged() {
+                    sink.record(tagged);
+                }
+            }
+        }
+    }
+}
+
+/// Whether an event must never be dropped from a full backlog.
+fn is_protected(name: &str) -> bool {
+    matches!(name, "shutdown" | "login" | "refresh")
+}
+
+/// Check that a command is supported by the host, given the negotiated API version.
+///
+/// Hosts do not answer unknown messages, thus a command they do not support
+/// would be silently lost. Rejecting it instead lets the sender know. This
+/// applies to every write path; before the host announces its version (e.g.
+/// on connect), only commands without a minimum version are supported.
+pub(crate) fn check_api_version(
+    name: &str,
+    min_api_version: u8,
+    api_version: u8,
+) -> Result<(), OgaError> {
+    if min_api_version <= api_version {
+        return Ok(());
+    }
+    let msg = format!(
+        "'{}' command requires API version {}, host supports {}",
+        name, min_api_version, api_version
+    );
+    Err(msg.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands;
+
+    #[test]
+    fn reject_unsupported_commands() {
+        let check = |cmd: &dyn commands::AsFrame, version| {
+            let cmd = EncodedCommand::encode(cmd).unwrap();
+            check_api_version(&cmd.name, cmd.min_api_version, version)
+        };
+        let cpus = commands::NumberOfCpus { count: 2 };
+        let timezone = commands::Timezone {
+            zone: "UTC".to_string(),
+            offset: 0,
+        };
+
+        for version in 0..=API_VERSION {
+            assert!(check(&commands::Heartbeat::default(), version).is_ok());
+        }
+        let err = check(&cpus, 0).unwrap_err();
+        assert!(err.0.contains("requires API version 1, host supports 0"));
+        assert!(check(&cpus, 1).is_ok());
+        assert!(check(&timezone, 1).is_err());
+        assert!(check(&timezone, 2).is_ok());
+    }
+
+    #[test]
+    fn negotiate_on_primary_channel() {
+        let settings = ManagerSettings {
+            channel: PRIMARY_CHANNEL.to_string(),
+            ..Default::default()
+        };
+        let negotiate = |settings: &ManagerSettings, frame: &str| {
+            let lazy = LazyEvent::parse_frame(frame).unwrap();
+            ManagerTask::negotiate_version(settings, &lazy);
+            settings.api_version.load(Ordering::Relaxed)
+        };
+
+        let frame = r#"{"__name__":"api-version","apiVersion":2}"#;
+        assert_eq!(negotiate(&settings, frame), 2);
+        let frame = r#"{"__name__":"refresh","apiVersion":9}"#;
+        assert_eq!(negotiate(&settings, frame), API_VERSION);
+        let frame = r#"{"__name__":"lock-screen"}"#;
+        assert_eq!(negotiate(&settings, frame), API_VERSION);
+
+        // Other channels share the version, without negotiating it.
+        let extra = ManagerSettings {
+            channel: "extra".to_string(),
+            ..settings.clone()
+        };
+        let frame = r#"{"__name__":"api-version","apiVersion":1}"#;
+        assert_eq!(negotiate(&extra, frame), API_VERSION);
+    }
+
+    #[test]
+    fn retain_raw_except_login() {
+        let incoming = |frame: &str| IncomingEvent {
+            channel: "test".to_string(),
+            lazy: LazyEvent::parse_frame(frame).unwrap(),
+            retain_raw: true,
+            offset: 0,
+        };
+
+        let frame = r#"{"__name__":"lock-screen"}"#;
+        let tagged = incoming(frame).into_tagged().unwrap();
+        assert_eq!(tagged.event.name(), "lock-screen");
+        assert_eq!(tagged.raw.as_deref(), Some(frame.as_bytes()));
+
+        let frame = r#"{"__name__":"login","username":"u","password":"hunter2"}"#;
+        assert!(!format!("{:?}", incoming(frame)).contains("hunter2"));
+        let tagged = incoming(frame).into_tagged().unwrap();
+        assert_eq!(tagged.event.name(), "login");
+        assert_eq!(tagged.raw, None);
+
+        let frame = r#"{"__name__":"set-number-of-cpus","count":"many"}"#;
+        assert!(incoming(frame).into_tagged().is_err());
+    }
+
+    #[test]
+    fn keep_protected_events_on_overflow() {
+        let settings = ManagerSettings {
+            backlog: 2,
+            ..Default::default()
+        };
+        let incoming = |frame: &str| IncomingEvent {
+            channel: "test".to_string(),
+            lazy: LazyEvent::parse_frame(frame).unwrap(),
+            retain_raw: false,
+            offset: 0,
+        };
+        let names = |backlog: &VecDeque<IncomingEvent>| -> Vec<String> {
+            backlog.iter().map(|e| e.lazy.name().to_string()).collect()
+        };
+
+        let mut backlog = VecDeque::new();
+        let shutdown = r#"{"__name__":"shutdown","message":"bye"}"#;
+        let lock = r#"{"__name__":"lock-screen"}"#;
+        let refresh = r#"{"__name__":"refresh","apiVersion":3}"#;
+        ManagerTask::enqueue_event(&mut backlog, &settings, incoming(shutdown));
+        for _ in 0..5 {
+            ManagerTask::enqueue_event(&mut backlog, &settings, incoming(lock));
+        }
+        assert_eq!(names(&backlog), ["shutdown", "lock-screen"]);
+
+        ManagerTask::enqueue_event(&mut backlog, &settings, incoming(refresh));
+        assert_eq!(names(&backlog), ["shutdown", "refresh"]);
+        ManagerTask::enqueue_event(&mut backlog, &settings, incoming(refresh));
+        assert_eq!(names(&backlog), ["shutdown", "refresh"]);
+
+        // With only protected events left, the backlog grows past its limit.
+        ManagerTask::enqueue_event(&mut backlog, &settings, incoming(shutdown));
+        assert_eq!(names(&backlog), ["shutdown", "refresh", "shutdown"]);
+        ManagerTask::enqueue_event(&mut backlog, &settings, incoming(lock));
+        assert_eq!(names(&backlog), ["shutdown", "refresh", "shutdown"]);
+    }
+
+    #[test]
+    fn backpressure_with_queued_subscribers() {
+        let settings = ManagerSettings {
+            backlog: 1,
+            ..Default::default()
+        };
+        let mut backlog = VecDeque::new();
+        backlog.push_back(IncomingEvent {
+            channel: "test".to_string(),
+            lazy: LazyEvent::parse_frame(r#"{"__name__":"lock-screen"}"#).unwrap(),
+            retain_raw: false,
+            offset: 0,
+        });
+        assert!(!ManagerTask::is_backpressured(&settings, &backlog));
+
+        let (tx, _rx) = mpsc::channel(1);
+        settings.queued_subscribers.lock().unwrap().push(tx);
+        assert!(ManagerTask::is_backpressured(&settings, &backlog));
+        backlog.clear();
+        assert!(!ManagerTask::is_backpressured(&settings, &backlog));
+    }
+
+    #[test]
+    fn tag_diagnostics_with_channel() {
+        use tokio_util::codec::Decoder;
+
+        let diagnostics = {
+            let bcast = broadcast::channel(8);
+            drop(bcast.1);
+            bcast.0
+        };
+        let mut rx = diagnostics.subscribe();
+        let settings = ManagerSettings {
+            channel: "extra".to_string(),
+            diagnostics: Some(diagnostics),
+            ..Default::default()
+        };
+        let mut codec = ManagerTask::codec(&settings);
+        let mut src = bytes::BytesMut::from(&b"junk\n{\"__name__\":\"lock-screen\"}\n"[..]);
+        let lazy = codec.decode(&mut src).unwrap().unwrap();
+        assert_eq!(lazy.name(), "lock-screen");
+
+        let tagged = rx.try_recv().unwrap();
+        assert_eq!(tagged.channel, "extra");
+        assert_eq!(
+            tagged.diagnostic.kind,
+            crate::protocol::DiagnosticKind::Resync
+        );
+        assert_eq!(tagged.diagnostic.offset, 0);
+        assert!(rx.try_recv().is_err());
+    }
 }
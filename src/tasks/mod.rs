@@ -3,7 +3,11 @@
 mod dispatcher;
 mod manager;
 mod pacemaker;
+mod sources;
+mod watchdog;
 
-pub(crate) use dispatcher::DispatcherTask;
-pub(crate) use manager::ManagerTask;
+pub(crate) use dispatcher::{DispatcherSettings, DispatcherTask};
+pub(crate) use manager::{check_api_version, IncomingEvent, ManagerSettings, ManagerTask};
 pub(crate) use pacemaker::PacemakerTask;
+pub(crate) use sources::{command_sources, CommandSources, SourceRegistry};
+pub(crate) use watchdog::WatchdogTask;
@@ -1,7 +1,7 @@
 use crate::commands;
-use crate::{FramePlusChan, OgaError};
+use crate::{DeliveryReceipt, EncodedCommand, FramePlusChan, OgaError};
 use futures::future::{self, AbortHandle, AbortRegistration, Abortable};
-use tokio::sync::{mpsc, oneshot};
+use tokio::sync::{mpsc, oneshot, watch};
 use tokio::time;
 
 #[derive(Debug)]
@@ -9,6 +9,7 @@ pub(crate) struct PacemakerTask {
     abort: AbortRegistration,
     chan_to_manager: mpsc::Sender<FramePlusChan>,
     pause: u8,
+    pulse: watch::Sender<time::Instant>,
 }
 
 impl PacemakerTask {
@@ -16,12 +17,14 @@ impl PacemakerTask {
     pub(crate) fn new(
         chan_to_manager: mpsc::Sender<FramePlusChan>,
         pause: u8,
+        pulse: watch::Sender<time::Instant>,
     ) -> (Self, AbortHandle) {
         let (handle, reg) = AbortHandle::new_pair();
         let task = Self {
             abort: reg,
             chan_to_manager,
             pause,
+            pulse,
         };
 
         (task, handle)
@@ -29,7 +32,7 @@ impl PacemakerTask {
 
     /// Run this task.
     pub(crate) async fn run(self) -> OgaError {
-        let exit = Self::process(self.chan_to_manager, self.pause);
+        let exit = Self::process(self.chan_to_manager, self.pause, self.pulse);
         let res = Abortable::new(exit, self.abort).await;
         match res {
             Ok(Err(exit)) => exit,
@@ -40,8 +43,9 @@ impl PacemakerTask {
 
     /// Run the core processing logic for this task.
     pub(crate) async fn process(
-        mut to_manager: mpsc::Sender<FramePlusChan>,
+        to_manager: mpsc::Sender<FramePlusChan>,
         pause: u8,
+        pulse: watch::Sender<time::Instant>,
     ) -> Result<(), OgaError> {
         let pause = u64::from(pause);
         if pause == 0 {
@@ -49,16 +53,39 @@ impl PacemakerTask {
             return Ok(());
         }
 
-        let beat = commands::Heartbeat::default();
+        let beat = EncodedCommand::encode(&commands::Heartbeat::default())?;
+        let mut ticker = time::interval(time::Duration::from_secs(pause));
+        ticker.set_missed_tick_behavior(time::MissedTickBehavior::Skip);
+        // Result channel of the last heartbeat, while not yet written.
+        let mut in_flight: Option<oneshot::Receiver<Result<DeliveryReceipt, OgaError>>> = None;
 
         loop {
-            let chan = oneshot::channel();
-            to_manager
-                .send((Box::new(beat.clone()), chan.0))
-                .await
-                .map_err(|e| OgaError::from(e.to_string()))?;
-            let _ = chan.1.await;
-            time::delay_for(time::Duration::from_secs(pause)).await;
+            tokio::select! {
+                _ = ticker.tick() => {
+                    // Coalesce with a heartbeat still queued, instead of piling up stale ones.
+                    if in_flight.is_some() {
+                        log::trace!("previous heartbeat not yet written, skipped");
+                        continue;
+                    }
+                    let chan = oneshot::channel();
+                    let cmd = EncodedCommand {
+                        queued_at: time::Instant::now(),
+                        ..beat.clone()
+                    };
+                    to_manager
+                        .send((cmd, chan.0))
+                        .await
+                        .map_err(|e| OgaError::from(e.to_string()))?;
+                    in_flight = Some(chan.1);
+                },
+                res = async { in_flight.as_mut().unwrap().await }, if in_flight.is_some() => {
+                    in_flight = None;
+                    // Record the time of each successfully delivered heartbeat.
+                    if let Ok(Ok(_)) = res {
+                        pulse.send_replace(time::Instant::now());
+                    }
+                },
+            }
         }
     }
 }
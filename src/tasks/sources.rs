@@ -0,0 +1,210 @@
+//! Per-sender command queues, drained fairly by the dispatcher.
+
+use crate::{DeliveryReceipt, EncodedCommand, FramePlusChan, OgaError};
+use futures::future;
+use std::collections::VecDeque;
+use std::future::poll_fn;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use tokio::sync::{mpsc, oneshot};
+use tokio::time::Instant;
+
+/// Create the command sources for a client, starting from its own queue.
+pub(crate) fn command_sources(
+    base_tx: &mpsc::Sender<FramePlusChan>,
+    base_rx: mpsc::Receiver<FramePlusChan>,
+    capacity: usize,
+) -> (SourceRegistry, CommandSources) {
+    let (control_tx, control_rx) = mpsc::unbounded_channel();
+    let registry = SourceRegistry {
+        control: control_tx,
+        capacity,
+        senders: Arc::new(Mutex::new(vec![base_tx.downgrade()])),
+    };
+    let sources = CommandSources {
+        control: control_rx,
+        sources: vec![base_rx],
+        barriers: VecDeque::new(),
+        next: 0,
+    };
+    (registry, sources)
+}
+
+/// Requests from command senders to the dispatcher.
+#[derive(Debug)]
+enum Control {
+    /// Start draining a new sender queue.
+    Register(mpsc::Receiver<FramePlusChan>),
+    /// Dispatch a barrier ahead of any further command.
+    Flush(FramePlusChan),
+}
+
+/// Handle for adding command sources, shared by senders.
+#[derive(Clone, Debug)]
+pub(crate) struct SourceRegistry {
+    control: mpsc::UnboundedSender<Control>,
+    capacity: usize,
+    /// Registered queues, for flushing and reporting their fill level.
+    senders: Arc<Mutex<Vec<mpsc::WeakSender<FramePlusChan>>>>,
+}
+
+impl SourceRegistry {
+    /// Register a new source, returning the sending end of its queue.
+    ///
+    /// This returns `None` if the dispatcher is gone.
+    pub(crate) fn register(&self) -> Option<mpsc::Sender<FramePlusChan>> {
+        let (tx, rx) = mpsc::channel(self.capacity);
+        self.control.send(Control::Register(rx)).ok()?;
+        if let Ok(mut senders) = self.senders.lock() {
+            senders.retain(|s| s.strong_count() > 0);
+            senders.push(tx.downgrade());
+        }
+        Some(tx)
+    }
+
+    /// Return the number of commands in the fullest queue.
+    pub(crate) fn max_depth(&self) -> usize {
+        self.live_senders()
+            .iter()
+            .map(|s| s.max_capacity() - s.capacity())
+            .max()
+            .unwrap_or(0)
+    }
+
+    /// Dispatch a barrier once all commands being queued by any source are dispatched.
+    ///
+    /// A marker is queued on each source, behind senders still waiting for
+    /// queue space. Once all markers got through, the barrier is dispatched.
+    pub(crate) async fn flush(&self, barrier: FramePlusChan) -> Result<(), OgaError> {
+        let markers = self.live_senders().into_iter().map(|queue| async move {
+            let ack = oneshot::channel();
+            queue
+                .send((EncodedCommand::marker(), ack.0))
+                .await
+                .map_err(|e| OgaError::from(e.to_string()))?;
+            ack.1.await.map_err(|e| OgaError::from(e.to_string()))??;
+            Ok::<_, OgaError>(())
+        });
+        future::try_join_all(markers).await?;
+
+        self.control
+            .send(Control::Flush(barrier))
+            .map_err(|_| OgaError::from("command sources closed"))
+    }
+
+    /// Return all queues still in use.
+    fn live_senders(&self) -> Vec<mpsc::Sender<FramePlusChan>> {
+        match self.senders.lock() {
+            Ok(senders) => senders
+                .iter()
+                .filter_map(mpsc::WeakSender::upgrade)
+                .collect(),
+            Err(_) => Vec::new(),
+        }
+    }
+}
+
+/// Command queues of all senders, drained round-robin.
+///
+/// Each sender has its own bounded queue, and each one in turn gets a
+/// command dispatched. A busy sender thus only delays others by one
+/// command per round, instead of by its whole backlog.
+///
+/// The client's own queue is always the first one; once it is closed,
+/// the sources are exhausted. Other queues are dropped when their sender
+/// goes away.
+#[derive(Debug)]
+pub(crate) struct CommandSources {
+    control: mpsc::UnboundedReceiver<Control>,
+    sources: Vec<mpsc::Receiver<FramePlusChan>>,
+    barriers: VecDeque<FramePlusChan>,
+    /// Position of the source to poll first.
+    next: usize,
+}
+
+impl CommandSources {
+    /// Receive the next command, in round-robin order across sources.
+    pub(crate) async fn recv(&mut self) -> Option<FramePlusChan> {
+        poll_fn(|cx| self.poll_recv(cx)).await
+    }
+
+    /// Return the number of commands in the fullest queue.
+    pub(crate) fn max_depth(&self) -> usize {
+        self.sources.iter().map(|s| s.len()).max().unwrap_or(0)
+    }
+
+    /// Close all queues and return their pending commands and barriers.
+    pub(crate) fn drain(&mut self) -> Vec<FramePlusChan> {
+        self.control.close();
+        while let Ok(msg) = self.control.try_recv() {
+            self.handle_control(msg);
+        }
+
+        let mut pending: Vec<FramePlusChan> = self.barriers.drain(..).collect();
+        for source in &mut self.sources {
+            source.close();
+            while let Ok(input) = source.try_recv() {
+                if !input.0.is_marker() {
+                    pending.push(input);
+                }
+            }
+        }
+        pending
+    }
+
+    fn poll_recv(&mut self, cx: &mut Context<'_>) -> Poll<Option<FramePlusChan>> {
+        while let Poll::Ready(Some(msg)) = self.control.poll_recv(cx) {
+            self.handle_control(msg);
+        }
+        if let Some(barrier) = self.barriers.pop_front() {
+            return Poll::Ready(Some(barrier));
+        }
+
+        loop {
+            match self.poll_sources(cx) {
+                Poll::Ready(Some((cmd, ack))) if cmd.is_marker() => {
+                    let now = Instant::now();
+                    let _ = ack.send(Ok(DeliveryReceipt::new(cmd.queued_at, now)));
+                }
+                res => return res,
+            }
+        }
+    }
+
+    /// Take a command from the next source which has one.
+    fn poll_sources(&mut self, cx: &mut Context<'_>) -> Poll<Option<FramePlusChan>> {
+        let count = self.sources.len();
+        let mut closed = Vec::new();
+        let mut res = Poll::Pending;
+        for offset in 0..count {
+            let idx = (self.next + offset) % count;
+            match self.sources[idx].poll_recv(cx) {
+                Poll::Ready(Some(input)) => {
+                    self.next = idx + 1;
+                    res = Poll::Ready(Some(input));
+                    break;
+                }
+                Poll::Ready(None) if idx == 0 => return Poll::Ready(None),
+                Poll::Ready(None) => closed.push(idx),
+                Poll::Pending => {}
+            }
+        }
+
+        // Drop the queues of departed senders, from the back.
+        closed.sort_unstable();
+        for idx in closed.into_iter().rev() {
+            self.sources.remove(idx);
+            if idx < self.next {
+                self.next -= 1;
+            }
+        }
+        res
+    }
+
+    fn handle_control(&mut self, msg: Control) {
+        match msg {
+            Control::Register(queue) => self.sources.push(queue),
+            Control::Flush(input) => self.barriers.push_back(input),
+        }
+    }
+}
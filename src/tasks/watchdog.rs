@@ -0,0 +1,80 @@
+use crate::health::{HealthEvent, SilencePolicy};
+use crate::OgaError;
+use futures::future::{AbortHandle, AbortRegistration, Abortable};
+use tokio::sync::{broadcast, watch};
+use tokio::time::{self, Duration, Instant};
+
+/// Watchdog for frames received from the host.
+#[derive(Debug)]
+pub(crate) struct WatchdogTask {
+    abort: AbortRegistration,
+    last_frame: watch::Receiver<Instant>,
+    timeout: Duration,
+    policy: SilencePolicy,
+    chan_health: broadcast::Sender<HealthEvent>,
+}
+
+impl WatchdogTask {
+    /// Prepare a new watchdog task, without starting it.
+    pub(crate) fn new(
+        last_frame: watch::Receiver<Instant>,
+        timeout: Duration,
+        policy: SilencePolicy,
+        chan_health: broadcast::Sender<HealthEvent>,
+    ) -> (Self, AbortHandle) {
+        let (handle, reg) = AbortHandle::new_pair();
+        let task = Self {
+            abort: reg,
+            last_frame,
+            timeout,
+            policy,
+            chan_health,
+        };
+
+        (task, handle)
+    }
+
+    /// Run this task.
+    pub(crate) async fn run(self) -> OgaError {
+        let exit = Self::process(self.last_frame, self.timeout, self.policy, self.chan_health);
+        let res = Abortable::new(exit, self.abort).await;
+        match res {
+            Ok(Err(exit)) => exit,
+            Ok(Ok(_)) => unreachable!(),
+            Err(_) => OgaError::from("watchdog task aborted"),
+        }
+    }
+
+    /// Run the core processing logic for this task.
+    pub(crate) async fn process(
+        mut last_frame: watch::Receiver<Instant>,
+        timeout: Duration,
+        policy: SilencePolicy,
+        health: broadcast::Sender<HealthEvent>,
+    ) -> Result<(), OgaError> {
+        loop {
+            let deadline = *last_frame.borrow_and_update() + timeout;
+            let changed = time::timeout_at(deadline, last_frame.changed()).await;
+            match changed {
+                Ok(res) => {
+                    res.map_err(|_| OgaError::from("watchdog: frames sender dropped"))?;
+                    continue;
+                }
+                Err(_) if policy == SilencePolicy::Terminate => {
+                    let msg = format!("no frames from host for {} seconds", timeout.as_secs());
+                    return Err(msg.into());
+                }
+                Err(_) => {}
+            }
+
+            log::warn!("no frames from host for {} seconds", timeout.as_secs());
+            let _ = health.send(HealthEvent::HostSilent(timeout));
+            last_frame
+                .changed()
+                .await
+                .map_err(|_| OgaError::from("watchdog: frames sender dropped"))?;
+            log::info!("host resumed sending frames");
+            let _ = health.send(HealthEvent::HostResumed);
+        }
+    }
+}
@@ -0,0 +1,318 @@
+/*! Active-user tracking.
+
+This detects the user currently active on the guest, through pluggable
+backends, and reports it to the host via `active-user` commands
+whenever it changes. Optionally, the full list of logged-in users can
+be reported too, via non-standard `logged-in-users` commands (see
+`commands::extensions`).
+*/
+
+use crate::commands::extensions::{LoggedInUser, LoggedInUsers};
+use crate::commands::ActiveUser;
+use crate::errors::OgaError;
+use crate::OgaCommandSender;
+use futures::future::BoxFuture;
+use futures::FutureExt;
+use std::collections::BTreeSet;
+#[cfg(unix)]
+use std::convert::TryFrom;
+use tokio::time::{self, Duration};
+
+/// Source of information about the active user.
+pub trait ActiveUserBackend: std::fmt::Debug + Send + Sync {
+    /// Return the name of the currently active user, if any.
+    fn active_user(&self) -> BoxFuture<'_, Result<Option<String>, OgaError>>;
+
+    /// Return all logged-in users.
+    ///
+    /// By default, this only reports the active user.
+    fn logged_in_users(&self) -> BoxFuture<'_, Result<Vec<LoggedInUser>, OgaError>> {
+        async move {
+            let active = self.active_user().await?;
+            let users = active
+                .map(|name| LoggedInUser {
+                    name,
+                    ..Default::default()
+                })
+                .into_iter()
+                .collect();
+            Ok(users)
+        }
+        .boxed()
+    }
+}
+
+/// Backend scanning the utmp database for logged-in users.
+///
+/// The user with the most recent login is considered the active one.
+#[cfg(unix)]
+#[derive(Clone, Debug, Default)]
+pub struct UtmpBackend {}
+
+#[cfg(unix)]
+impl UtmpBackend {
+    /// Return a new utmp backend.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Scan utmp entries for logged-in users.
+    fn scan() -> Vec<LoggedInUser> {
+        let mut users = Vec::new();
+
+        // SAFETY: utmpx access is confined to this blocking scan; returned
+        // entries are only read before the next `getutxent()` call.
+        unsafe {
+            libc::setutxent();
+            loop {
+                let entry = libc::getutxent();
+                if entry.is_null() {
+                    break;
+                }
+                let entry = &*entry;
+                if entry.ut_type != libc::USER_PROCESS {
+                    continue;
+                }
+
+                let name = c_field(&entry.ut_user);
+                if name.is_empty() {
+                    continue;
+                }
+                let line = Some(c_field(&entry.ut_line)).filter(|l| !l.is_empty());
+                let host = Some(c_field(&entry.ut_host)).filter(|h| !h.is_empty());
+                let login_time = u64::try_from(entry.ut_tv.tv_sec).ok();
+                users.push(LoggedInUser {
+                    name,
+                    line,
+                    host,
+                    login_time,
+                });
+            }
+            libc::endutxent();
+        }
+
+        users
+    }
+}
+
+/// Convert a fixed-size, possibly NUL-terminated, C character field.
+#[cfg(unix)]
+fn c_field(field: &[libc::c_char]) -> String {
+    let raw: Vec<u8> = field
+        .iter()
+        .take_while(|c| **c != 0)
+        .map(|c| *c as u8)
+        .collect();
+    String::from_utf8_lossy(&raw).into_owned()
+}
+
+#[cfg(unix)]
+impl ActiveUserBackend for UtmpBackend {
+    fn active_user(&self) -> BoxFuture<'_, Result<Option<String>, OgaError>> {
+        async move {
+            let users = self.logged_in_users().await?;
+            let latest = users.into_iter().max_by_key(|u| u.login_time);
+            Ok(latest.map(|u| u.name))
+        }
+        .boxed()
+    }
+
+    fn logged_in_users(&self) -> BoxFuture<'_, Result<Vec<LoggedInUser>, OgaError>> {
+        async {
+            tokio::task::spawn_blocking(Self::scan)
+                .await
+                .map_err(|e| OgaError::from(format!("utmp scan task failed: {}", e)))
+        }
+        .boxed()
+    }
+}
+
+/// Backend querying systemd-logind for the owner of the active session.
+#[cfg(feature = "logind")]
+#[derive(Clone, Debug, Default)]
+pub struct LogindBackend {}
+
+#[cfg(feature = "logind")]
+impl LogindBackend {
+    /// Return a new logind backend.
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+#[cfg(feature = "logind")]
+impl ActiveUserBackend for LogindBackend {
+    fn active_user(&self) -> BoxFuture<'_, Result<Option<String>, OgaError>> {
+        async {
+            let path = match crate::logind::active_session().await {
+                Ok((_, path)) => path,
+                Err(e) => {
+                    log::trace!("no active logind session: {}", e);
+                    return Ok(None);
+                }
+            };
+            crate::logind::session_user(path).await.map(Some)
+        }
+        .boxed()
+    }
+
+    fn logged_in_users(&self) -> BoxFuture<'_, Result<Vec<LoggedInUser>, OgaError>> {
+        async {
+            let manager = crate::logind::manager().await?;
+            let sessions = manager
+                .list_sessions()
+                .await
+                .map_err(crate::logind::dbus_error)?;
+            let users = sessions
+                .into_iter()
+                .map(|(_, _, name, seat, _)| LoggedInUser {
+                    name,
+                    line: Some(seat).filter(|s| !s.is_empty()),
+                    ..Default::default()
+                })
+                .collect();
+            Ok(users)
+        }
+        .boxed()
+    }
+}
+
+/// Backend querying WMI for the user logged into the console session.
+#[cfg(windows)]
+#[derive(Clone, Debug, Default)]
+pub struct WmiBackend {}
+
+#[cfg(windows)]
+impl WmiBackend {
+    /// Return a new WMI backend.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Query the console user, as `DOMAIN\user`.
+    fn query() -> Result<Option<String>, OgaError> {
+        #[derive(serde::Deserialize)]
+        #[serde(rename_all = "PascalCase")]
+        struct ComputerSystem {
+            user_name: Option<String>,
+        }
+
+        let com = wmi::COMLibrary::new().map_err(|e| format!("COM init failed: {}", e))?;
+        let conn =
+            wmi::WMIConnection::new(com).map_err(|e| format!("WMI connection failed: {}", e))?;
+        let systems: Vec<ComputerSystem> = conn
+            .raw_query("SELECT UserName FROM Win32_ComputerSystem")
+            .map_err(|e| format!("WMI query failed: {}", e))?;
+        let user = systems
+            .into_iter()
+            .find_map(|s| s.user_name)
+            .filter(|u| !u.is_empty());
+        Ok(user)
+    }
+}
+
+#[cfg(windows)]
+impl ActiveUserBackend for WmiBackend {
+    fn active_user(&self) -> BoxFuture<'_, Result<Option<String>, OgaError>> {
+        async {
+            tokio::task::spawn_blocking(Self::query)
+                .await
+                .map_err(|e| OgaError::from(format!("WMI query task failed: {}", e)))?
+        }
+        .boxed()
+    }
+}
+
+/// Tracker reporting active-user changes to the host.
+#[derive(Debug)]
+pub struct ActiveUserTracker {
+    backend: Box<dyn ActiveUserBackend>,
+    interval: Duration,
+    report_users: bool,
+    ignored_users: BTreeSet<String>,
+}
+
+impl ActiveUserTracker {
+    /// Return a tracker polling the given backend at the given interval.
+    pub fn new(backend: Box<dyn ActiveUserBackend>, interval: Duration) -> Self {
+        Self {
+            backend,
+            interval,
+            report_users: false,
+            ignored_users: BTreeSet::new(),
+        }
+    }
+
+    /// Whether to also report all logged-in users on changes (default: false).
+    ///
+    /// This sends non-standard `logged-in-users` commands, which VDSM does
+    /// not recognize. Only enable it for hosts extended to handle them.
+    pub fn report_users(mut self, enabled: bool) -> Self {
+        self.report_users = enabled;
+        self
+    }
+
+    /// Names of users never to report, e.g. display manager accounts (default: none).
+    ///
+    /// An ignored active user is reported as no user at all. This matches
+    /// `ignored_users` in the reference agent configuration.
+    pub fn ignored_users(mut self, users: Vec<String>) -> Self {
+        self.ignored_users = users.into_iter().collect();
+        self
+    }
+
+    /// Track the active user, sending an `active-user` command on each change.
+    ///
+    /// The current user is reported when starting. This only returns on
+    /// failures to deliver commands; backend failures are logged and retried.
+    pub async fn run(self, sender: OgaCommandSender) -> OgaError {
+        let mut ticker = time::interval(self.interval);
+        let mut reported: Option<Option<String>> = None;
+        let mut reported_users: Option<Vec<LoggedInUser>> = None;
+
+        loop {
+            ticker.tick().await;
+            if self.report_users {
+                let users = self.backend.logged_in_users().await.map(|mut users| {
+                    users.retain(|u| !self.ignored_users.contains(&u.name));
+                    users
+                });
+                match users {
+                    Ok(users) if reported_users.as_ref() != Some(&users) => {
+                        let cmd = LoggedInUsers {
+                            users: users.clone(),
+                        };
+                        if let Err(e) = sender.send(Box::new(cmd)).await {
+                            return e;
+                        }
+                        log::debug!("reported {} logged-in users", users.len());
+                        reported_users = Some(users);
+                    }
+                    Ok(_) => {}
+                    Err(e) => log::warn!("failed to detect logged-in users: {}", e),
+                }
+            }
+
+            let current = match self.backend.active_user().await {
+                Ok(user) => user.filter(|name| !self.ignored_users.contains(name)),
+                Err(e) => {
+                    log::warn!("failed to detect active user: {}", e);
+                    continue;
+                }
+            };
+            if reported.as_ref() == Some(&current) {
+                continue;
+            }
+
+            let cmd = match &current {
+                Some(name) => ActiveUser { name: name.clone() },
+                None => ActiveUser::default(),
+            };
+            if let Err(e) = sender.send(Box::new(cmd)).await {
+                return e;
+            }
+            log::debug!("reported active user: {:?}", current);
+            reported = Some(current);
+        }
+    }
+}
@@ -1,90 +1,764 @@
 /*! Asynchronous I/O logic for virtio-serial devices.
 
 This implements asynchrounous logic for reading and writing
-from virtio serial ports (i.e. /dev/vport<X>n<Y>).
+from virtio serial ports (i.e. `/dev/vport<X>n<Y>`).
 Those are character devices that can polled and support read()
 and write() in non-blocking mode, but are not seekable.
 
+On other unix systems, ports may instead be exposed as terminal devices
+(e.g. `/dev/vtcon/<name>` on FreeBSD), which are switched to raw mode
+so that no line discipline gets applied to frames.
+
+On Windows, ports are exposed by the virtio-serial driver as device
+paths (e.g. `\\.\Global\<name>`), supporting overlapped I/O. Those
+are driven through the same completion-port logic as named pipes.
+
+A [`VirtioPort`] is a plain `AsyncRead` + `AsyncWrite` byte stream, which
+can be used on its own (e.g. with custom framing) without a full client.
+
 References:
  * <https://www.linux-kvm.org/page/Virtio-serial_API>
-!*/
+*/
 
 use crate::errors;
-use mio::event::Evented;
-use mio::unix::EventedFd;
-use mio::{Poll, PollOpt, Ready, Token};
+use bytes::{Buf, Bytes};
+#[cfg(unix)]
 use std::fs::{File, OpenOptions};
+#[cfg(unix)]
 use std::io::{Read, Write};
+#[cfg(unix)]
 use std::os::unix::fs::OpenOptionsExt;
+#[cfg(unix)]
 use std::os::unix::io::AsRawFd;
 use std::path::Path;
-use tokio::io::PollEvented;
+#[cfg(unix)]
+use std::path::PathBuf;
+use std::pin::Pin;
+#[cfg(unix)]
+use std::task::ready;
+use std::task::{Context, Poll};
+#[cfg(unix)]
+use tokio::io::unix::AsyncFd;
+use tokio::io::{AsyncRead, AsyncWrite, AsyncWriteExt, ReadBuf, ReadHalf, WriteHalf};
+#[cfg(windows)]
+use tokio::net::windows::named_pipe::{ClientOptions, NamedPipeClient};
+use tokio::time::{self, Duration, Instant};
 
 /// VirtIO serial port (guest side).
 #[derive(Debug)]
 pub struct VirtioPort {
-    dev: File,
+    #[cfg(unix)]
+    dev: AsyncFd<File>,
+    #[cfg(windows)]
+    dev: NamedPipeClient,
 }
 
+#[cfg(unix)]
 impl VirtioPort {
     /// Open a virtio-serial device at given path, in non-blocking mode.
-    pub(crate) fn open(path: impl AsRef<Path>) -> Result<Self, errors::OgaError> {
-        let dev = OpenOptions::new()
+    ///
+    /// The device is registered with the tokio reactor, thus this
+    /// must be called from within a runtime context.
+    ///
+    /// If a by-name device link (i.e. under /dev/virtio-ports) is missing,
+    /// as without udev in minimal environments, the port is looked up by
+    /// its name in sysfs and opened through its kernel device node.
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, errors::OgaError> {
+        let file =
+            Self::open_node(path.as_ref(), false).map_err(|e| open_error(path.as_ref(), &e))?;
+        Self::from_node(path.as_ref(), file)
+    }
+
+    /// Open the device node for a port, falling back to sysfs lookup if missing.
+    ///
+    /// In strict mode, the node is resolved without escaping /dev and must be
+    /// a virtio-serial character device.
+    fn open_node(path: &Path, strict: bool) -> std::io::Result<File> {
+        let open_file = if strict {
+            Self::open_file_strict
+        } else {
+            Self::open_file
+        };
+        match open_file(path) {
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => match by_name_fallback(path) {
+                Some(node) => {
+                    log::debug!(
+                        "device '{}' not found, using '{}'",
+                        path.display(),
+                        node.display()
+                    );
+                    open_file(&node)
+                }
+                None => Err(e),
+            },
+            res => res,
+        }
+    }
+
+    /// Configure an open device node and register it with the reactor.
+    fn from_node(path: &Path, file: File) -> Result<Self, errors::OgaError> {
+        Self::set_raw_mode(&file).map_err(|e| {
+            format!(
+                "failed to configure terminal device '{}': {}",
+                path.display(),
+                e
+            )
+        })?;
+        let dev = AsyncFd::new(file)
+            .map_err(|e| format!("failed to register pollable virtio port: {}", e))?;
+        let vport = Self { dev };
+        Ok(vport)
+    }
+
+    /// Open a device node, in non-blocking mode.
+    fn open_file(path: &Path) -> std::io::Result<File> {
+        OpenOptions::new()
             .create(false)
             .read(true)
             .write(true)
-            .custom_flags(libc::O_NONBLOCK)
-            .open(path.as_ref())
-            .map_err(|e| format!("failed to open device '{}': {}", path.as_ref().display(), e))?;
+            .custom_flags(libc::O_NONBLOCK | libc::O_NOCTTY)
+            .open(path)
+    }
+
+    /// Open a device node, resolving it beneath /dev and verifying its type.
+    #[cfg(target_os = "linux")]
+    fn open_file_strict(path: &Path) -> std::io::Result<File> {
+        use std::os::unix::ffi::OsStrExt;
+        use std::os::unix::io::FromRawFd;
+
+        let relative = path.strip_prefix(DEV_DIR).map_err(|_| {
+            std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!("device path is not under {}", DEV_DIR),
+            )
+        })?;
+        let relative_c = std::ffi::CString::new(relative.as_os_str().as_bytes())?;
+        let dev_dir = OpenOptions::new()
+            .read(true)
+            .custom_flags(libc::O_DIRECTORY)
+            .open(DEV_DIR)?;
+
+        // SAFETY: `how` is fully initialized, and both the directory
+        // descriptor and the relative path outlive the call.
+        let res = unsafe {
+            let mut how: libc::open_how = std::mem::zeroed();
+            how.flags = (libc::O_RDWR | libc::O_NONBLOCK | libc::O_NOCTTY | libc::O_CLOEXEC) as u64;
+            how.resolve = libc::RESOLVE_BENEATH | libc::RESOLVE_NO_MAGICLINKS;
+            let fd = libc::syscall(
+                libc::SYS_openat2,
+                dev_dir.as_raw_fd(),
+                relative_c.as_ptr(),
+                &how as *const libc::open_how,
+                std::mem::size_of::<libc::open_how>(),
+            );
+            match fd {
+                fd if fd < 0 => Err(std::io::Error::last_os_error()),
+                fd => Ok(File::from_raw_fd(fd as i32)),
+            }
+        };
+        let file = match res {
+            // `openat2` is only available since Linux 5.6.
+            Err(e) if e.raw_os_error() == Some(libc::ENOSYS) => {
+                log::debug!(
+                    "openat2 not supported, resolving '{}' by components",
+                    path.display()
+                );
+                open_beneath(dev_dir, Path::new(DEV_DIR), relative)?
+            }
+            res => res?,
+        };
+
+        Self::verify_device(&file)?;
+        Ok(file)
+    }
+
+    /// Open a device node, verifying its type.
+    #[cfg(not(target_os = "linux"))]
+    fn open_file_strict(path: &Path) -> std::io::Result<File> {
+        let file = Self::open_file(path)?;
+        Self::verify_device(&file)?;
+        Ok(file)
+    }
+
+    /// Check that an open node is a virtio-serial character device.
+    fn verify_device(file: &File) -> std::io::Result<()> {
+        use std::os::unix::fs::{FileTypeExt, MetadataExt};
+
+        let meta = file.metadata()?;
+        if !meta.file_type().is_char_device() {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                "not a character device",
+            ));
+        }
+
+        #[cfg(target_os = "linux")]
+        {
+            let (major, minor) = (libc::major(meta.rdev()), libc::minor(meta.rdev()));
+            let link = format!("/sys/dev/char/{}:{}/subsystem", major, minor);
+            let subsystem = std::fs::read_link(&link).unwrap_or_default();
+            if subsystem.file_name() != Some(std::ffi::OsStr::new("virtio-ports")) {
+                let msg = format!("device {}:{} is not a virtio-serial port", major, minor);
+                return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, msg));
+            }
+        }
+        Ok(())
+    }
+
+    /// Switch a terminal device to raw mode, leaving other devices untouched.
+    fn set_raw_mode(file: &File) -> std::io::Result<()> {
+        let fd = file.as_raw_fd();
+        // SAFETY: `fd` is a valid open descriptor for the whole block, and
+        // `termios` is fully initialized by `tcgetattr` before use.
+        unsafe {
+            if libc::isatty(fd) != 1 {
+                return Ok(());
+            }
+            let mut termios: libc::termios = std::mem::zeroed();
+            if libc::tcgetattr(fd, &mut termios) != 0 {
+                return Err(std::io::Error::last_os_error());
+            }
+            libc::cfmakeraw(&mut termios);
+            if libc::tcsetattr(fd, libc::TCSANOW, &termios) != 0 {
+                return Err(std::io::Error::last_os_error());
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Directory for device nodes.
+#[cfg(target_os = "linux")]
+static DEV_DIR: &str = "/dev";
+
+/// Maximum number of symbolic links followed while resolving a device path.
+#[cfg(target_os = "linux")]
+const MAX_SYMLINKS: usize = 40;
+
+/// Open a device node beneath a base directory, one component at a time.
+///
+/// This is the fallback for `openat2(RESOLVE_BENEATH)` on older kernels:
+/// each component is opened with `O_NOFOLLOW` relative to its parent,
+/// while symbolic links are resolved here, failing if they escape `base`.
+#[cfg(target_os = "linux")]
+fn open_beneath(base: File, base_path: &Path, relative: &Path) -> std::io::Result<File> {
+    use std::collections::VecDeque;
+    use std::ffi::{CString, OsString};
+    use std::os::unix::ffi::OsStrExt;
+    use std::os::unix::io::FromRawFd;
+    use std::path::Component;
+
+    let escaped = || {
+        let msg = format!("device path escapes {}", base_path.display());
+        std::io::Error::new(std::io::ErrorKind::InvalidInput, msg)
+    };
+    let components = |path: &Path| -> Vec<OsString> {
+        path.components()
+            .filter_map(|c| match c {
+                Component::Normal(name) => Some(name.to_os_string()),
+                Component::ParentDir => Some(OsString::from("..")),
+                _ => None,
+            })
+            .collect()
+    };
+
+    let mut dirs = vec![base];
+    let mut pending: VecDeque<OsString> = components(relative).into();
+    let mut links = 0;
+    while let Some(name) = pending.pop_front() {
+        if name == ".." {
+            if dirs.len() == 1 {
+                return Err(escaped());
+            }
+            dirs.pop();
+            continue;
+        }
+        let parent = dirs[dirs.len() - 1].as_raw_fd();
+        let name = CString::new(name.as_bytes())?;
+
+        if let Some(target) = read_link_at(parent, &name)? {
+            links += 1;
+            if links > MAX_SYMLINKS {
+                return Err(std::io::Error::from_raw_os_error(libc::ELOOP));
+            }
+            let target = PathBuf::from(target);
+            let target = match target.strip_prefix(base_path) {
+                Ok(beneath) => {
+                    dirs.truncate(1);
+                    beneath.to_path_buf()
+                }
+                Err(_) if target.is_absolute() => return Err(escaped()),
+                Err(_) => target,
+            };
+            for component in components(&target).into_iter().rev() {
+                pending.push_front(component);
+            }
+            continue;
+        }
+
+        let flags = match pending.is_empty() {
+            true => libc::O_RDWR | libc::O_NONBLOCK | libc::O_NOCTTY,
+            false => libc::O_RDONLY | libc::O_DIRECTORY,
+        };
+        // SAFETY: both the parent descriptor and the name outlive the call.
+        let fd = unsafe {
+            libc::openat(
+                parent,
+                name.as_ptr(),
+                flags | libc::O_NOFOLLOW | libc::O_CLOEXEC,
+            )
+        };
+        if fd < 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+        // SAFETY: `fd` is a freshly opened descriptor, owned from here on.
+        let file = unsafe { File::from_raw_fd(fd) };
+        if pending.is_empty() {
+            return Ok(file);
+        }
+        dirs.push(file);
+    }
+    Err(std::io::Error::new(
+        std::io::ErrorKind::InvalidInput,
+        "device path does not name a file",
+    ))
+}
+
+/// Read the target of a symbolic link, returning `None` for other file types.
+#[cfg(target_os = "linux")]
+fn read_link_at(dir: i32, name: &std::ffi::CStr) -> std::io::Result<Option<std::ffi::OsString>> {
+    use std::os::unix::ffi::OsStringExt;
+
+    let mut buf = vec![0u8; libc::PATH_MAX as usize];
+    // SAFETY: `buf` is valid for writes of its whole length.
+    let len = unsafe { libc::readlinkat(dir, name.as_ptr(), buf.as_mut_ptr().cast(), buf.len()) };
+    if len < 0 {
+        let err = std::io::Error::last_os_error();
+        return match err.raw_os_error() {
+            Some(libc::EINVAL) => Ok(None),
+            _ => Err(err),
+        };
+    }
+    buf.truncate(len as usize);
+    Ok(Some(std::ffi::OsString::from_vec(buf)))
+}
+
+/// Directory for by-name links to virtio-serial ports, as created by udev.
+#[cfg(target_os = "linux")]
+static BY_NAME_DIR: &str = "/dev/virtio-ports";
+
+/// Base sysfs directory for virtio-serial ports.
+#[cfg(target_os = "linux")]
+static SYSFS_PORTS_DIR: &str = "/sys/class/virtio-ports";
+
+/// Resolve a missing by-name link to the kernel device node of the named port.
+#[cfg(target_os = "linux")]
+fn by_name_fallback(path: &Path) -> Option<PathBuf> {
+    let name = path.strip_prefix(BY_NAME_DIR).ok()?.to_str()?;
+    find_port_node(name)
+}
+
+#[cfg(all(unix, not(target_os = "linux")))]
+fn by_name_fallback(_path: &Path) -> Option<PathBuf> {
+    None
+}
+
+/// Find the device node (i.e. `/dev/vport<X>n<Y>`) of the port with the given name.
+#[cfg(target_os = "linux")]
+pub fn find_port_node(name: &str) -> Option<PathBuf> {
+    let entries = std::fs::read_dir(SYSFS_PORTS_DIR).ok()?;
+    for entry in entries.flatten() {
+        let port_name = match std::fs::read_to_string(entry.path().join("name")) {
+            Ok(content) => content,
+            Err(_) => continue,
+        };
+        if port_name.trim() == name {
+            let node = Path::new("/dev").join(entry.file_name());
+            return Some(node);
+        }
+    }
+    None
+}
+
+#[cfg(windows)]
+impl VirtioPort {
+    /// Open a virtio-serial device at given path, in overlapped mode.
+    ///
+    /// The device is registered with the tokio reactor, thus this
+    /// must be called from within a runtime context.
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, errors::OgaError> {
+        let dev =
+            Self::open_node(path.as_ref(), false).map_err(|e| open_error(path.as_ref(), &e))?;
+        Self::from_node(path.as_ref(), dev)
+    }
+
+    /// Open the device handle for a port.
+    ///
+    /// Strict device checks are not supported, thus ignored.
+    fn open_node(path: &Path, _strict: bool) -> std::io::Result<NamedPipeClient> {
+        ClientOptions::new().open(path)
+    }
+
+    /// Wrap an open device handle.
+    fn from_node(_path: &Path, dev: NamedPipeClient) -> Result<Self, errors::OgaError> {
         let vport = Self { dev };
         Ok(vport)
     }
+}
+
+impl VirtioPort {
+    /// Open a virtio-serial device, retrying on permission errors until `retry` elapses.
+    ///
+    /// Permission errors right after boot or hotplug are often transient,
+    /// until udev rules get applied to the device node. In `strict` mode,
+    /// the device node is verified before use.
+    ///
+    /// Each attempt runs on the blocking thread pool and fails after `timeout`,
+    /// so that a hung device node does not stall the runtime. A timed out
+    /// attempt keeps its blocking thread until the underlying call returns.
+    pub async fn open_with_retry(
+        path: &Path,
+        retry: Duration,
+        strict: bool,
+        timeout: Duration,
+    ) -> Result<Self, errors::OgaError> {
+        let deadline = Instant::now() + retry;
+        loop {
+            let node_path = path.to_path_buf();
+            let attempt = tokio::task::spawn_blocking(move || Self::open_node(&node_path, strict));
+            let res = time::timeout(timeout, attempt)
+                .await
+                .map_err(|_| format!("timed out opening device '{}'", path.display()))?
+                .map_err(|e| format!("device open task failed: {}", e))?;
+            match res {
+                Ok(node) => return Self::from_node(path, node),
+                Err(e) if is_permission_error(&e) && Instant::now() < deadline => {
+                    log::debug!("device '{}' not accessible yet, retrying", path.display());
+                    time::sleep(PERMISSION_RETRY_STEP).await;
+                }
+                Err(e) => return Err(open_error(path, &e)),
+            }
+        }
+    }
+
+    /// Split this port into independently owned read and write halves.
+    pub fn split(self) -> (ReadHalf<Self>, WriteHalf<Self>) {
+        tokio::io::split(self)
+    }
+}
+
+/// Delay between attempts to open a device with permission errors.
+const PERMISSION_RETRY_STEP: Duration = Duration::from_millis(250);
+
+/// Whether an error is due to missing permissions on the device.
+fn is_permission_error(err: &std::io::Error) -> bool {
+    #[cfg(unix)]
+    if matches!(err.raw_os_error(), Some(libc::EACCES) | Some(libc::EPERM)) {
+        return true;
+    }
+    err.kind() == std::io::ErrorKind::PermissionDenied
+}
+
+/// Describe a failure to open a device, with diagnostics for permission errors.
+fn open_error(path: &Path, err: &std::io::Error) -> errors::OgaError {
+    let mut msg = format!("failed to open device '{}': {}", path.display(), err);
+    if is_permission_error(err) {
+        #[cfg(unix)]
+        if let Some(details) = permission_details(path) {
+            msg.push_str(&format!(" ({})", details));
+        }
+        msg.push_str("; check device ownership or udev rules for the agent user");
+    }
+    errors::OgaError::from(msg)
+}
+
+/// Describe device ownership and mode, versus the current process credentials.
+#[cfg(unix)]
+fn permission_details(path: &Path) -> Option<String> {
+    use std::os::unix::fs::MetadataExt;
+
+    let meta = std::fs::metadata(path).ok()?;
+    // SAFETY: these calls have no preconditions and cannot fail.
+    let (euid, egid) = unsafe { (libc::geteuid(), libc::getegid()) };
+    let details = format!(
+        "device owned by {}:{} with mode {:04o}, agent running as {}:{}",
+        user_name(meta.uid()),
+        group_name(meta.gid()),
+        meta.mode() & 0o7777,
+        user_name(euid),
+        group_name(egid),
+    );
+    Some(details)
+}
+
+/// Return the name of a user, or its numeric ID if unknown.
+#[cfg(unix)]
+fn user_name(uid: libc::uid_t) -> String {
+    let mut buf = vec![0 as libc::c_char; 1024];
+    // SAFETY: `pwd` and `buf` outlive the call, and the returned name
+    // points into `buf`, which is only read while `result` is non-null.
+    unsafe {
+        let mut pwd: libc::passwd = std::mem::zeroed();
+        let mut result = std::ptr::null_mut();
+        libc::getpwuid_r(uid, &mut pwd, buf.as_mut_ptr(), buf.len(), &mut result);
+        if result.is_null() || pwd.pw_name.is_null() {
+            return uid.to_string();
+        }
+        let name = std::ffi::CStr::from_ptr(pwd.pw_name).to_string_lossy();
+        format!("{}({})", name, uid)
+    }
+}
+
+/// Return the name of a group, or its numeric ID if unknown.
+#[cfg(unix)]
+fn group_name(gid: libc::gid_t) -> String {
+    let mut buf = vec![0 as libc::c_char; 1024];
+    // SAFETY: `grp` and `buf` outlive the call, and the returned name
+    // points into `buf`, which is only read while `result` is non-null.
+    unsafe {
+        let mut grp: libc::group = std::mem::zeroed();
+        let mut result = std::ptr::null_mut();
+        libc::getgrgid_r(gid, &mut grp, buf.as_mut_ptr(), buf.len(), &mut result);
+        if result.is_null() || grp.gr_name.is_null() {
+            return gid.to_string();
+        }
+        let name = std::ffi::CStr::from_ptr(grp.gr_name).to_string_lossy();
+        format!("{}({})", name, gid)
+    }
+}
+
+/// Writer of whole frames to a port, resuming partial writes.
+///
+/// Under host backpressure, writes may only accept part of a frame. Those
+/// are resumed until the frame is complete. If writing gets interrupted
+/// (e.g. cancelled), the rest of the frame is kept and completed first by
+/// the next write, so that frames never interleave.
+#[derive(Debug)]
+pub(crate) struct FrameWriter<W> {
+    inner: W,
+    partial: Bytes,
+    /// Bytes of the last frame written so far, excluding resumed ones.
+    written: usize,
+}
+
+impl<W: AsyncWrite + Unpin> FrameWriter<W> {
+    /// Return a writer wrapping the given port.
+    pub(crate) fn new(inner: W) -> Self {
+        Self {
+            inner,
+            partial: Bytes::new(),
+            written: 0,
+        }
+    }
+
+    /// Whether any part of the last frame reached the port.
+    ///
+    /// Such a frame must not be written again after a failure, as the host
+    /// would otherwise see it (partially) twice.
+    pub(crate) fn wrote_any(&self) -> bool {
+        self.written > 0
+    }
+
+    /// Write a whole frame, then flush the port.
+    ///
+    /// This is cancel-safe, as progress is recorded after each write.
+    pub(crate) async fn write_frame(&mut self, frame: &Bytes) -> std::io::Result<()> {
+        // Only the given frame is accounted, even if resuming fails.
+        self.written = 0;
+        if !self.partial.is_empty() {
+            log::debug!("resuming partially written frame");
+            self.write_partial(false).await?;
+        }
+        self.partial = frame.clone();
+        self.write_partial(true).await?;
+        self.inner.flush().await
+    }
+
+    /// Write the remaining part of the current frame, optionally accounting it.
+    async fn write_partial(&mut self, account: bool) -> std::io::Result<()> {
+        while !self.partial.is_empty() {
+            let len = self.inner.write(&self.partial).await?;
+            if len == 0 {
+                return Err(std::io::ErrorKind::WriteZero.into());
+            }
+            self.partial.advance(len);
+            if account {
+                self.written += len;
+            }
+            if !self.partial.is_empty() {
+                log::trace!("short write, {} bytes left", self.partial.len());
+            }
+        }
+        Ok(())
+    }
+}
 
-    /// Trasnsform into a tokio-compatible evented object.
-    pub(crate) fn evented(self) -> Result<PollEvented<VirtioPort>, errors::OgaError> {
-        PollEvented::new(self)
-            .map_err(|e| format!("failed to register pollable virtio port: {}", e).into())
+#[cfg(unix)]
+impl AsyncRead for VirtioPort {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        loop {
+            let mut guard = ready!(self.dev.poll_read_ready(cx))?;
+            let unfilled = buf.initialize_unfilled();
+            match guard.try_io(|inner| inner.get_ref().read(unfilled)) {
+                Ok(Ok(len)) => {
+                    buf.advance(len);
+                    return Poll::Ready(Ok(()));
+                }
+                Ok(Err(err)) if err.kind() == std::io::ErrorKind::Interrupted => continue,
+                Ok(Err(err)) => return Poll::Ready(Err(err)),
+                Err(_would_block) => continue,
+            }
+        }
     }
 }
 
-impl Evented for VirtioPort {
-    fn register(
-        &self,
-        poll: &Poll,
-        token: Token,
-        interest: Ready,
-        opts: PollOpt,
-    ) -> std::io::Result<()> {
-        EventedFd(&self.dev.as_raw_fd()).register(poll, token, interest, opts)
+#[cfg(unix)]
+impl AsyncWrite for VirtioPort {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<std::io::Result<usize>> {
+        loop {
+            let mut guard = ready!(self.dev.poll_write_ready(cx))?;
+            match guard.try_io(|inner| inner.get_ref().write(buf)) {
+                Ok(Err(err)) if err.kind() == std::io::ErrorKind::Interrupted => continue,
+                Ok(res) => return Poll::Ready(res),
+                Err(_would_block) => continue,
+            }
+        }
     }
 
-    fn reregister(
-        &self,
-        poll: &Poll,
-        token: Token,
-        interest: Ready,
-        opts: PollOpt,
-    ) -> std::io::Result<()> {
-        EventedFd(&self.dev.as_raw_fd()).reregister(poll, token, interest, opts)
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Poll::Ready(self.dev.get_ref().flush())
     }
 
-    fn deregister(&self, poll: &Poll) -> std::io::Result<()> {
-        EventedFd(&self.dev.as_raw_fd()).deregister(poll)
+    fn poll_shutdown(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Poll::Ready(Ok(()))
     }
 }
 
-impl Write for VirtioPort {
-    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
-        self.dev.write(buf)
+#[cfg(windows)]
+impl AsyncRead for VirtioPort {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.dev).poll_read(cx, buf)
+    }
+}
+
+#[cfg(windows)]
+impl AsyncWrite for VirtioPort {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<std::io::Result<usize>> {
+        Pin::new(&mut self.dev).poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.dev).poll_flush(cx)
     }
 
-    fn flush(&mut self) -> std::io::Result<()> {
-        self.dev.flush()
+    fn poll_shutdown(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Poll::Ready(Ok(()))
     }
 }
 
-impl Read for VirtioPort {
-    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
-        self.dev.read(buf)
+#[cfg(all(test, target_os = "linux"))]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::symlink;
+
+    /// Port accepting a limited amount of bytes, then failing.
+    struct ShortPort {
+        budget: usize,
+        written: Vec<u8>,
+    }
+
+    impl AsyncWrite for ShortPort {
+        fn poll_write(
+            mut self: Pin<&mut Self>,
+            _cx: &mut Context<'_>,
+            buf: &[u8],
+        ) -> Poll<std::io::Result<usize>> {
+            if self.budget == 0 {
+                return Poll::Ready(Err(std::io::ErrorKind::BrokenPipe.into()));
+            }
+            let len = buf.len().min(self.budget);
+            self.budget -= len;
+            self.written.extend_from_slice(&buf[..len]);
+            Poll::Ready(Ok(len))
+        }
+
+        fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn poll_shutdown(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+            Poll::Ready(Ok(()))
+        }
+    }
+
+    #[tokio::test]
+    async fn account_only_the_last_frame() {
+        let port = ShortPort {
+            budget: 2,
+            written: Vec::new(),
+        };
+        let mut writer = FrameWriter::new(port);
+        assert!(!writer.wrote_any());
+
+        writer
+            .write_frame(&Bytes::from("first\n"))
+            .await
+            .unwrap_err();
+        assert!(writer.wrote_any());
+
+        // Failing to resume the torn frame leaves the next one untouched.
+        writer
+            .write_frame(&Bytes::from("second\n"))
+            .await
+            .unwrap_err();
+        assert!(!writer.wrote_any());
+
+        writer.inner.budget = 16;
+        writer.write_frame(&Bytes::from("third\n")).await.unwrap();
+        assert!(writer.wrote_any());
+        assert_eq!(writer.inner.written, b"first\nthird\n");
+    }
+
+    #[test]
+    fn open_beneath_by_components() {
+        let dir = tempfile::tempdir().unwrap();
+        let base = dir.path();
+        std::fs::create_dir(base.join("ports")).unwrap();
+        std::fs::write(base.join("vport0p1"), "").unwrap();
+        symlink("../vport0p1", base.join("ports/relative")).unwrap();
+        symlink(base.join("vport0p1"), base.join("ports/absolute")).unwrap();
+        symlink("../../outside", base.join("ports/escaping")).unwrap();
+        symlink("/etc/hostname", base.join("ports/foreign")).unwrap();
+
+        let open = |relative: &str| {
+            let base_dir = File::open(base).unwrap();
+            open_beneath(base_dir, base, Path::new(relative))
+        };
+        open("vport0p1").unwrap();
+        open("ports/relative").unwrap();
+        open("ports/absolute").unwrap();
+        open("ports/../vport0p1").unwrap();
+        let err = open("ports/missing").unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::NotFound);
+        for relative in ["../vport0p1", "ports/escaping", "ports/foreign"] {
+            let err = open(relative).unwrap_err();
+            assert_eq!(err.kind(), std::io::ErrorKind::InvalidInput, "{}", relative);
+        }
     }
 }
@@ -0,0 +1,143 @@
+//! Fair interleaving of commands from multiple senders.
+
+#![cfg(unix)]
+
+use futures::future;
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::os::unix::io::FromRawFd;
+use std::time::Duration;
+use tokio_oga::commands::{Applications, AsFrame, SessionStartup};
+use tokio_oga::{OgaClient, OgaCommandSender};
+
+/// Open a pseudo-terminal in raw mode, returning its master side and the path of its slave.
+fn pty() -> (File, String) {
+    // SAFETY: plain libc calls on a freshly opened descriptor, owned by the returned `File`.
+    unsafe {
+        let master = libc::posix_openpt(libc::O_RDWR | libc::O_NOCTTY);
+        assert!(master >= 0, "posix_openpt failed");
+        assert_eq!(libc::grantpt(master), 0);
+        assert_eq!(libc::unlockpt(master), 0);
+        let name = std::ffi::CStr::from_ptr(libc::ptsname(master))
+            .to_string_lossy()
+            .into_owned();
+        let mut attrs: libc::termios = std::mem::zeroed();
+        libc::tcgetattr(master, &mut attrs);
+        libc::cfmakeraw(&mut attrs);
+        libc::tcsetattr(master, libc::TCSANOW, &attrs);
+        (File::from_raw_fd(master), name)
+    }
+}
+
+/// Connect a client to a fake host, which does not read until told to.
+async fn connect() -> (OgaClient, File, File) {
+    let (host, port) = pty();
+    // Keep the slave side open, so that the host never sees a hang-up.
+    let keep = File::options().read(true).write(true).open(&port).unwrap();
+    let client = OgaClient::builder()
+        .device_path(Some(&port))
+        .pacemaker(Some(false))
+        .initial_heartbeat(Some(false))
+        .commands_buffer(Some(4))
+        .connect()
+        .await
+        .unwrap();
+    (client, host, keep)
+}
+
+/// Build a bulky report, tagged with its sender and sequence number.
+fn report(sender: &str, seq: usize) -> Box<dyn AsFrame> {
+    let padding = "x".repeat(2048);
+    let cmd = Applications {
+        applications: vec![format!("{}-{}", sender, seq), padding],
+    };
+    Box::new(cmd)
+}
+
+/// Queue many reports on a single sender, without waiting for each one.
+fn flood(sender: OgaCommandSender, tag: &'static str, count: usize) -> tokio::task::JoinHandle<()> {
+    tokio::spawn(async move {
+        let sends = (0..count).map(|seq| sender.send(report(tag, seq)));
+        for res in future::join_all(sends).await {
+            res.unwrap();
+        }
+    })
+}
+
+/// Read frames as written by the client, returning a label for each one.
+fn read_frames(host: File, count: usize) -> Vec<String> {
+    let mut lines = BufReader::new(host).lines();
+    let mut frames = Vec::with_capacity(count);
+    while frames.len() < count {
+        let line = lines.next().unwrap().unwrap();
+        let frame: serde_json::Value = serde_json::from_str(&line).unwrap();
+        let label = match frame["__name__"].as_str().unwrap() {
+            "applications" => frame["applications"][0].as_str().unwrap().to_string(),
+            name => name.to_string(),
+        };
+        frames.push(label);
+    }
+    frames
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn busy_sender_does_not_starve_others() {
+    let (client, host, _keep) = connect().await;
+
+    // Let a chatty reporter build a large backlog, while the host is stuck.
+    let reporter = flood(client.command_chan(), "reporter", 60);
+    tokio::time::sleep(Duration::from_millis(200)).await;
+
+    let app = client.command_chan();
+    let started = tokio::spawn(async move { app.send(Box::new(SessionStartup::default())).await });
+    tokio::time::sleep(Duration::from_millis(200)).await;
+
+    let frames = tokio::task::spawn_blocking(move || read_frames(host, 61))
+        .await
+        .unwrap();
+    started.await.unwrap().unwrap();
+    reporter.await.unwrap();
+
+    let pos = frames.iter().position(|f| f == "session-startup").unwrap();
+    assert!(
+        pos < 30,
+        "session-startup written after {} reports: {:?}",
+        pos,
+        frames
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn busy_senders_are_interleaved() {
+    let (client, host, _keep) = connect().await;
+
+    let sender = client.command_chan();
+    let first = flood(sender.clone(), "first", 40);
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    let second = flood(sender, "second", 40);
+    tokio::time::sleep(Duration::from_millis(200)).await;
+
+    let frames = tokio::task::spawn_blocking(move || read_frames(host, 80))
+        .await
+        .unwrap();
+    first.await.unwrap();
+    second.await.unwrap();
+
+    // Once both backlogs are queued, senders take turns.
+    let longest_run = frames[20..60]
+        .chunk_by(|a, b| a.split('-').next() == b.split('-').next())
+        .map(<[String]>::len)
+        .max()
+        .unwrap();
+    assert!(longest_run <= 2, "senders not interleaved: {:?}", frames);
+
+    // Each sender keeps its own order.
+    for tag in &["first-", "second-"] {
+        let seqs: Vec<usize> = frames
+            .iter()
+            .filter_map(|f| f.strip_prefix(tag))
+            .map(|seq| seq.parse().unwrap())
+            .collect();
+        assert_eq!(seqs, (0..40).collect::<Vec<_>>());
+    }
+}
@@ -0,0 +1,216 @@
+//! Property-based checks for the encoding of commands and events.
+
+use bytes::BytesMut;
+use proptest::collection::{btree_map, vec};
+use proptest::option;
+use proptest::prelude::*;
+use serde_json::{Map, Value};
+use tokio_oga::commands::{self, AsFrame};
+use tokio_oga::events::{self, Event, LazyEvent};
+use tokio_oga::{protocol, Secret};
+
+/// Root directory, as a prefix for absolute paths.
+#[cfg(unix)]
+const ROOT_DIR: &str = "/";
+#[cfg(windows)]
+const ROOT_DIR: &str = "C:\\";
+
+/// Arbitrary text, including control and non-ASCII characters.
+fn text() -> impl Strategy<Value = String> {
+    any::<String>()
+}
+
+/// Non-empty text, for fields which must not be empty.
+fn name() -> impl Strategy<Value = String> {
+    text().prop_filter("empty name", |s| !s.is_empty())
+}
+
+/// Flat JSON object, without the reserved `__name__` field.
+fn payload() -> impl Strategy<Value = Map<String, Value>> {
+    let value = prop_oneof![
+        Just(Value::Null),
+        any::<bool>().prop_map(Value::from),
+        any::<i64>().prop_map(Value::from),
+        text().prop_map(Value::from),
+    ];
+    btree_map(text(), value, 0..8).prop_map(|fields| {
+        fields
+            .into_iter()
+            .filter(|(key, _)| key != "__name__")
+            .collect()
+    })
+}
+
+/// Any valid command.
+fn command() -> impl Strategy<Value = Box<dyn AsFrame>> {
+    fn boxed(cmd: impl AsFrame + 'static) -> Box<dyn AsFrame> {
+        Box::new(cmd)
+    }
+
+    let user = (
+        name(),
+        option::of(text()),
+        option::of(text()),
+        option::of(any::<u64>()),
+    )
+        .prop_map(
+            |(name, line, host, login_time)| commands::extensions::LoggedInUser {
+                name,
+                line,
+                host,
+                login_time,
+            },
+        );
+    let interface = (name(), text(), vec(text(), 0..4), vec(text(), 0..4)).prop_map(
+        |(name, hw, inet, inet6)| commands::NetworkInterface {
+            name,
+            hw,
+            inet,
+            inet6,
+        },
+    );
+    let disk = (text(), text(), any::<u64>(), any::<u64>()).prop_map(|(path, fs, a, b)| {
+        commands::DiskUsage {
+            path: format!("{}{}", ROOT_DIR, path),
+            fs,
+            total: a.max(b),
+            used: a.min(b),
+        }
+    });
+    let container = (text(), vec(text(), 0..4), text(), text(), text()).prop_map(
+        |(id, names, image, command, status)| commands::Container {
+            id,
+            names,
+            image,
+            command,
+            status,
+        },
+    );
+
+    prop_oneof![
+        any::<u64>().prop_map(|free_ram| {
+            let mut heartbeat = commands::Heartbeat::default();
+            heartbeat.free_ram = free_ram;
+            boxed(heartbeat)
+        }),
+        Just(()).prop_map(|_| boxed(commands::SessionStartup::default())),
+        Just(()).prop_map(|_| boxed(commands::SessionShutdown::default())),
+        Just(()).prop_map(|_| boxed(commands::Uninstalled::default())),
+        name().prop_map(|name| boxed(commands::ActiveUser { name })),
+        vec(user, 0..4).prop_map(|users| boxed(commands::extensions::LoggedInUsers { users })),
+        (1..u32::MAX).prop_map(|count| boxed(commands::NumberOfCpus { count })),
+        (name(), any::<i32>())
+            .prop_map(|(zone, offset)| boxed(commands::Timezone { zone, offset })),
+        btree_map(name(), text(), 0..4).prop_map(|disks| {
+            let mapping = disks
+                .into_iter()
+                .map(|(serial, name)| (serial, commands::MappedDisk { name }))
+                .collect();
+            boxed(commands::DiskMapping { mapping })
+        }),
+        vec(interface, 0..4)
+            .prop_map(|interfaces| boxed(commands::NetworkInterfaces { interfaces })),
+        vec(disk, 0..4).prop_map(|disks| boxed(commands::DisksUsage { disks })),
+        vec(text(), 0..8).prop_map(|applications| boxed(commands::Applications { applications })),
+        text().prop_map(|version| boxed(commands::OsVersion { version })),
+        (text(), text(), text(), text(), text(), text()).prop_map(
+            |(version, distribution, codename, arch, os_type, kernel)| boxed(commands::OsInfo {
+                version,
+                distribution,
+                codename,
+                arch,
+                os_type,
+                kernel,
+            })
+        ),
+        vec(container, 0..4).prop_map(|list| boxed(commands::Containers { list })),
+        name().prop_map(|name| boxed(commands::HostName { name })),
+        payload().prop_map(|payload| boxed(commands::EchoReply { payload })),
+        text().prop_map(|id| boxed(commands::EchoProbe { id })),
+        (name(), payload()).prop_map(|(name, payload)| boxed(commands::Custom { name, payload })),
+    ]
+}
+
+/// Any event.
+fn event() -> impl Strategy<Value = Event> {
+    prop_oneof![
+        any::<u8>().prop_map(|api_version| Event::ApiVersion(events::ApiVersion { api_version })),
+        payload().prop_map(|payload| Event::Echo(events::Echo { payload })),
+        Just(Event::Hibernate(events::Hibernate {})),
+        Just(Event::LifecycleEvent(events::LifecycleEvent {})),
+        Just(Event::LockScreen(events::LockScreen {})),
+        (option::of(text()), option::of(text()), option::of(text())).prop_map(
+            |(username, domain, password)| Event::Login(events::Login {
+                username,
+                domain,
+                password: password.map(Secret::new),
+            })
+        ),
+        Just(Event::LogOff(events::LogOff {})),
+        any::<u8>().prop_map(|api_version| Event::Refresh(events::Refresh { api_version })),
+        any::<u32>().prop_map(|count| Event::SetNumberOfCpus(events::SetNumberOfCpus { count })),
+        (
+            option::of(text()),
+            option::of(any::<u64>()),
+            option::of(text())
+        )
+            .prop_map(
+                |(message, timeout, reboot)| Event::Shutdown(events::Shutdown {
+                    message,
+                    timeout,
+                    reboot,
+                })
+            ),
+    ]
+}
+
+proptest! {
+    #[test]
+    fn commands_encode_to_single_frame(cmd in command()) {
+        prop_assert!(cmd.validate().is_ok());
+        let frame = cmd.as_frame().unwrap();
+
+        // A single line, terminated by a newline.
+        let body = frame.strip_suffix(b"\n").expect("unterminated frame");
+        prop_assert!(!body.contains(&b'\n'));
+
+        // A JSON object, tagged with the command name.
+        let value: Value = serde_json::from_slice(body).unwrap();
+        let object = value.as_object().expect("not a JSON object");
+        prop_assert_eq!(object.get("__name__").and_then(Value::as_str), Some(cmd.name()));
+
+        // All encoding paths agree.
+        prop_assert_eq!(&protocol::encode_frame(cmd.as_ref()).unwrap(), &frame);
+        let mut buf = BytesMut::new();
+        protocol::write_frame(cmd.as_ref(), &mut buf).unwrap();
+        prop_assert_eq!(&buf[..], &frame[..]);
+    }
+
+    #[test]
+    fn events_round_trip(event in event()) {
+        // Passwords are never encoded, thus do not survive a round-trip.
+        let event = match event {
+            Event::Login(login) => {
+                let mut with_password = login.clone();
+                with_password.password = Some(Secret::new("hunter2".to_string()));
+                let frame = Event::Login(with_password).to_frame().unwrap();
+                let value: Value = serde_json::from_slice(&frame).unwrap();
+                prop_assert!(value.get("password").is_none());
+                Event::Login(events::Login { password: None, ..login })
+            }
+            event => event,
+        };
+
+        let frame = event.to_frame().unwrap();
+        prop_assert_eq!(frame.iter().filter(|b| **b == b'\n').count(), 1);
+        prop_assert_eq!(Event::parse_frame(&frame).unwrap(), event.clone());
+
+        let line = std::str::from_utf8(&frame[..frame.len() - 1]).unwrap();
+        let lazy = LazyEvent::parse_frame(line).unwrap();
+        prop_assert_eq!(lazy.name(), event.name());
+        prop_assert_eq!(lazy.into_event().unwrap(), event.clone());
+
+        let value = serde_json::to_value(&event).unwrap();
+        prop_assert_eq!(serde_json::from_value::<Event>(value).unwrap(), event);
+    }
+}
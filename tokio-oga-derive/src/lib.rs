@@ -0,0 +1,109 @@
+/*!
+Derive macros for the `tokio-oga` library.
+
+This provides `#[derive(OgaCommand)]`, which implements the `AsFrame`
+trait for a serializable struct, given its protocol message name and, optionally, the minimum protocol
+version it requires:
+
+```ignore
+#[derive(Debug, serde::Serialize, tokio_oga::commands::OgaCommand)]
+#[oga(name = "host-name", min_api_version = 1)]
+struct HostName {
+    name: String,
+}
+```
+
+The `__name__` tag is added automatically when encoding, thus the
+struct must not carry its own `#[serde(tag)]` attribute.
+*/
+
+use proc_macro::TokenStream;
+use proc_macro2::TokenStream as TokenStream2;
+use quote::quote;
+use syn::{parse_macro_input, DeriveInput, LitInt, LitStr};
+
+/// Derive `AsFrame` for a command, from its `#[oga(...)]` attribute.
+#[proc_macro_derive(OgaCommand, attributes(oga))]
+pub fn derive_oga_command(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    match expand(&input) {
+        Ok(tokens) => tokens.into(),
+        Err(err) => err.to_compile_error().into(),
+    }
+}
+
+/// Generate the `AsFrame` implementation.
+fn expand(input: &DeriveInput) -> syn::Result<TokenStream2> {
+    let attrs = OgaAttrs::parse(input)?;
+    let msg_name = attrs.name;
+    let min_api_version = attrs.min_api_version.unwrap_or(0);
+    let ident = &input.ident;
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+
+    let tokens = quote! {
+        impl #impl_generics ::tokio_oga::commands::AsFrame for #ident #ty_generics #where_clause {
+            fn as_frame(&self) -> ::std::result::Result<::tokio_oga::bytes::Bytes, ::tokio_oga::OgaError> {
+                ::tokio_oga::commands::encode_tagged(#msg_name, self)
+            }
+
+            fn write_frame(
+                &self,
+                buf: &mut dyn ::tokio_oga::bytes::BufMut,
+            ) -> ::std::result::Result<(), ::tokio_oga::OgaError> {
+                ::tokio_oga::commands::write_tagged(#msg_name, self, buf)
+            }
+
+            fn name(&self) -> &str {
+                #msg_name
+            }
+
+            fn min_api_version(&self) -> u8 {
+                #min_api_version
+            }
+        }
+    };
+    Ok(tokens)
+}
+
+/// Settings from the `#[oga(...)]` attribute.
+struct OgaAttrs {
+    name: LitStr,
+    min_api_version: Option<u8>,
+}
+
+impl OgaAttrs {
+    /// Parse settings from the `#[oga(name = "...", min_api_version = N)]` attribute.
+    fn parse(input: &DeriveInput) -> syn::Result<Self> {
+        let mut name = None;
+        let mut min_api_version = None;
+        for attr in input.attrs.iter().filter(|a| a.path().is_ident("oga")) {
+            attr.parse_nested_meta(|meta| {
+                if meta.path.is_ident("name") {
+                    let value: LitStr = meta.value()?.parse()?;
+                    if value.value().is_empty() {
+                        return Err(meta.error("empty message name"));
+                    }
+                    name = Some(value);
+                    Ok(())
+                } else if meta.path.is_ident("min_api_version") {
+                    let value: LitInt = meta.value()?.parse()?;
+                    min_api_version = Some(value.base10_parse()?);
+                    Ok(())
+                } else {
+                    Err(meta.error("unsupported `oga` attribute"))
+                }
+            })?;
+        }
+
+        let name = name.ok_or_else(|| {
+            syn::Error::new_spanned(
+                &input.ident,
+                "missing message name, add `#[oga(name = \"...\")]`",
+            )
+        })?;
+        Ok(Self {
+            name,
+            min_api_version,
+        })
+    }
+}